@@ -0,0 +1,32 @@
+//! Compares the cost of spawning a payload with `SpawnMethod::Fork` and `SpawnMethod::Vfork`
+//! from a parent with a large resident heap.
+//!
+//! ```sh
+//! cargo run --release --example spawn_benchmark -- [heap MiB] [iterations]
+//! ```
+
+use std::time::Instant;
+
+use memfd_runner::{RunOptions, SpawnMethod};
+
+fn main() {
+    let mut args = std::env::args().skip(1);
+    let heap_mib: usize = args.next().map_or(1024, |arg| arg.parse().unwrap());
+    let iterations: u32 = args.next().map_or(200, |arg| arg.parse().unwrap());
+
+    // Every page is written so that it is mapped, which is what fork has to copy
+    let heap = vec![1u8; heap_mib << 20];
+    let elf_bytes = std::fs::read("/bin/true").unwrap();
+
+    println!("{heap_mib} MiB resident, {iterations} spawns of /bin/true");
+    for method in [SpawnMethod::Fork, SpawnMethod::Vfork] {
+        let options = RunOptions::new().with_spawn_method(method);
+        let start = Instant::now();
+        for _ in 0..iterations {
+            let status = options.spawn(&elf_bytes).unwrap().wait().unwrap();
+            assert!(status.success());
+        }
+        println!("{method:?}: {:?} per spawn", start.elapsed() / iterations);
+    }
+    std::hint::black_box(&heap);
+}
@@ -0,0 +1,316 @@
+//! Capabilities of the payload, see
+//! [`RunOptions::with_ambient_capabilities`](crate::RunOptions::with_ambient_capabilities).
+//!
+//! The bounding set and securebits are changed before the uid is switched, while the child
+//! still has `CAP_SETPCAP`. The capabilities to keep survive the switch with `PR_SET_KEEPCAPS`,
+//! then are raised in the ambient set, which is the only one `execve` preserves for a program
+//! without file capabilities.
+
+use core::ops::{BitOr, BitOrAssign};
+
+use crate::Errno;
+use crate::seccomp::PR_SET_NO_NEW_PRIVS;
+use crate::syscalls::{self, cvt};
+
+const PR_SET_KEEPCAPS: i32 = 8;
+const PR_CAPBSET_DROP: i32 = 24;
+const PR_SET_SECUREBITS: i32 = 28;
+const PR_CAP_AMBIENT: i32 = 47;
+const PR_CAP_AMBIENT_RAISE: u64 = 2;
+const LINUX_CAPABILITY_VERSION_3: u32 = 0x2008_0522;
+
+/// A set of capabilities.
+///
+/// # Example
+/// ```rust
+/// use memfd_runner::Capabilities;
+///
+/// let capabilities = Capabilities::NET_BIND_SERVICE | Capabilities::NET_RAW;
+/// assert!(capabilities.contains(Capabilities::NET_RAW));
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Capabilities(u64);
+
+impl Capabilities {
+    /// `CAP_CHOWN`: change the owner and group of any file
+    pub const CHOWN: Self = Self(1 << 0);
+    /// `CAP_DAC_OVERRIDE`: bypass file read, write and execute permission checks
+    pub const DAC_OVERRIDE: Self = Self(1 << 1);
+    /// `CAP_DAC_READ_SEARCH`: bypass file read and directory search permission checks
+    pub const DAC_READ_SEARCH: Self = Self(1 << 2);
+    /// `CAP_FOWNER`: bypass the checks requiring to own a file, like `chmod()`
+    pub const FOWNER: Self = Self(1 << 3);
+    /// `CAP_FSETID`: keep the set-user-ID and set-group-ID bits when modifying a file
+    pub const FSETID: Self = Self(1 << 4);
+    /// `CAP_KILL`: send signals to any process
+    pub const KILL: Self = Self(1 << 5);
+    /// `CAP_SETGID`: change the group ids and supplementary groups
+    pub const SETGID: Self = Self(1 << 6);
+    /// `CAP_SETUID`: change the user ids
+    pub const SETUID: Self = Self(1 << 7);
+    /// `CAP_SETPCAP`: change the bounding set and securebits, grant any inheritable capability
+    pub const SETPCAP: Self = Self(1 << 8);
+    /// `CAP_LINUX_IMMUTABLE`: set the immutable and append-only file attributes
+    pub const LINUX_IMMUTABLE: Self = Self(1 << 9);
+    /// `CAP_NET_BIND_SERVICE`: bind sockets to ports below 1024
+    pub const NET_BIND_SERVICE: Self = Self(1 << 10);
+    /// `CAP_NET_BROADCAST`: broadcast and listen to multicast
+    pub const NET_BROADCAST: Self = Self(1 << 11);
+    /// `CAP_NET_ADMIN`: configure interfaces, routing and firewall rules
+    pub const NET_ADMIN: Self = Self(1 << 12);
+    /// `CAP_NET_RAW`: use raw and packet sockets
+    pub const NET_RAW: Self = Self(1 << 13);
+    /// `CAP_IPC_LOCK`: lock memory with `mlock()` and `mmap(MAP_LOCKED)`
+    pub const IPC_LOCK: Self = Self(1 << 14);
+    /// `CAP_IPC_OWNER`: bypass the permission checks on System V IPC objects
+    pub const IPC_OWNER: Self = Self(1 << 15);
+    /// `CAP_SYS_MODULE`: load and unload kernel modules
+    pub const SYS_MODULE: Self = Self(1 << 16);
+    /// `CAP_SYS_RAWIO`: perform I/O port operations and access `/dev/mem`
+    pub const SYS_RAWIO: Self = Self(1 << 17);
+    /// `CAP_SYS_CHROOT`: call `chroot()`
+    pub const SYS_CHROOT: Self = Self(1 << 18);
+    /// `CAP_SYS_PTRACE`: trace any process and read its memory
+    pub const SYS_PTRACE: Self = Self(1 << 19);
+    /// `CAP_SYS_PACCT`: enable process accounting
+    pub const SYS_PACCT: Self = Self(1 << 20);
+    /// `CAP_SYS_ADMIN`: mount filesystems, and most other administration operations
+    pub const SYS_ADMIN: Self = Self(1 << 21);
+    /// `CAP_SYS_BOOT`: reboot the system and load a new kernel
+    pub const SYS_BOOT: Self = Self(1 << 22);
+    /// `CAP_SYS_NICE`: raise the priority of processes and change their scheduling
+    pub const SYS_NICE: Self = Self(1 << 23);
+    /// `CAP_SYS_RESOURCE`: override resource limits and raise hard limits
+    pub const SYS_RESOURCE: Self = Self(1 << 24);
+    /// `CAP_SYS_TIME`: set the system clock
+    pub const SYS_TIME: Self = Self(1 << 25);
+    /// `CAP_SYS_TTY_CONFIG`: call `vhangup()` and configure terminals
+    pub const SYS_TTY_CONFIG: Self = Self(1 << 26);
+    /// `CAP_MKNOD`: create device files with `mknod()`
+    pub const MKNOD: Self = Self(1 << 27);
+    /// `CAP_LEASE`: take leases on any file
+    pub const LEASE: Self = Self(1 << 28);
+    /// `CAP_AUDIT_WRITE`: write to the kernel audit log
+    pub const AUDIT_WRITE: Self = Self(1 << 29);
+    /// `CAP_AUDIT_CONTROL`: configure kernel auditing
+    pub const AUDIT_CONTROL: Self = Self(1 << 30);
+    /// `CAP_SETFCAP`: set the file capabilities of a program
+    pub const SETFCAP: Self = Self(1 << 31);
+    /// `CAP_MAC_OVERRIDE`: bypass mandatory access control
+    pub const MAC_OVERRIDE: Self = Self(1 << 32);
+    /// `CAP_MAC_ADMIN`: configure mandatory access control
+    pub const MAC_ADMIN: Self = Self(1 << 33);
+    /// `CAP_SYSLOG`: read and clear the kernel log
+    pub const SYSLOG: Self = Self(1 << 34);
+    /// `CAP_WAKE_ALARM`: set alarms waking the system up
+    pub const WAKE_ALARM: Self = Self(1 << 35);
+    /// `CAP_BLOCK_SUSPEND`: prevent the system from suspending
+    pub const BLOCK_SUSPEND: Self = Self(1 << 36);
+    /// `CAP_AUDIT_READ`: read the audit log over netlink
+    pub const AUDIT_READ: Self = Self(1 << 37);
+    /// `CAP_PERFMON`: use performance monitoring with `perf_event_open()`
+    pub const PERFMON: Self = Self(1 << 38);
+    /// `CAP_BPF`: load BPF programs and create maps
+    pub const BPF: Self = Self(1 << 39);
+    /// `CAP_CHECKPOINT_RESTORE`: checkpoint and restore processes, choose the pid of `clone3()`
+    pub const CHECKPOINT_RESTORE: Self = Self(1 << 40);
+
+    /// No capability.
+    pub const fn empty() -> Self {
+        Self(0)
+    }
+
+    /// Returns `true` if no capability is set.
+    pub const fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    /// Returns `true` if all the capabilities of `other` are set.
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// The set as a mask, where capability `n` is bit `n`.
+    pub const fn bits(self) -> u64 {
+        self.0
+    }
+
+    /// The numbers of the capabilities in the set.
+    fn numbers(self) -> impl Iterator<Item = u64> {
+        (0..u64::BITS as u64).filter(move |&number| self.0 & (1 << number) != 0)
+    }
+}
+
+impl BitOr for Capabilities {
+    type Output = Self;
+
+    fn bitor(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+}
+
+impl BitOrAssign for Capabilities {
+    fn bitor_assign(&mut self, other: Self) {
+        self.0 |= other.0;
+    }
+}
+
+/// Securebits, which change how the kernel grants capabilities to uid 0 and across uid changes.
+///
+/// Each `*_LOCKED` flag prevents the matching flag from being changed again, also by the
+/// payload.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct SecureBits(u32);
+
+impl SecureBits {
+    /// `SECBIT_NOROOT`: uid 0 is not granted capabilities when running a program
+    pub const NOROOT: Self = Self(1 << 0);
+    /// `SECBIT_NOROOT_LOCKED`: `NOROOT` cannot be changed
+    pub const NOROOT_LOCKED: Self = Self(1 << 1);
+    /// `SECBIT_NO_SETUID_FIXUP`: capabilities are not adjusted when switching from or to uid 0
+    pub const NO_SETUID_FIXUP: Self = Self(1 << 2);
+    /// `SECBIT_NO_SETUID_FIXUP_LOCKED`: `NO_SETUID_FIXUP` cannot be changed
+    pub const NO_SETUID_FIXUP_LOCKED: Self = Self(1 << 3);
+    /// `SECBIT_KEEP_CAPS`: the permitted capabilities are kept when switching away from uid 0
+    pub const KEEP_CAPS: Self = Self(1 << 4);
+    /// `SECBIT_KEEP_CAPS_LOCKED`: `KEEP_CAPS` cannot be changed
+    pub const KEEP_CAPS_LOCKED: Self = Self(1 << 5);
+    /// `SECBIT_NO_CAP_AMBIENT_RAISE`: ambient capabilities cannot be raised
+    pub const NO_CAP_AMBIENT_RAISE: Self = Self(1 << 6);
+    /// `SECBIT_NO_CAP_AMBIENT_RAISE_LOCKED`: `NO_CAP_AMBIENT_RAISE` cannot be changed
+    pub const NO_CAP_AMBIENT_RAISE_LOCKED: Self = Self(1 << 7);
+
+    /// No securebit set.
+    pub const fn empty() -> Self {
+        Self(0)
+    }
+
+    /// Returns `true` if all the securebits of `other` are set.
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// The `SECBIT_*` flags of the set.
+    pub const fn bits(self) -> u32 {
+        self.0
+    }
+}
+
+impl BitOr for SecureBits {
+    type Output = Self;
+
+    fn bitor(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+}
+
+impl BitOrAssign for SecureBits {
+    fn bitor_assign(&mut self, other: Self) {
+        self.0 |= other.0;
+    }
+}
+
+/// `struct __user_cap_header_struct`
+#[repr(C)]
+struct CapHeader {
+    version: u32,
+    pid: i32,
+}
+
+/// `struct __user_cap_data_struct`, the low then high 32 bits of each set
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct CapData {
+    effective: u32,
+    permitted: u32,
+    inheritable: u32,
+}
+
+/// Capability changes the child makes around the uid switch.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct CapabilitySetup {
+    /// Capabilities kept across the uid switch and raised in the ambient set
+    pub(crate) ambient: Option<Capabilities>,
+    /// Capabilities left in the bounding set
+    pub(crate) bounding: Option<Capabilities>,
+    pub(crate) securebits: Option<SecureBits>,
+    pub(crate) no_new_privs: bool,
+}
+
+impl CapabilitySetup {
+    /// Restrict the bounding set and set the securebits, then keep the permitted capabilities
+    /// through the uid switch if some are to be raised. Only issues system calls.
+    pub(crate) fn before_uid_switch(&self) -> Result<(), Errno> {
+        if let Some(bounding) = self.bounding {
+            for number in 0..u64::BITS as u64 {
+                if bounding.0 & (1 << number) != 0 {
+                    continue;
+                }
+                match cvt(unsafe { syscalls::prctl(PR_CAPBSET_DROP, number, 0, 0, 0) }) {
+                    Ok(_) => {}
+                    // past the last capability of the kernel
+                    Err(Errno::EINVAL) => break,
+                    Err(errno) => return Err(errno),
+                }
+            }
+        }
+        if let Some(securebits) = self.securebits {
+            cvt(unsafe { syscalls::prctl(PR_SET_SECUREBITS, securebits.0 as u64, 0, 0, 0) })?;
+        }
+        if self.ambient.is_some() {
+            cvt(unsafe { syscalls::prctl(PR_SET_KEEPCAPS, 1, 0, 0, 0) })?;
+        }
+        Ok(())
+    }
+
+    /// Reduce the capabilities to the ambient ones and raise them, then set
+    /// `PR_SET_NO_NEW_PRIVS`. Only issues system calls.
+    pub(crate) fn after_uid_switch(&self) -> Result<(), Errno> {
+        if let Some(ambient) = self.ambient {
+            // raising an ambient capability requires it to be permitted and inheritable
+            let header = CapHeader {
+                version: LINUX_CAPABILITY_VERSION_3,
+                pid: 0,
+            };
+            let data = [ambient.0 as u32, (ambient.0 >> 32) as u32].map(|set| CapData {
+                effective: set,
+                permitted: set,
+                inheritable: set,
+            });
+            cvt(unsafe {
+                syscalls::capset(
+                    (&header as *const CapHeader).cast_mut().cast(),
+                    data.as_ptr().cast_mut().cast(),
+                )
+            })?;
+            for number in ambient.numbers() {
+                cvt(unsafe {
+                    syscalls::prctl(PR_CAP_AMBIENT, PR_CAP_AMBIENT_RAISE, number, 0, 0)
+                })?;
+            }
+        }
+        if self.no_new_privs {
+            cvt(unsafe { syscalls::prctl(PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) })?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    extern crate std;
+    use std::vec::Vec;
+
+    #[test]
+    fn test_capabilities_set() {
+        let capabilities = Capabilities::NET_BIND_SERVICE | Capabilities::CHECKPOINT_RESTORE;
+        assert!(capabilities.contains(Capabilities::NET_BIND_SERVICE));
+        assert!(!capabilities.contains(Capabilities::NET_RAW));
+        assert_eq!(capabilities.numbers().collect::<Vec<_>>(), [10, 40]);
+        assert!(Capabilities::empty().is_empty());
+        let mut securebits = SecureBits::empty();
+        securebits |= SecureBits::NOROOT | SecureBits::NOROOT_LOCKED;
+        assert_eq!(securebits.bits(), 0b11);
+    }
+}
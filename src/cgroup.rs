@@ -0,0 +1,356 @@
+//! cgroup v2 placement of the payload, see [`RunOptions::with_cgroup`](crate::RunOptions::with_cgroup).
+//!
+//! In fork mode the child is created directly in the cgroup by `clone3(CLONE_INTO_CGROUP)`, so
+//! that the payload and every process it starts are accounted and limited from their first
+//! instruction. In replace mode, the current process moves itself to the cgroup.
+
+use core::ffi::CStr;
+use core::time::Duration;
+
+use crate::Errno;
+use crate::fd::{self, Fd, O_CLOEXEC, O_DIRECTORY, O_PATH, PollFd};
+use crate::namespaces::decimal;
+use crate::syscalls::{self, cvt};
+
+const O_RDONLY: i32 = 0o0;
+const O_WRONLY: i32 = 0o1;
+const AT_REMOVEDIR: i32 = 0x200;
+const SEEK_SET: i32 = 0;
+const POLLPRI: i16 = 0x2;
+const POLLERR: i16 = 0x8;
+/// Longest cgroup name, with its terminating NUL
+const NAME_LEN: usize = 256;
+/// Large enough for `cpu.stat` with every controller enabled
+const STAT_LEN: usize = 1024;
+
+/// A cgroup v2 the payload is spawned into, along with the limits set on it.
+///
+/// # Example
+/// ```rust
+/// use core::time::Duration;
+/// use memfd_runner::Cgroup;
+///
+/// // half a CPU, 256 MiB and 64 processes
+/// let cgroup = Cgroup::create(c"/sys/fs/cgroup/runner", c"payload-1")
+///     .with_cpu_max(Duration::from_millis(50), Duration::from_millis(100))
+///     .with_memory_max(256 << 20)
+///     .with_pids_max(64);
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Cgroup<'a> {
+    path: &'a CStr,
+    name: Option<&'a CStr>,
+    memory_max: Option<u64>,
+    cpu_max: Option<(Duration, Duration)>,
+    pids_max: Option<u64>,
+}
+
+impl<'a> Cgroup<'a> {
+    /// The existing cgroup at `path`, such as `/sys/fs/cgroup/runner`.
+    ///
+    /// Setting its limits requires its parent to have enabled the matching controllers, and it
+    /// is left in place once the payload exits.
+    pub const fn existing(path: &'a CStr) -> Self {
+        Self {
+            path,
+            name: None,
+            memory_max: None,
+            cpu_max: None,
+            pids_max: None,
+        }
+    }
+
+    /// A new cgroup `name`, created in the cgroup at `parent` before forking and removed when
+    /// the [`Child`](crate::Child) is dropped.
+    ///
+    /// The controllers its limits need are enabled in `parent`, which must not contain
+    /// processes itself (except for the root cgroup). The removal fails, leaving the cgroup in
+    /// place, while processes started by the payload are still in it: see
+    /// [`Child::kill_tree`](crate::Child::kill_tree).
+    pub const fn create(parent: &'a CStr, name: &'a CStr) -> Self {
+        Self {
+            name: Some(name),
+            ..Self::existing(parent)
+        }
+    }
+
+    /// Set `memory.max`: above `bytes`, the memory of the cgroup is reclaimed, and the payload
+    /// is killed by the OOM killer if that fails.
+    pub const fn with_memory_max(mut self, bytes: u64) -> Self {
+        self.memory_max = Some(bytes);
+        self
+    }
+
+    /// Set `cpu.max`: the cgroup may only run for `quota` in each `period`, so a quota above
+    /// the period allows using several CPUs.
+    pub const fn with_cpu_max(mut self, quota: Duration, period: Duration) -> Self {
+        self.cpu_max = Some((quota, period));
+        self
+    }
+
+    /// Set `pids.max`: `fork()` fails with `EAGAIN` once the cgroup has `max` processes and
+    /// threads.
+    pub const fn with_pids_max(mut self, max: u64) -> Self {
+        self.pids_max = Some(max);
+        self
+    }
+
+    /// Open or create the cgroup, then set its limits.
+    pub(crate) fn prepare(&self) -> Result<CgroupDir, Errno> {
+        let dir = fd::open_dir(self.path)?;
+        let Some(name) = self.name else {
+            let cgroup = CgroupDir {
+                dir,
+                _created: None,
+            };
+            cgroup.set_limits(self)?;
+            return Ok(cgroup);
+        };
+        let name = name.to_bytes_with_nul();
+        if name.len() > NAME_LEN || name.contains(&b'/') {
+            return Err(Errno::EINVAL);
+        }
+        for (needed, controller) in [
+            (self.memory_max.is_some(), b"+memory".as_slice()),
+            (self.cpu_max.is_some(), b"+cpu"),
+            (self.pids_max.is_some(), b"+pids"),
+        ] {
+            if needed {
+                write_file(&dir, c"cgroup.subtree_control", controller)?;
+            }
+        }
+        cvt(unsafe { syscalls::mkdirat(dir.raw(), name, 0o755) })?;
+        let mut created = Created {
+            parent: dir,
+            name: [0; NAME_LEN],
+        };
+        created.name[..name.len()].copy_from_slice(name);
+        // removed by dropping `created` from here on
+        let dir = cvt(unsafe {
+            syscalls::openat(
+                created.parent.raw(),
+                name,
+                O_PATH | O_DIRECTORY | O_CLOEXEC,
+                0,
+            )
+        })?;
+        let cgroup = CgroupDir {
+            dir: Fd::from_raw(dir),
+            _created: Some(created),
+        };
+        cgroup.set_limits(self)?;
+        Ok(cgroup)
+    }
+}
+
+/// Resources consumed by the processes of the cgroup of a payload, read once it was reaped.
+///
+/// The counters cover every process which ever ran in the cgroup, including the descendants
+/// of the payload it did not wait for, and in an [existing](Cgroup::existing) cgroup, the
+/// processes which were already there.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CgroupStats {
+    /// Largest memory usage, in bytes (`memory.peak`, with the memory controller, Linux 5.19+)
+    pub memory_peak: Option<u64>,
+    /// Total CPU time (`usage_usec` of `cpu.stat`)
+    pub cpu_time: Duration,
+    /// CPU time spent in user mode (`user_usec`)
+    pub user_time: Duration,
+    /// CPU time spent in kernel mode (`system_usec`)
+    pub system_time: Duration,
+    /// Periods in which the cgroup ran out of its [CPU quota](Cgroup::with_cpu_max)
+    /// (`nr_throttled`, with the cpu controller)
+    pub throttled_periods: u64,
+    /// Time the cgroup was kept from running by its CPU quota (`throttled_usec`)
+    pub throttled_time: Duration,
+}
+
+impl CgroupStats {
+    /// Decode the content of `cpu.stat`, keeping `memory_peak`.
+    fn parse_cpu_stat(mut self, stat: &[u8]) -> Self {
+        for line in stat.split(|&byte| byte == b'\n') {
+            let mut fields = line.splitn(2, |&byte| byte == b' ');
+            let (Some(key), Some(value)) = (fields.next(), fields.next().and_then(parse_u64))
+            else {
+                continue;
+            };
+            match key {
+                b"usage_usec" => self.cpu_time = Duration::from_micros(value),
+                b"user_usec" => self.user_time = Duration::from_micros(value),
+                b"system_usec" => self.system_time = Duration::from_micros(value),
+                b"nr_throttled" => self.throttled_periods = value,
+                b"throttled_usec" => self.throttled_time = Duration::from_micros(value),
+                _ => {}
+            }
+        }
+        self
+    }
+}
+
+/// A cgroup directory a payload is placed in.
+#[derive(Debug)]
+pub(crate) struct CgroupDir {
+    dir: Fd,
+    /// Removes the cgroup when dropped, if the runner created it
+    _created: Option<Created>,
+}
+
+/// A cgroup created by the runner, removed when dropped.
+#[derive(Debug)]
+struct Created {
+    parent: Fd,
+    name: [u8; NAME_LEN],
+}
+
+impl Drop for Created {
+    fn drop(&mut self) {
+        // fails while processes are left in the cgroup
+        unsafe { syscalls::unlinkat(self.parent.raw(), self.name, AT_REMOVEDIR) };
+    }
+}
+
+impl CgroupDir {
+    /// The `O_PATH` descriptor of the directory, given to `clone3`.
+    pub(crate) fn raw(&self) -> i32 {
+        self.dir.raw()
+    }
+
+    fn set_limits(&self, cgroup: &Cgroup<'_>) -> Result<(), Errno> {
+        let mut digits = [0; 20];
+        if let Some(bytes) = cgroup.memory_max {
+            write_file(&self.dir, c"memory.max", decimal(bytes, &mut digits))?;
+        }
+        if let Some((quota, period)) = cgroup.cpu_max {
+            // "$QUOTA $PERIOD" in microseconds
+            let mut cpu_max = [b' '; 41];
+            let quota = decimal(quota.as_micros() as u64, &mut digits);
+            let quota_len = quota.len();
+            cpu_max[..quota_len].copy_from_slice(quota);
+            let period = decimal(period.as_micros() as u64, &mut digits);
+            let len = quota_len + 1 + period.len();
+            cpu_max[quota_len + 1..len].copy_from_slice(period);
+            write_file(&self.dir, c"cpu.max", &cpu_max[..len])?;
+        }
+        if let Some(max) = cgroup.pids_max {
+            write_file(&self.dir, c"pids.max", decimal(max, &mut digits))?;
+        }
+        Ok(())
+    }
+
+    /// Move the calling process to the cgroup.
+    pub(crate) fn enter(&self) -> Result<(), Errno> {
+        write_file(&self.dir, c"cgroup.procs", b"0")
+    }
+
+    /// Kill every process of the cgroup with `cgroup.kill` (Linux 5.14+), then wait until they
+    /// are all gone.
+    pub(crate) fn kill(&self) -> Result<(), Errno> {
+        write_file(&self.dir, c"cgroup.kill", b"1")?;
+        let events = open_file(&self.dir, c"cgroup.events", O_RDONLY)?;
+        let mut buf = [0u8; 256];
+        loop {
+            cvt(unsafe { syscalls::lseek(events.raw(), 0, SEEK_SET) })?;
+            let len = events.read(&mut buf)?;
+            if buf[..len]
+                .split(|&byte| byte == b'\n')
+                .any(|line| line == b"populated 0")
+            {
+                return Ok(());
+            }
+            // the file is modified once the last one exited, which is signaled with POLLPRI and
+            // POLLERR: cgroup files are always readable, polling for POLLIN would spin
+            let mut fds = [PollFd {
+                fd: events.raw(),
+                events: POLLPRI,
+                revents: 0,
+            }];
+            loop {
+                match cvt(unsafe { syscalls::poll(fds.as_mut_ptr().cast(), 1, -1) }) {
+                    Ok(_) if fds[0].revents & (POLLPRI | POLLERR) != 0 => break,
+                    Ok(_) | Err(Errno::EINTR) => {}
+                    Err(errno) => return Err(errno),
+                }
+            }
+        }
+    }
+
+    /// Read the resources consumed by the cgroup.
+    pub(crate) fn stats(&self) -> Result<CgroupStats, Errno> {
+        let mut buf = [0u8; STAT_LEN];
+        let stats = CgroupStats {
+            memory_peak: read_file(&self.dir, c"memory.peak", &mut buf)
+                .ok()
+                .and_then(parse_u64),
+            ..CgroupStats::default()
+        };
+        Ok(stats.parse_cpu_stat(read_file(&self.dir, c"cpu.stat", &mut buf)?))
+    }
+}
+
+/// Parse a decimal number, followed by an optional newline.
+fn parse_u64(value: &[u8]) -> Option<u64> {
+    let value = value.strip_suffix(b"\n").unwrap_or(value);
+    if value.is_empty() {
+        return None;
+    }
+    value.iter().try_fold(0u64, |number, &digit| match digit {
+        b'0'..=b'9' => number.checked_mul(10)?.checked_add((digit - b'0') as u64),
+        _ => None,
+    })
+}
+
+fn open_file(dir: &Fd, name: &CStr, flags: i32) -> Result<Fd, Errno> {
+    let fd = cvt(unsafe {
+        syscalls::openat(dir.raw(), name.to_bytes_with_nul(), flags | O_CLOEXEC, 0)
+    })?;
+    Ok(Fd::from_raw(fd))
+}
+
+/// Write `content` to the file `name` of `dir` in a single `write()`, as the kernel expects.
+fn write_file(dir: &Fd, name: &CStr, content: &[u8]) -> Result<(), Errno> {
+    if open_file(dir, name, O_WRONLY)?.write(content)? != content.len() {
+        return Err(Errno::EIO);
+    }
+    Ok(())
+}
+
+/// Read the file `name` of `dir` into `buf`.
+fn read_file<'b>(dir: &Fd, name: &CStr, buf: &'b mut [u8]) -> Result<&'b [u8], Errno> {
+    let len = open_file(dir, name, O_RDONLY)?.read(buf)?;
+    Ok(&buf[..len])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_u64() {
+        assert_eq!(parse_u64(b"4096\n"), Some(4096));
+        assert_eq!(parse_u64(b"18446744073709551615"), Some(u64::MAX));
+        assert_eq!(parse_u64(b"18446744073709551616"), None);
+        assert_eq!(parse_u64(b"max\n"), None);
+        assert_eq!(parse_u64(b"\n"), None);
+    }
+
+    #[test]
+    fn test_parse_cpu_stat() {
+        let stat = b"usage_usec 1500\nuser_usec 1000\nsystem_usec 500\nnr_periods 4\n\
+            nr_throttled 2\nthrottled_usec 250\nnr_bursts 0\n";
+        let stats = CgroupStats {
+            memory_peak: Some(1 << 20),
+            ..CgroupStats::default()
+        }
+        .parse_cpu_stat(stat);
+        assert_eq!(stats.memory_peak, Some(1 << 20));
+        assert_eq!(stats.cpu_time, Duration::from_micros(1500));
+        assert_eq!(stats.user_time, Duration::from_millis(1));
+        assert_eq!(stats.system_time, Duration::from_micros(500));
+        assert_eq!(stats.throttled_periods, 2);
+        assert_eq!(stats.throttled_time, Duration::from_micros(250));
+        // without the cpu controller
+        let stats = CgroupStats::default().parse_cpu_stat(b"usage_usec 7\nuser_usec 7\n");
+        assert_eq!(stats.throttled_periods, 0);
+        assert_eq!(stats.cpu_time, Duration::from_micros(7));
+    }
+}
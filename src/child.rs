@@ -0,0 +1,400 @@
+#[cfg(feature = "alloc")]
+use crate::output::{self, Output};
+use core::time::Duration;
+
+use crate::cgroup::CgroupDir;
+use crate::fd::{POLLHUP, POLLIN, PollFd};
+use crate::pidfd::{self, PidFd};
+use crate::rusage::{RawRusage, RunReport};
+use crate::seccomp::{Notification, Notifier, Response};
+use crate::stdio::StdioSetup;
+use crate::syscalls::{self, cvt};
+use crate::time;
+use crate::{ChildStderr, ChildStdin, ChildStdout, Errno, ExitStatus, RunError};
+
+const WNOHANG: i32 = 0x1;
+pub(crate) const SIGKILL: i32 = 9;
+
+/// One step of the shutdown of a payload which outlived its timeout: `signal` is sent, then the
+/// payload is given `grace` to exit before the next step.
+///
+/// See [`RunOptions::with_kill_sequence`](crate::RunOptions::with_kill_sequence).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct KillStep {
+    /// Signal number to send, e.g. 15 for `SIGTERM`
+    pub signal: i32,
+    /// How long to wait for the payload to exit after sending the signal
+    pub grace: Duration,
+}
+
+impl KillStep {
+    /// Creates a step sending `signal` then waiting up to `grace`.
+    pub const fn new(signal: i32, grace: Duration) -> Self {
+        Self { signal, grace }
+    }
+}
+
+/// Handle to a payload running in a forked child process.
+///
+/// Returned by [`RunOptions::spawn`](crate::RunOptions::spawn). The child is not waited for when
+/// the handle is dropped: call [`Child::wait`] to reap it and retrieve its exit status.
+#[derive(Debug)]
+pub struct Child {
+    pid: i32,
+    pidfd: Option<PidFd>,
+    /// Monotonic time just before the fork
+    started: Duration,
+    report: Option<RunReport>,
+    notifier: Option<Notifier>,
+    cgroup: Option<CgroupDir>,
+    /// The writing end of the payload's stdin, if it was configured with
+    /// [`Stdio::Piped`](crate::Stdio::Piped).
+    pub stdin: Option<ChildStdin>,
+    /// The reading end of the payload's stdout, if it was configured with
+    /// [`Stdio::Piped`](crate::Stdio::Piped).
+    pub stdout: Option<ChildStdout>,
+    /// The reading end of the payload's stderr, if it was configured with
+    /// [`Stdio::Piped`](crate::Stdio::Piped).
+    pub stderr: Option<ChildStderr>,
+}
+
+impl Child {
+    pub(crate) fn new(
+        pid: i32,
+        pidfd: Option<PidFd>,
+        started: Duration,
+        stdio: StdioSetup,
+        notifier: Option<Notifier>,
+        cgroup: Option<CgroupDir>,
+    ) -> Self {
+        let (stdin, stdout, stderr) = stdio.into_parent_ends();
+        Self {
+            pid,
+            pidfd,
+            started,
+            report: None,
+            notifier,
+            cgroup,
+            stdin,
+            stdout,
+            stderr,
+        }
+    }
+
+    /// Returns the process identifier of the child.
+    pub fn id(&self) -> u32 {
+        self.pid as u32
+    }
+
+    /// Returns the pidfd of the child, unless the kernel does not support pidfds (Linux < 5.3).
+    ///
+    /// It stays valid after the child is reaped and can be registered in an `epoll` loop, where
+    /// it becomes readable once the child exits.
+    pub fn pidfd(&self) -> Option<&PidFd> {
+        self.pidfd.as_ref()
+    }
+
+    /// Returns the listener of the system calls the payload makes through
+    /// [`Action::Notify`](crate::seccomp::Action::Notify) rules, if its
+    /// [seccomp filter](crate::RunOptions::with_seccomp) has any.
+    ///
+    /// Use it to answer the calls from an event loop, instead of [`supervise`](Self::supervise).
+    pub fn notifier(&self) -> Option<&Notifier> {
+        self.notifier.as_ref()
+    }
+
+    /// Answers the system calls the payload makes through
+    /// [`Action::Notify`](crate::seccomp::Action::Notify) rules with `handler` until it exits,
+    /// then reaps it and returns its exit status.
+    ///
+    /// The payload is only suspended in the calls being answered, so notified calls may already
+    /// be waiting when this is called. Calls of processes it started which outlive it are left
+    /// waiting until the `Child` is dropped, then fail with `ENOSYS`. Without any notifying
+    /// rule, this is [`wait`](Self::wait).
+    ///
+    /// # Example
+    /// ```rust,no_run
+    /// use memfd_runner::seccomp::{x86_64, Action, Response, Rule, SeccompFilter};
+    /// use memfd_runner::{Errno, RunOptions};
+    ///
+    /// let elf_bytes = std::fs::read("/usr/bin/cat").unwrap();
+    /// let filter = SeccompFilter::new(Action::Allow).rule(Rule::new(x86_64::OPENAT, Action::Notify));
+    /// let mut child = RunOptions::new()
+    ///     .with_args(&["/data/report.txt"])
+    ///     .with_seccomp(&filter)
+    ///     .spawn(&elf_bytes)
+    ///     .unwrap();
+    /// // only open files under /data, besides the libraries the payload loads (a real policy
+    /// // would also reject `..` components)
+    /// let allowed: [&[u8]; 4] = [b"/data/", b"/etc/ld.so.", b"/lib", b"/usr/lib"];
+    /// let status = child
+    ///     .supervise(|notifier, call| {
+    ///         let mut buf = [0u8; 4096];
+    ///         match notifier.read_c_str(call, call.args[1], &mut buf) {
+    ///             Ok(path) if allowed.iter().any(|dir| path.to_bytes().starts_with(dir)) => {
+    ///                 Response::Continue
+    ///             }
+    ///             _ => Response::Errno(Errno::EACCES),
+    ///         }
+    ///     })
+    ///     .unwrap();
+    /// ```
+    pub fn supervise(
+        &mut self,
+        mut handler: impl FnMut(&Notifier, &Notification) -> Response,
+    ) -> Result<ExitStatus, RunError> {
+        let (Some(notifier), None) = (&self.notifier, self.report) else {
+            return self.wait();
+        };
+        let mut opened = None;
+        let pidfd = self.raw_pidfd(&mut opened)?;
+        loop {
+            let mut fds = [notifier.as_raw_fd(), pidfd].map(|fd| PollFd {
+                fd,
+                events: POLLIN,
+                revents: 0,
+            });
+            let ret = unsafe { syscalls::poll(fds.as_mut_ptr().cast(), fds.len() as u32, -1) };
+            match cvt(ret) {
+                Ok(_) => {}
+                Err(Errno::EINTR) => continue,
+                Err(errno) => return Err(RunError::WaitError(errno)),
+            }
+            if fds[0].revents & POLLIN != 0 {
+                // ENOENT: the call was interrupted, or its thread died, before it was answered
+                match notifier
+                    .recv()
+                    .and_then(|call| notifier.respond(&call, handler(notifier, &call)))
+                {
+                    Ok(()) | Err(Errno::ENOENT) => {}
+                    Err(errno) => return Err(RunError::SeccompError(errno)),
+                }
+            } else if fds[0].revents & POLLHUP != 0 || fds[1].revents & POLLIN != 0 {
+                // no process uses the filter anymore (Linux 5.8+), or the payload exited
+                break;
+            }
+        }
+        self.wait()
+    }
+
+    /// Blocks until the child exits and returns its exit status.
+    ///
+    /// The stdin pipe, if any, is closed first so that a payload reading it until end of file
+    /// can terminate. Calling `wait` again after the child has been reaped returns the cached
+    /// exit status.
+    pub fn wait(&mut self) -> Result<ExitStatus, RunError> {
+        Ok(self.wait_report()?.status)
+    }
+
+    /// Blocks until the child exits and returns its exit status along with the resources it
+    /// consumed and its wall-clock run time.
+    ///
+    /// Like [`wait`](Self::wait), the stdin pipe is closed first and the report is cached once
+    /// the child has been reaped.
+    pub fn wait_report(&mut self) -> Result<RunReport, RunError> {
+        if let Some(report) = self.report {
+            return Ok(report);
+        }
+        drop(self.stdin.take());
+        let (status, rusage) = wait_pid(self.pid, 0)?.expect("blocking wait4 returned no child");
+        Ok(self.reaped(status, rusage))
+    }
+
+    /// Reads the stdout and stderr pipes until end of file, then waits for the child to exit.
+    ///
+    /// Pipes are read concurrently so the payload cannot deadlock on a full pipe. Streams which
+    /// were not configured with [`Stdio::Piped`](crate::Stdio::Piped) are returned empty.
+    #[cfg(feature = "alloc")]
+    pub fn wait_with_output(mut self) -> Result<Output, RunError> {
+        drop(self.stdin.take());
+        let (stdout, stderr) = (self.stdout.take(), self.stderr.take());
+        let (stdout, stderr) = output::read2(
+            stdout.as_ref().map(|pipe| &pipe.fd),
+            stderr.as_ref().map(|pipe| &pipe.fd),
+        )
+        .map_err(RunError::OutputError)?;
+        let status = self.wait()?;
+        Ok(Output {
+            status,
+            stdout,
+            stderr,
+        })
+    }
+
+    /// Checks whether the child has exited without blocking (`WNOHANG`).
+    ///
+    /// Returns `Ok(None)` while the child is still running.
+    pub fn try_wait(&mut self) -> Result<Option<ExitStatus>, RunError> {
+        if let Some(report) = self.report {
+            return Ok(Some(report.status));
+        }
+        let waited = wait_pid(self.pid, WNOHANG)?;
+        Ok(waited.map(|(status, rusage)| self.reaped(status, rusage).status))
+    }
+
+    /// Waits for the child to exit for at most `timeout`.
+    ///
+    /// Returns `Ok(None)` if the child is still running once the timeout elapsed. The wait
+    /// sleeps in `poll()` on a pidfd of the child (Linux 5.3+), it does not busy-loop. A
+    /// timeout too large to be represented, like `Duration::MAX`, waits for as long as it takes.
+    pub fn wait_timeout(&mut self, timeout: Duration) -> Result<Option<ExitStatus>, RunError> {
+        if let Some(status) = self.try_wait()? {
+            return Ok(Some(status));
+        }
+        // None when the deadline overflows: no deadline at all
+        let deadline = time::monotonic_now().checked_add(timeout);
+        let mut opened = None;
+        let pidfd = self.raw_pidfd(&mut opened)?;
+        loop {
+            let remaining = deadline.map(|deadline| deadline.saturating_sub(time::monotonic_now()));
+            let mut fds = [PollFd {
+                fd: pidfd,
+                events: POLLIN,
+                revents: 0,
+            }];
+            let ret = unsafe {
+                syscalls::poll(
+                    fds.as_mut_ptr().cast(),
+                    1,
+                    remaining.map_or(-1, time::poll_timeout),
+                )
+            };
+            match cvt(ret) {
+                // the pidfd becomes readable once the child exits
+                Ok(_) => {}
+                Err(Errno::EINTR) => continue,
+                Err(errno) => return Err(RunError::WaitError(errno)),
+            }
+            if let Some(status) = self.try_wait()? {
+                return Ok(Some(status));
+            }
+            if remaining.is_some_and(|remaining| remaining.is_zero()) {
+                return Ok(None);
+            }
+        }
+    }
+
+    /// Shuts the child down by going through `sequence`, then waits for it to exit.
+    ///
+    /// Each step sends its signal and gives the child its grace period to exit. If it is still
+    /// running after the last step, it is killed with `SIGKILL`.
+    ///
+    /// # Example
+    /// ```rust,no_run
+    /// use core::time::Duration;
+    /// use memfd_runner::{KillStep, RunOptions};
+    ///
+    /// let elf_bytes = std::fs::read("/usr/bin/sleep").unwrap();
+    /// let mut child = RunOptions::new().with_args(&["60"]).spawn(&elf_bytes).unwrap();
+    /// // SIGTERM, then SIGKILL if still running one second later
+    /// let status = child.terminate(&[KillStep::new(15, Duration::from_secs(1))]).unwrap();
+    /// ```
+    pub fn terminate(&mut self, sequence: &[KillStep]) -> Result<ExitStatus, RunError> {
+        for step in sequence {
+            self.signal(step.signal)?;
+            if let Some(status) = self.wait_timeout(step.grace)? {
+                return Ok(status);
+            }
+        }
+        self.kill()?;
+        self.wait()
+    }
+
+    /// Sends `SIGKILL` to the child.
+    ///
+    /// This does nothing if the child has already been reaped, so a recycled pid is never signaled.
+    pub fn kill(&mut self) -> Result<(), RunError> {
+        self.signal(SIGKILL)
+    }
+
+    /// Sends `signal` to the child, through its pidfd when available.
+    ///
+    /// This does nothing if the child has already been reaped, so a recycled pid is never signaled.
+    pub fn signal(&mut self, signal: i32) -> Result<(), RunError> {
+        if self.report.is_some() {
+            return Ok(());
+        }
+        match &self.pidfd {
+            Some(pidfd) => pidfd.send_signal(signal),
+            None => cvt(unsafe { syscalls::kill(self.pid, signal) }).map(drop),
+        }
+        .map_err(RunError::KillError)
+    }
+
+    /// Kills the payload and every process it started, through the `cgroup.kill` file of its
+    /// [cgroup](crate::RunOptions::with_cgroup) (Linux 5.14+), then waits until they are all
+    /// gone.
+    ///
+    /// Unlike [`kill`](Self::kill), this reaches the descendants which left the process tree of
+    /// the payload, and still works once the payload itself was reaped. The payload is left to
+    /// be reaped by [`wait`](Self::wait). Fails with [`RunError::KillError`]`(EINVAL)` if the
+    /// child was spawned without a cgroup, and kills every process of an
+    /// [existing](crate::Cgroup::existing) cgroup.
+    ///
+    /// # Example
+    /// ```rust,no_run
+    /// use memfd_runner::{Cgroup, RunOptions};
+    ///
+    /// let elf_bytes = std::fs::read("/usr/bin/sh").unwrap();
+    /// let cgroup = Cgroup::create(c"/sys/fs/cgroup/runner", c"payload-1");
+    /// let mut child = RunOptions::new()
+    ///     .with_args(&["-c", "sleep 60 & sleep 60 &"])
+    ///     .with_cgroup(cgroup)
+    ///     .spawn(&elf_bytes)
+    ///     .unwrap();
+    /// child.wait().unwrap();
+    /// // the orphaned sleeps are still running in the cgroup
+    /// child.kill_tree().unwrap();
+    /// ```
+    pub fn kill_tree(&mut self) -> Result<(), RunError> {
+        self.cgroup
+            .as_ref()
+            .ok_or(Errno::EINVAL)
+            .and_then(CgroupDir::kill)
+            .map_err(RunError::KillError)
+    }
+
+    /// Returns the raw pidfd of the child, opening one into `opened` if it has none.
+    fn raw_pidfd(&self, opened: &mut Option<PidFd>) -> Result<i32, RunError> {
+        let pidfd = match &self.pidfd {
+            Some(pidfd) => pidfd,
+            // The pid cannot be recycled while the child is not reaped, so the pidfd refers to it
+            None => opened.insert(pidfd::open(self.pid).map_err(RunError::WaitError)?),
+        };
+        Ok(pidfd.fd().raw())
+    }
+
+    fn reaped(&mut self, status: i32, rusage: RawRusage) -> RunReport {
+        let report = RunReport {
+            status: ExitStatus::from_raw(status),
+            rusage: rusage.into(),
+            wall_time: time::monotonic_now().saturating_sub(self.started),
+            cgroup: self.cgroup.as_ref().and_then(|cgroup| cgroup.stats().ok()),
+        };
+        self.report = Some(report);
+        report
+    }
+}
+
+/// Calls `wait4` on `pid`, retrying on `EINTR`, and returns the raw status and resource usage.
+/// Returns `None` if `WNOHANG` was requested and the child has not changed state yet.
+pub(crate) fn wait_pid(pid: i32, options: i32) -> Result<Option<(i32, RawRusage)>, RunError> {
+    let mut status: i32 = 0;
+    let mut rusage = RawRusage::default();
+    loop {
+        let waited_pid = unsafe {
+            syscalls::wait4(
+                pid,
+                &mut status as *mut i32 as *mut u8,
+                options,
+                (&mut rusage as *mut RawRusage).cast(),
+            )
+        };
+        match cvt(waited_pid) {
+            Ok(0) => return Ok(None),
+            Ok(_) => return Ok(Some((status, rusage))),
+            Err(Errno::EINTR) => continue,
+            Err(errno) => return Err(RunError::WaitError(errno)),
+        }
+    }
+}
@@ -0,0 +1,113 @@
+//! Privilege drop of the payload, see [`RunOptions::with_uid`](crate::RunOptions::with_uid).
+
+use crate::Errno;
+use crate::syscalls::{self, cvt};
+
+/// Supplementary groups that can be read back in the child, whose stack is small.
+pub(crate) const MAX_GROUPS: usize = 1024;
+
+/// Identity the child switches to before `execve`.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct Credentials<'a> {
+    pub(crate) uid: Option<u32>,
+    pub(crate) gid: Option<u32>,
+    pub(crate) groups: Option<&'a [u32]>,
+}
+
+impl Credentials<'_> {
+    /// Switch to the credentials, in the child. Only issues system calls.
+    ///
+    /// Supplementary groups and the gid are changed first, while the process still has the
+    /// privileges to do so, then the uid. The result is read back, and a dropped root uid must
+    /// not be recoverable: any mismatch is reported as `EPERM`.
+    pub(crate) fn apply(&self) -> Result<(), Errno> {
+        match self.groups {
+            Some(groups) if groups.len() > MAX_GROUPS => return Err(Errno::EINVAL),
+            Some(groups) => set_groups(groups)?,
+            // like std, do not leak the supplementary groups of the parent to another user
+            None if self.uid.is_some() => match set_groups(&[]) {
+                Ok(()) | Err(Errno::EPERM) => {}
+                Err(errno) => return Err(errno),
+            },
+            None => {}
+        }
+        if let Some(gid) = self.gid {
+            cvt(unsafe { syscalls::setresgid(gid, gid, gid) })?;
+        }
+        if let Some(uid) = self.uid {
+            cvt(unsafe { syscalls::setresuid(uid, uid, uid) })?;
+        }
+        self.verify()
+    }
+
+    fn verify(&self) -> Result<(), Errno> {
+        if let Some(gid) = self.gid {
+            let ids = get_ids(syscalls::getresgid)?;
+            if ids != [gid; 3] {
+                return Err(Errno::EPERM);
+            }
+        }
+        if let Some(groups) = self.groups {
+            let mut buf = [0u32; MAX_GROUPS];
+            let count =
+                cvt(unsafe { syscalls::getgroups(MAX_GROUPS as i32, buf.as_mut_ptr().cast()) })?;
+            if !same_ids(&buf[..count as usize], groups) {
+                return Err(Errno::EPERM);
+            }
+        }
+        if let Some(uid) = self.uid {
+            let ids = get_ids(syscalls::getresuid)?;
+            if ids != [uid; 3] {
+                return Err(Errno::EPERM);
+            }
+            // u32::MAX (-1) leaves the real and saved ids unchanged
+            if uid != 0 && unsafe { syscalls::setresuid(u32::MAX, 0, u32::MAX) } == 0 {
+                return Err(Errno::EPERM);
+            }
+        }
+        Ok(())
+    }
+}
+
+fn set_groups(groups: &[u32]) -> Result<(), Errno> {
+    cvt(unsafe { syscalls::setgroups(groups.len() as i32, groups.as_ptr().cast_mut().cast()) })?;
+    Ok(())
+}
+
+/// Returns `true` if both lists hold the same ids, ignoring order and duplicates, as the kernel
+/// sorts the supplementary groups.
+fn same_ids(a: &[u32], b: &[u32]) -> bool {
+    a.iter().all(|id| b.contains(id)) && b.iter().all(|id| a.contains(id))
+}
+
+/// Read the real, effective and saved ids with `getresuid` or `getresgid`.
+fn get_ids(getres: unsafe fn(*mut u8, *mut u8, *mut u8) -> i32) -> Result<[u32; 3], Errno> {
+    let mut ids = [0u32; 3];
+    let [real, effective, saved] = ids.each_mut().map(|id| (id as *mut u32).cast());
+    cvt(unsafe { getres(real, effective, saved) })?;
+    Ok(ids)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_same_ids() {
+        assert!(same_ids(&[100, 65534], &[65534, 100]));
+        assert!(same_ids(&[100, 100], &[100]));
+        assert!(same_ids(&[], &[]));
+        assert!(!same_ids(&[100, 27], &[100, 65534]));
+        assert!(!same_ids(&[100], &[100, 65534]));
+    }
+
+    #[test]
+    fn test_too_many_groups() {
+        let groups = [0u32; MAX_GROUPS + 1];
+        let credentials = Credentials {
+            groups: Some(&groups),
+            ..Credentials::default()
+        };
+        assert_eq!(credentials.apply(), Err(Errno::EINVAL));
+    }
+}
@@ -0,0 +1,335 @@
+//! Environment of the executed payload: the inherited or explicit base environment, merged with
+//! the overrides configured on [`RunOptions`](crate::RunOptions).
+//!
+//! The merge happens once in the parent, before forking, so the child only has to pass the
+//! prepared `envp` to `execve`.
+
+#[cfg(feature = "alloc")]
+use alloc::vec::Vec;
+
+#[cfg(not(feature = "std"))]
+use crate::{
+    Errno,
+    syscalls::{self, cvt},
+};
+use crate::{RunError, RunOptions};
+
+#[cfg(not(feature = "std"))]
+const AT_FDCWD: i32 = -100;
+#[cfg(not(feature = "std"))]
+const O_RDONLY: i32 = 0;
+#[cfg(not(feature = "std"))]
+const O_CLOEXEC: i32 = 0o2000000;
+
+/// Upper bound on the size of the inherited environment without the `alloc` feature
+#[cfg(not(feature = "alloc"))]
+pub(crate) const ENVIRON_BUF_LEN: usize = 32 * 1024;
+
+/// A modification applied on top of the base environment.
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum EnvOp<'a> {
+    Set(&'a str, &'a str),
+    Remove(&'a str),
+}
+
+impl<'a> EnvOp<'a> {
+    fn key(&self) -> &'a str {
+        match *self {
+            EnvOp::Set(key, _) | EnvOp::Remove(key) => key,
+        }
+    }
+}
+
+/// One entry of the final environment.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum EnvEntry<'e> {
+    /// `KEY=VALUE` inherited from the parent, followed by a null terminator in memory
+    Inherited(&'e [u8]),
+    /// `KEY=VALUE` provided through [`RunOptions::with_env`]
+    Var(&'e str),
+    /// An override, joined as `KEY=VALUE`
+    Pair(&'e str, &'e str),
+}
+
+impl<'e> EnvEntry<'e> {
+    fn key(&self) -> &'e [u8] {
+        let var = match *self {
+            EnvEntry::Inherited(var) => var,
+            EnvEntry::Var(var) => var.as_bytes(),
+            EnvEntry::Pair(key, _) => return key.as_bytes(),
+        };
+        let end = var.iter().position(|&b| b == b'=').unwrap_or(var.len());
+        &var[..end]
+    }
+
+    /// The pieces which concatenated form `KEY=VALUE`
+    pub(crate) fn parts(&self) -> [&'e [u8]; 3] {
+        match *self {
+            EnvEntry::Inherited(var) => [var, b"", b""],
+            EnvEntry::Var(var) => [var.as_bytes(), b"", b""],
+            EnvEntry::Pair(key, value) => [key.as_bytes(), b"=", value.as_bytes()],
+        }
+    }
+}
+
+/// Iterator over the merged environment.
+///
+/// Base entries keep their position, with overridden values replaced in place and removed keys
+/// skipped. Overrides for keys missing from the base are appended in the order they were set.
+#[derive(Clone)]
+pub(crate) struct MergedEnv<'e, B> {
+    base: B,
+    remaining_base: B,
+    ops: &'e [EnvOp<'e>],
+    next_op: usize,
+}
+
+impl<'e, B: Iterator<Item = EnvEntry<'e>> + Clone> MergedEnv<'e, B> {
+    fn last_op(&self, key: &[u8]) -> Option<(usize, EnvOp<'e>)> {
+        self.ops
+            .iter()
+            .enumerate()
+            .rev()
+            .find(|(_, op)| op.key().as_bytes() == key)
+            .map(|(i, op)| (i, *op))
+    }
+}
+
+impl<'e, B: Iterator<Item = EnvEntry<'e>> + Clone> Iterator for MergedEnv<'e, B> {
+    type Item = EnvEntry<'e>;
+
+    fn next(&mut self) -> Option<EnvEntry<'e>> {
+        while let Some(entry) = self.remaining_base.next() {
+            match self.last_op(entry.key()) {
+                None => return Some(entry),
+                Some((_, EnvOp::Set(key, value))) => return Some(EnvEntry::Pair(key, value)),
+                Some((_, EnvOp::Remove(_))) => continue,
+            }
+        }
+        while let Some(&op) = self.ops.get(self.next_op) {
+            let index = self.next_op;
+            self.next_op += 1;
+            let EnvOp::Set(key, value) = op else {
+                continue;
+            };
+            let is_last = matches!(self.last_op(key.as_bytes()), Some((last, _)) if last == index);
+            let in_base = self.base.clone().any(|entry| entry.key() == key.as_bytes());
+            if is_last && !in_base {
+                return Some(EnvEntry::Pair(key, value));
+            }
+        }
+        None
+    }
+}
+
+/// Merge the base environment with the overrides of `options`.
+///
+/// The base is the list given to [`RunOptions::with_env`] if any, otherwise `inherited`, which
+/// holds null terminated `KEY=VALUE` entries.
+pub(crate) fn merged<'e>(
+    options: &'e RunOptions<'e>,
+    inherited: &'e [u8],
+) -> MergedEnv<'e, impl Iterator<Item = EnvEntry<'e>> + Clone> {
+    let explicit = options.env.unwrap_or_default();
+    let inherited = match options.env {
+        Some(_) => &[][..],
+        None => inherited,
+    };
+    let base = explicit.iter().map(|var| EnvEntry::Var(var)).chain(
+        inherited
+            .split(|&b| b == 0)
+            .filter(|var| !var.is_empty())
+            .map(EnvEntry::Inherited),
+    );
+    MergedEnv {
+        remaining_base: base.clone(),
+        base,
+        ops: options.env_ops.as_slice(),
+        next_op: 0,
+    }
+}
+
+/// Returns `true` if the parent environment has to be read for `options`.
+pub(crate) fn inherits(options: &RunOptions<'_>) -> bool {
+    options.env.is_none() && !options.env_clear
+}
+
+/// Read the parent environment as null terminated `KEY=VALUE` entries.
+#[cfg(feature = "std")]
+pub(crate) fn inherited_environment() -> Result<Vec<u8>, RunError> {
+    use std::os::unix::ffi::OsStrExt;
+
+    // vars_os() reads `environ` while holding std's environment lock
+    let mut environ = Vec::new();
+    for (key, value) in std::env::vars_os() {
+        environ.extend_from_slice(key.as_bytes());
+        environ.push(b'=');
+        environ.extend_from_slice(value.as_bytes());
+        environ.push(0);
+    }
+    Ok(environ)
+}
+
+/// Read the parent environment from `/proc/self/environ`, as null terminated `KEY=VALUE` entries.
+///
+/// This is the environment the process was started with, later `setenv` calls are not visible.
+/// When /proc is unavailable, nothing is inherited.
+#[cfg(all(feature = "alloc", not(feature = "std")))]
+pub(crate) fn inherited_environment() -> Result<Vec<u8>, RunError> {
+    let mut environ = Vec::new();
+    let Some(fd) = open_proc_environ()? else {
+        return Ok(environ);
+    };
+    let mut chunk = [0u8; 4096];
+    let result = loop {
+        match read_some(fd, &mut chunk) {
+            Ok(0) => break Ok(()),
+            Ok(n) => environ.extend_from_slice(&chunk[..n]),
+            Err(errno) => break Err(errno),
+        }
+    };
+    unsafe { syscalls::close(fd) };
+    result.map_err(RunError::EnvReadError)?;
+    if environ.last().is_some_and(|&b| b != 0) {
+        environ.push(0);
+    }
+    Ok(environ)
+}
+
+/// Read the parent environment from `/proc/self/environ` into `buf`, as null terminated
+/// `KEY=VALUE` entries.
+///
+/// This is the environment the process was started with, later `setenv` calls are not visible.
+/// When /proc is unavailable, nothing is inherited.
+#[cfg(not(feature = "alloc"))]
+pub(crate) fn inherited_environment(buf: &mut [u8; ENVIRON_BUF_LEN]) -> Result<&[u8], RunError> {
+    let Some(fd) = open_proc_environ()? else {
+        return Ok(&[]);
+    };
+    let mut len = 0;
+    // Keep one byte to null terminate the last entry if needed
+    let result = loop {
+        if len == buf.len() - 1 {
+            break Err(RunError::TooManyEnvVars);
+        }
+        match read_some(fd, &mut buf[len..ENVIRON_BUF_LEN - 1]) {
+            Ok(0) => break Ok(()),
+            Ok(n) => len += n,
+            Err(errno) => break Err(RunError::EnvReadError(errno)),
+        }
+    };
+    unsafe { syscalls::close(fd) };
+    result?;
+    if len > 0 && buf[len - 1] != 0 {
+        buf[len] = 0;
+        len += 1;
+    }
+    Ok(&buf[..len])
+}
+
+#[cfg(not(feature = "std"))]
+fn open_proc_environ() -> Result<Option<i32>, RunError> {
+    let ret =
+        unsafe { syscalls::openat(AT_FDCWD, b"/proc/self/environ\0", O_RDONLY | O_CLOEXEC, 0) };
+    match cvt(ret) {
+        Ok(fd) => Ok(Some(fd)),
+        Err(Errno::ENOENT | Errno::EACCES) => Ok(None),
+        Err(errno) => Err(RunError::EnvReadError(errno)),
+    }
+}
+
+#[cfg(not(feature = "std"))]
+fn read_some(fd: i32, buf: &mut [u8]) -> Result<usize, Errno> {
+    loop {
+        match cvt(unsafe { syscalls::read(fd, buf.as_mut_ptr(), buf.len()) }) {
+            Ok(n) => return Ok(n as usize),
+            Err(Errno::EINTR) => continue,
+            Err(errno) => return Err(errno),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    extern crate std;
+    use std::vec::Vec;
+
+    fn merge<'e>(options: &'e RunOptions<'e>, inherited: &'e [u8]) -> Vec<std::string::String> {
+        merged(options, inherited)
+            .map(|entry| {
+                let parts = entry.parts();
+                std::string::String::from_utf8([parts[0], parts[1], parts[2]].concat()).unwrap()
+            })
+            .collect()
+    }
+
+    const INHERITED: &[u8] = b"PATH=/usr/bin\0HOME=/root\0LANG=C\0";
+
+    #[test]
+    fn test_merge_inherited() {
+        let options = RunOptions::new();
+        assert_eq!(
+            merge(&options, INHERITED),
+            ["PATH=/usr/bin", "HOME=/root", "LANG=C"]
+        );
+    }
+
+    #[test]
+    fn test_merge_overrides_in_place() {
+        let options = RunOptions::new()
+            .env("HOME", "/tmp")
+            .env("NEW", "1")
+            .env_remove("LANG");
+        assert_eq!(
+            merge(&options, INHERITED),
+            ["PATH=/usr/bin", "HOME=/tmp", "NEW=1"]
+        );
+    }
+
+    #[test]
+    fn test_merge_last_operation_wins() {
+        let options = RunOptions::new()
+            .env("NEW", "1")
+            .env_remove("PATH")
+            .env("PATH", "/bin")
+            .env_remove("NEW")
+            .env("NEW", "2");
+        assert_eq!(
+            merge(&options, INHERITED),
+            ["PATH=/bin", "HOME=/root", "LANG=C", "NEW=2"]
+        );
+    }
+
+    #[test]
+    fn test_merge_explicit_env_replaces_inherited() {
+        let env = ["A=1", "B=2"];
+        let options = RunOptions::new().with_env(&env).env("B", "3");
+        assert_eq!(merge(&options, INHERITED), ["A=1", "B=3"]);
+    }
+
+    #[test]
+    fn test_merge_env_clear() {
+        let options = RunOptions::new().env("OLD", "1").env_clear().env("A", "1");
+        assert!(!inherits(&options));
+        assert_eq!(merge(&options, b""), ["A=1"]);
+    }
+
+    #[test]
+    fn test_inherited_environment() {
+        #[cfg(not(feature = "alloc"))]
+        let mut buf = [0u8; ENVIRON_BUF_LEN];
+        #[cfg(not(feature = "alloc"))]
+        let environ = inherited_environment(&mut buf).unwrap();
+        #[cfg(feature = "alloc")]
+        let environ = inherited_environment().unwrap();
+
+        // cargo always runs the tests with CARGO_* variables set
+        assert!(
+            environ
+                .split(|&b| b == 0)
+                .any(|var| var.starts_with(b"CARGO_PKG_NAME=memfd-runner"))
+        );
+        assert_eq!(environ.last(), Some(&0));
+    }
+}
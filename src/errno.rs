@@ -0,0 +1,158 @@
+use core::fmt;
+
+/// An error number returned by a failed Linux system call.
+///
+/// System calls report failures by returning `-errno`. This type wraps the (positive) error
+/// number and gives access to its symbolic name without requiring libc.
+///
+/// # Example
+/// ```rust
+/// use memfd_runner::Errno;
+///
+/// let errno = Errno::from_raw(2);
+/// assert_eq!(errno, Errno::ENOENT);
+/// assert_eq!(errno.name(), Some("ENOENT"));
+/// assert_eq!(errno.to_string(), "ENOENT: No such file or directory");
+/// ```
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Errno(i32);
+
+macro_rules! errnos {
+    ($($name:ident = $value:literal => $description:literal,)*) => {
+        impl Errno {
+            $(
+                #[doc = $description]
+                pub const $name: Errno = Errno($value);
+            )*
+
+            /// Returns the symbolic name of this error number (e.g. `"ENOENT"`), if known.
+            pub const fn name(self) -> Option<&'static str> {
+                match self.0 {
+                    $($value => Some(stringify!($name)),)*
+                    _ => None,
+                }
+            }
+
+            /// Returns a short human readable description of this error number, if known.
+            pub const fn description(self) -> Option<&'static str> {
+                match self.0 {
+                    $($value => Some($description),)*
+                    _ => None,
+                }
+            }
+        }
+    };
+}
+
+errnos! {
+    EPERM = 1 => "Operation not permitted",
+    ENOENT = 2 => "No such file or directory",
+    ESRCH = 3 => "No such process",
+    EINTR = 4 => "Interrupted system call",
+    EIO = 5 => "Input/output error",
+    ENXIO = 6 => "No such device or address",
+    E2BIG = 7 => "Argument list too long",
+    ENOEXEC = 8 => "Exec format error",
+    EBADF = 9 => "Bad file descriptor",
+    ECHILD = 10 => "No child processes",
+    EAGAIN = 11 => "Resource temporarily unavailable",
+    ENOMEM = 12 => "Cannot allocate memory",
+    EACCES = 13 => "Permission denied",
+    EFAULT = 14 => "Bad address",
+    EBUSY = 16 => "Device or resource busy",
+    EEXIST = 17 => "File exists",
+    EXDEV = 18 => "Invalid cross-device link",
+    ENODEV = 19 => "No such device",
+    ENOTDIR = 20 => "Not a directory",
+    EISDIR = 21 => "Is a directory",
+    EINVAL = 22 => "Invalid argument",
+    ENFILE = 23 => "Too many open files in system",
+    EMFILE = 24 => "Too many open files",
+    ENOTTY = 25 => "Inappropriate ioctl for device",
+    ETXTBSY = 26 => "Text file busy",
+    EFBIG = 27 => "File too large",
+    ENOSPC = 28 => "No space left on device",
+    ESPIPE = 29 => "Illegal seek",
+    EROFS = 30 => "Read-only file system",
+    EMLINK = 31 => "Too many links",
+    EPIPE = 32 => "Broken pipe",
+    ERANGE = 34 => "Numerical result out of range",
+    EDEADLK = 35 => "Resource deadlock avoided",
+    ENAMETOOLONG = 36 => "File name too long",
+    ENOSYS = 38 => "Function not implemented",
+    ENOTEMPTY = 39 => "Directory not empty",
+    ELOOP = 40 => "Too many levels of symbolic links",
+    ELIBBAD = 80 => "Accessing a corrupted shared library",
+    EILSEQ = 84 => "Invalid or incomplete multibyte or wide character",
+    EOPNOTSUPP = 95 => "Operation not supported",
+    EAFNOSUPPORT = 97 => "Address family not supported by protocol",
+    EADDRINUSE = 98 => "Address already in use",
+    ETIMEDOUT = 110 => "Connection timed out",
+    ECONNREFUSED = 111 => "Connection refused",
+    ECANCELED = 125 => "Operation canceled",
+}
+
+impl Errno {
+    /// Wraps a raw (positive) error number.
+    pub const fn from_raw(errno: i32) -> Self {
+        Self(errno)
+    }
+
+    /// Returns the raw (positive) error number.
+    pub const fn raw(self) -> i32 {
+        self.0
+    }
+
+    /// Converts the negative return value of a raw system call into an `Errno`.
+    pub(crate) const fn from_ret(ret: isize) -> Self {
+        Self(-ret as i32)
+    }
+}
+
+impl fmt::Debug for Errno {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.name() {
+            Some(name) => f.write_str(name),
+            None => write!(f, "Errno({})", self.0),
+        }
+    }
+}
+
+impl fmt::Display for Errno {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.name(), self.description()) {
+            (Some(name), Some(description)) => write!(f, "{name}: {description}"),
+            _ => write!(f, "unknown error {}", self.0),
+        }
+    }
+}
+
+impl core::error::Error for Errno {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    extern crate std;
+    use std::format;
+
+    #[test]
+    fn test_errno_names() {
+        assert_eq!(Errno::from_raw(2), Errno::ENOENT);
+        assert_eq!(Errno::ETXTBSY.name(), Some("ETXTBSY"));
+        assert_eq!(Errno::ENOSYS.raw(), 38);
+        assert_eq!(Errno::from_raw(4095).name(), None);
+    }
+
+    #[test]
+    fn test_errno_from_ret() {
+        assert_eq!(Errno::from_ret(-13), Errno::EACCES);
+    }
+
+    #[test]
+    fn test_errno_formatting() {
+        assert_eq!(format!("{:?}", Errno::E2BIG), "E2BIG");
+        assert_eq!(format!("{:?}", Errno::from_raw(4095)), "Errno(4095)");
+        assert_eq!(format!("{}", Errno::EACCES), "EACCES: Permission denied");
+        assert_eq!(format!("{}", Errno::from_raw(4095)), "unknown error 4095");
+    }
+}
@@ -0,0 +1,257 @@
+use core::cell::Cell;
+use core::ffi::CStr;
+
+use crate::Errno;
+use crate::syscalls::{self, cvt};
+
+pub(crate) const AT_FDCWD: i32 = -100;
+pub(crate) const O_CLOEXEC: i32 = 0o2000000;
+pub(crate) const O_DIRECTORY: i32 = 0o200000;
+pub(crate) const O_PATH: i32 = 0o10000000;
+pub(crate) const POLLIN: i16 = 0x1;
+pub(crate) const POLLHUP: i16 = 0x10;
+pub(crate) const F_SETFD: i32 = 2;
+const F_DUPFD_CLOEXEC: i32 = 1030;
+const AF_UNIX: i32 = 1;
+const SOCK_STREAM: i32 = 1;
+const SOL_SOCKET: i32 = 1;
+const SCM_RIGHTS: i32 = 1;
+const MSG_DONTWAIT: i32 = 0x40;
+pub(crate) const MSG_NOSIGNAL: i32 = 0x4000;
+const MSG_CMSG_CLOEXEC: i32 = 0x40000000;
+
+/// `struct pollfd`
+#[repr(C)]
+pub(crate) struct PollFd {
+    pub(crate) fd: i32,
+    pub(crate) events: i16,
+    pub(crate) revents: i16,
+}
+
+/// `struct iovec`
+#[repr(C)]
+pub(crate) struct IoVec {
+    pub(crate) base: *mut u8,
+    pub(crate) len: usize,
+}
+
+/// `struct msghdr`
+#[repr(C)]
+struct MsgHdr {
+    name: *mut u8,
+    namelen: u32,
+    iov: *mut IoVec,
+    iovlen: usize,
+    control: *mut u8,
+    controllen: usize,
+    flags: i32,
+}
+
+/// `struct cmsghdr` carrying a single descriptor, padded to `CMSG_SPACE(sizeof(int))`
+#[repr(C)]
+#[derive(Default)]
+struct FdMessage {
+    len: usize,
+    level: i32,
+    kind: i32,
+    fd: i32,
+    padding: i32,
+}
+
+impl MsgHdr {
+    const fn empty() -> Self {
+        Self {
+            name: core::ptr::null_mut(),
+            namelen: 0,
+            iov: core::ptr::null_mut(),
+            iovlen: 0,
+            control: core::ptr::null_mut(),
+            controllen: 0,
+            flags: 0,
+        }
+    }
+
+    fn new(iov: &mut IoVec, control: &mut FdMessage) -> Self {
+        Self {
+            name: core::ptr::null_mut(),
+            namelen: 0,
+            iov,
+            iovlen: 1,
+            control: (control as *mut FdMessage).cast(),
+            controllen: size_of::<FdMessage>(),
+            flags: 0,
+        }
+    }
+}
+
+/// Room for the `msghdr` of [`send_fd`], so that its address is known before the call: a
+/// seccomp filter can then let exactly that `sendmsg` through.
+pub(crate) struct MsgSlot(Cell<MsgHdr>);
+
+impl MsgSlot {
+    pub(crate) const fn new() -> Self {
+        Self(Cell::new(MsgHdr::empty()))
+    }
+
+    /// The address passed to `sendmsg`
+    pub(crate) fn as_ptr(&self) -> *const u8 {
+        self.0.as_ptr().cast()
+    }
+}
+
+/// An owned file descriptor, closed when dropped.
+#[derive(Debug)]
+pub(crate) struct Fd(i32);
+
+impl Fd {
+    /// Takes ownership of `fd`.
+    pub(crate) const fn from_raw(fd: i32) -> Self {
+        Self(fd)
+    }
+
+    pub(crate) const fn raw(&self) -> i32 {
+        self.0
+    }
+
+    /// Releases ownership of the descriptor without closing it.
+    pub(crate) fn into_raw(self) -> i32 {
+        let fd = self.0;
+        core::mem::forget(self);
+        fd
+    }
+
+    /// Moves the descriptor to the lowest free number at or above `min`, if it is below.
+    pub(crate) fn at_least(self, min: i32) -> Result<Fd, Errno> {
+        if self.0 >= min {
+            return Ok(self);
+        }
+        let fd = cvt(unsafe { syscalls::fcntl(self.0, F_DUPFD_CLOEXEC, min) })?;
+        Ok(Fd(fd))
+    }
+
+    /// `read()` retrying on `EINTR`. Returns 0 at end of file.
+    pub(crate) fn read(&self, buf: &mut [u8]) -> Result<usize, Errno> {
+        loop {
+            match cvt(unsafe { syscalls::read(self.0, buf.as_mut_ptr(), buf.len()) }) {
+                Ok(n) => return Ok(n as usize),
+                Err(Errno::EINTR) => continue,
+                Err(errno) => return Err(errno),
+            }
+        }
+    }
+
+    /// `write()` retrying on `EINTR`. Returns the number of bytes written.
+    pub(crate) fn write(&self, buf: &[u8]) -> Result<usize, Errno> {
+        loop {
+            match cvt(unsafe { syscalls::write(self.0, buf.as_ptr().cast_mut(), buf.len()) }) {
+                Ok(n) => return Ok(n as usize),
+                Err(Errno::EINTR) => continue,
+                Err(errno) => return Err(errno),
+            }
+        }
+    }
+}
+
+impl Drop for Fd {
+    fn drop(&mut self) {
+        unsafe { syscalls::close(self.0) };
+    }
+}
+
+/// Opens the directory at `path` as an `O_PATH` descriptor, usable with `fchdir()`.
+pub(crate) fn open_dir(path: &CStr) -> Result<Fd, Errno> {
+    let fd = cvt(unsafe {
+        syscalls::openat(
+            AT_FDCWD,
+            path.to_bytes_with_nul(),
+            O_PATH | O_DIRECTORY | O_CLOEXEC,
+            0,
+        )
+    })?;
+    Ok(Fd(fd))
+}
+
+/// Opens `path` as an `O_PATH` descriptor, also returning whether it is a directory.
+pub(crate) fn open_path(path: &CStr) -> Result<(Fd, bool), Errno> {
+    match open_dir(path) {
+        Err(Errno::ENOTDIR) => {
+            let fd = cvt(unsafe {
+                syscalls::openat(AT_FDCWD, path.to_bytes_with_nul(), O_PATH | O_CLOEXEC, 0)
+            })?;
+            Ok((Fd(fd), false))
+        }
+        result => result.map(|fd| (fd, true)),
+    }
+}
+
+/// Creates a pipe with both ends marked close-on-exec. Returns `(read_end, write_end)`.
+pub(crate) fn pipe() -> Result<(Fd, Fd), Errno> {
+    let mut fds = [0i32; 2];
+    cvt(unsafe { syscalls::pipe2(fds.as_mut_ptr().cast(), O_CLOEXEC) })?;
+    Ok((Fd(fds[0]), Fd(fds[1])))
+}
+
+/// Creates a connected pair of `AF_UNIX` stream sockets, both marked close-on-exec.
+pub(crate) fn socketpair() -> Result<(Fd, Fd), Errno> {
+    let mut fds = [0i32; 2];
+    cvt(unsafe {
+        syscalls::socketpair(AF_UNIX, SOCK_STREAM | O_CLOEXEC, 0, fds.as_mut_ptr().cast())
+    })?;
+    Ok((Fd(fds[0]), Fd(fds[1])))
+}
+
+/// Sends a copy of `fd` over the `AF_UNIX` socket `socket` (`SCM_RIGHTS`), with the message
+/// header in `slot` and the `MSG_NOSIGNAL` flag. Only issues system calls.
+pub(crate) fn send_fd(socket: i32, fd: i32, slot: &MsgSlot) -> Result<(), Errno> {
+    let mut byte = [0u8; 1];
+    let mut iov = IoVec {
+        base: byte.as_mut_ptr(),
+        len: byte.len(),
+    };
+    let mut control = FdMessage {
+        len: size_of::<FdMessage>() - size_of::<i32>(),
+        level: SOL_SOCKET,
+        kind: SCM_RIGHTS,
+        fd,
+        padding: 0,
+    };
+    slot.0.set(MsgHdr::new(&mut iov, &mut control));
+    cvt(unsafe { syscalls::sendmsg(socket, slot.as_ptr().cast_mut(), MSG_NOSIGNAL) })?;
+    Ok(())
+}
+
+/// Receives a descriptor sent with [`send_fd`], marked close-on-exec, without blocking.
+///
+/// Returns `EAGAIN` if nothing was sent yet, and `EIO` if the peer closed the socket or the
+/// message carries no descriptor.
+pub(crate) fn recv_fd(socket: &Fd) -> Result<Fd, Errno> {
+    let mut byte = [0u8; 1];
+    let mut iov = IoVec {
+        base: byte.as_mut_ptr(),
+        len: byte.len(),
+    };
+    let mut control = FdMessage::default();
+    let mut msg = MsgHdr::new(&mut iov, &mut control);
+    let received = cvt(unsafe {
+        syscalls::recvmsg(
+            socket.0,
+            (&mut msg as *mut MsgHdr).cast(),
+            MSG_DONTWAIT | MSG_CMSG_CLOEXEC,
+        )
+    })?;
+    if received == 0 || control.level != SOL_SOCKET || control.kind != SCM_RIGHTS {
+        return Err(Errno::EIO);
+    }
+    Ok(Fd(control.fd))
+}
+
+/// `dup2(src, dst)`, or clear the close-on-exec flag of `src` when both are equal: `dup2()` is
+/// a no-op then, and the descriptor still has to survive `execve`.
+pub(crate) fn dup_onto(src: i32, dst: i32) -> Result<(), Errno> {
+    if src == dst {
+        cvt(unsafe { syscalls::fcntl(src, F_SETFD, 0) })?;
+    } else {
+        cvt(unsafe { syscalls::dup2(src, dst) })?;
+    }
+    Ok(())
+}
@@ -0,0 +1,217 @@
+//! `posix_spawn`-style file actions, see [`RunOptions::with_file_actions`](crate::RunOptions::with_file_actions).
+
+use core::ffi::CStr;
+
+use crate::Errno;
+use crate::fd::{self, AT_FDCWD, F_SETFD, Fd, O_CLOEXEC};
+use crate::syscalls::{self, cvt};
+
+const O_RDONLY: i32 = 0o0;
+const O_WRONLY: i32 = 0o1;
+const O_CREAT: i32 = 0o100;
+const O_TRUNC: i32 = 0o1000;
+const O_APPEND: i32 = 0o2000;
+const O_DIRECTORY: i32 = 0o200000;
+const FD_CLOEXEC: i32 = 1;
+const CLOSE_RANGE_CLOEXEC: u32 = 1 << 2;
+
+/// An operation on the file descriptor table of the child, applied before `execve`.
+///
+/// Actions run in order after the [standard streams](crate::Stdio) are set up, so they can
+/// also rewire descriptors 0 to 2. Descriptors they produce are inherited by the payload.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FileAction<'a> {
+    /// Duplicate `src` onto `dst`, like `dup2(src, dst)`. When both are equal, the descriptor
+    /// is only made inheritable.
+    Dup2 { src: i32, dst: i32 },
+    /// Open `path` with the raw `open(2)` `flags` and `mode`, and place the descriptor at `fd`.
+    /// Relative paths are resolved from the working directory of the child.
+    Open {
+        fd: i32,
+        path: &'a CStr,
+        flags: i32,
+        mode: u32,
+    },
+    /// Close `fd`.
+    Close(i32),
+}
+
+impl<'a> FileAction<'a> {
+    /// Open `path` read-only at `fd`.
+    pub const fn open_read(fd: i32, path: &'a CStr) -> Self {
+        FileAction::Open {
+            fd,
+            path,
+            flags: O_RDONLY,
+            mode: 0,
+        }
+    }
+
+    /// Open `path` write-only at `fd`, creating it or truncating it.
+    pub const fn open_write(fd: i32, path: &'a CStr) -> Self {
+        FileAction::Open {
+            fd,
+            path,
+            flags: O_WRONLY | O_CREAT | O_TRUNC,
+            mode: 0o666,
+        }
+    }
+
+    /// Open `path` write-only at `fd` for appending, creating it if needed.
+    pub const fn open_append(fd: i32, path: &'a CStr) -> Self {
+        FileAction::Open {
+            fd,
+            path,
+            flags: O_WRONLY | O_CREAT | O_APPEND,
+            mode: 0o666,
+        }
+    }
+
+    /// Perform the action, in the child. Only issues system calls.
+    fn apply(&self) -> Result<(), Errno> {
+        match *self {
+            FileAction::Dup2 { src, dst } => fd::dup_onto(src, dst),
+            FileAction::Open {
+                fd,
+                path,
+                flags,
+                mode,
+            } => {
+                let opened = cvt(unsafe {
+                    syscalls::openat(AT_FDCWD, path.to_bytes_with_nul(), flags, mode)
+                })?;
+                if opened == fd {
+                    return Ok(());
+                }
+                let opened = Fd::from_raw(opened);
+                cvt(unsafe { syscalls::dup2(opened.raw(), fd) })?;
+                Ok(())
+            }
+            FileAction::Close(fd) => {
+                cvt(unsafe { syscalls::close(fd) })?;
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Lowest descriptor above every destination of `actions`.
+///
+/// The memfd and the error pipe are moved at or above it before forking, so that no action can
+/// replace them in the child.
+pub(crate) fn first_free_fd(actions: &[FileAction<'_>]) -> i32 {
+    actions
+        .iter()
+        .filter_map(|action| match *action {
+            FileAction::Dup2 { dst: fd, .. } | FileAction::Open { fd, .. } => Some(fd),
+            FileAction::Close(_) => None,
+        })
+        .max()
+        .map_or(0, |fd| fd.saturating_add(1))
+}
+
+/// Apply `actions` in order, in the child.
+pub(crate) fn apply(actions: &[FileAction<'_>]) -> Result<(), Errno> {
+    actions.iter().try_for_each(FileAction::apply)
+}
+
+/// Mark every descriptor above stderr close-on-exec, in the child.
+///
+/// Marking rather than closing keeps the memfd and the error pipe usable until `execve`, and
+/// descriptors installed afterwards by file actions are still inherited. Uses
+/// `close_range(CLOSE_RANGE_CLOEXEC)` (Linux 5.11+), falling back to listing `/proc/self/fd`.
+pub(crate) fn cloexec_other_fds() -> Result<(), Errno> {
+    match cvt(unsafe { syscalls::close_range(3, u32::MAX, CLOSE_RANGE_CLOEXEC) }) {
+        Ok(_) => Ok(()),
+        // close_range is missing, or does not know the flag yet
+        Err(Errno::ENOSYS | Errno::EINVAL) => cloexec_listed_fds(),
+        Err(errno) => Err(errno),
+    }
+}
+
+/// Walk `/proc/self/fd` with `getdents64` and mark the descriptors above stderr close-on-exec.
+fn cloexec_listed_fds() -> Result<(), Errno> {
+    let dir = cvt(unsafe {
+        syscalls::openat(
+            AT_FDCWD,
+            b"/proc/self/fd\0",
+            O_RDONLY | O_DIRECTORY | O_CLOEXEC,
+            0,
+        )
+    })?;
+    let dir = Fd::from_raw(dir);
+    // `struct linux_dirent64` records, which are 8 bytes aligned
+    let mut buf = [0u64; 128];
+    loop {
+        let len = cvt(unsafe {
+            syscalls::getdents64(dir.raw(), buf.as_mut_ptr().cast(), size_of_val(&buf))
+        })? as usize;
+        if len == 0 {
+            return Ok(());
+        }
+        let bytes = unsafe { core::slice::from_raw_parts(buf.as_ptr().cast::<u8>(), len) };
+        let mut offset = 0;
+        while offset < len {
+            // d_ino (8 bytes), d_off (8 bytes), d_reclen (2 bytes), d_type (1 byte), d_name
+            let reclen = u16::from_ne_bytes([bytes[offset + 16], bytes[offset + 17]]) as usize;
+            let name = &bytes[offset + 19..offset + reclen];
+            offset += reclen;
+            match parse_fd(name) {
+                Some(fd) if fd > 2 && fd != dir.raw() => {
+                    cvt(unsafe { syscalls::fcntl(fd, F_SETFD, FD_CLOEXEC) })?;
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Parse a null terminated directory entry name as a descriptor number, skipping `.` and `..`.
+fn parse_fd(name: &[u8]) -> Option<i32> {
+    let end = name.iter().position(|&byte| byte == 0)?;
+    let digits = &name[..end];
+    if digits.is_empty() {
+        return None;
+    }
+    digits.iter().try_fold(0i32, |fd, &digit| {
+        let digit = digit.checked_sub(b'0').filter(|digit| *digit < 10)?;
+        fd.checked_mul(10)?.checked_add(digit as i32)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Namespaces, pidfd};
+
+    const F_GETFD: i32 = 1;
+
+    #[test]
+    fn test_parse_fd() {
+        assert_eq!(parse_fd(b"42\0\0\0"), Some(42));
+        assert_eq!(parse_fd(b".\0"), None);
+        assert_eq!(parse_fd(b"..\0"), None);
+        assert_eq!(parse_fd(b"\0"), None);
+    }
+
+    #[test]
+    fn test_cloexec_listed_fds() {
+        let (pid, _) = pidfd::fork(Namespaces::empty(), None).unwrap();
+        if pid == 0 {
+            // in the child: an inheritable descriptor gets marked, stderr is left alone
+            let code = unsafe {
+                syscalls::dup2(2, 900);
+                match cloexec_listed_fds() {
+                    Ok(()) => {
+                        (syscalls::fcntl(900, F_GETFD, 0) != FD_CLOEXEC) as i32
+                            + 2 * (syscalls::fcntl(2, F_GETFD, 0) != 0) as i32
+                    }
+                    Err(_) => 4,
+                }
+            };
+            unsafe { syscalls::exit_group(code) };
+        }
+        let (status, _) = crate::child::wait_pid(pid, 0).unwrap().unwrap();
+        assert_eq!(status, 0);
+    }
+}
@@ -0,0 +1,327 @@
+//! Unprivileged filesystem and network confinement of the payload with Landlock, see
+//! [`RunOptions::with_landlock`](crate::RunOptions::with_landlock).
+//!
+//! A [`Ruleset`] lists the paths the payload may read or write and the TCP ports it may bind or
+//! connect to. Everything else is denied: files and directories beneath no listed path, and once
+//! TCP is restricted, every other port. The ruleset is created before forking, so that a missing
+//! path is reported early, then enforced in the child right before `execve`.
+//!
+//! The access rights are those of the Landlock ABI of the running kernel, given by
+//! [`abi_version`]: reparenting files across directories is restricted from ABI 2, truncation
+//! from ABI 3, TCP from ABI 4 and device ioctls from ABI 5. When the kernel lacks Landlock, or
+//! TCP is restricted on a kernel before ABI 4, [`Compatibility`] decides between running the
+//! payload with what the kernel enforces and failing.
+//!
+//! ```rust
+//! use memfd_runner::landlock::{Compatibility, Ruleset};
+//!
+//! let ruleset = Ruleset::new()
+//!     .read_only(c"/usr")
+//!     .read_only(c"/etc/ssl")
+//!     .read_write(c"/tmp")
+//!     .allow_connect(443)
+//!     .compatibility(Compatibility::Strict);
+//! ```
+
+use core::ffi::CStr;
+
+use crate::Errno;
+use crate::fd::{self, Fd};
+use crate::list::List;
+use crate::seccomp::PR_SET_NO_NEW_PRIVS;
+use crate::syscalls::{self, cvt};
+
+const MAX_PATHS: usize = 32;
+const MAX_PORTS: usize = 16;
+
+const LANDLOCK_CREATE_RULESET_VERSION: u32 = 1 << 0;
+const LANDLOCK_RULE_PATH_BENEATH: u32 = 1;
+const LANDLOCK_RULE_NET_PORT: u32 = 2;
+
+const ACCESS_FS_EXECUTE: u64 = 1 << 0;
+const ACCESS_FS_WRITE_FILE: u64 = 1 << 1;
+const ACCESS_FS_READ_FILE: u64 = 1 << 2;
+const ACCESS_FS_READ_DIR: u64 = 1 << 3;
+/// Rights of ABI 1, from `EXECUTE` to `MAKE_SYM`
+const ACCESS_FS_V1: u64 = (1 << 13) - 1;
+const ACCESS_FS_REFER: u64 = 1 << 13;
+const ACCESS_FS_TRUNCATE: u64 = 1 << 14;
+const ACCESS_FS_IOCTL_DEV: u64 = 1 << 15;
+/// Rights granted beneath a [read-only](Ruleset::read_only) path
+const ACCESS_FS_READ: u64 = ACCESS_FS_EXECUTE | ACCESS_FS_READ_FILE | ACCESS_FS_READ_DIR;
+/// Rights which apply to a file, the others only apply to directories
+const ACCESS_FS_FILE: u64 = ACCESS_FS_EXECUTE
+    | ACCESS_FS_WRITE_FILE
+    | ACCESS_FS_READ_FILE
+    | ACCESS_FS_TRUNCATE
+    | ACCESS_FS_IOCTL_DEV;
+const ACCESS_NET_BIND_TCP: u64 = 1 << 0;
+const ACCESS_NET_CONNECT_TCP: u64 = 1 << 1;
+
+/// What happens when the kernel cannot enforce the whole [`Ruleset`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Compatibility {
+    /// Enforce what the kernel supports: without Landlock the payload runs unconfined, and
+    /// before ABI 4 its TCP ports are not restricted
+    #[default]
+    BestEffort,
+    /// Fail with `EOPNOTSUPP` (or `ENOSYS` for a kernel built without Landlock) rather than
+    /// run the payload less confined than requested
+    Strict,
+}
+
+/// Paths and TCP ports the payload may access, see the [module documentation](self).
+///
+/// Without the `alloc` feature, at most 32 paths of each kind and 16 ports of each kind can be
+/// added.
+#[derive(Clone, Default)]
+pub struct Ruleset<'a> {
+    read_only: List<&'a CStr, MAX_PATHS>,
+    read_write: List<&'a CStr, MAX_PATHS>,
+    bind_ports: List<u16, MAX_PORTS>,
+    connect_ports: List<u16, MAX_PORTS>,
+    restrict_tcp: bool,
+    compatibility: Compatibility,
+}
+
+impl<'a> Ruleset<'a> {
+    /// A ruleset denying access to the whole filesystem, and leaving TCP unrestricted.
+    pub const fn new() -> Self {
+        Self {
+            read_only: List::new(),
+            read_write: List::new(),
+            bind_ports: List::new(),
+            connect_ports: List::new(),
+            restrict_tcp: false,
+            compatibility: Compatibility::BestEffort,
+        }
+    }
+
+    /// Allow reading, listing and executing the files beneath `path`, or the file itself.
+    pub fn read_only(mut self, path: &'a CStr) -> Self {
+        self.read_only.push(path);
+        self
+    }
+
+    /// Allow any access to the files beneath `path`, or the file itself: in addition to
+    /// [reading](Self::read_only), creating, writing, truncating, renaming and removing them.
+    pub fn read_write(mut self, path: &'a CStr) -> Self {
+        self.read_write.push(path);
+        self
+    }
+
+    /// Allow binding TCP sockets to `port`, which restricts TCP.
+    pub fn allow_bind(mut self, port: u16) -> Self {
+        self.bind_ports.push(port);
+        self.deny_tcp()
+    }
+
+    /// Allow connecting TCP sockets to `port`, which restricts TCP.
+    pub fn allow_connect(mut self, port: u16) -> Self {
+        self.connect_ports.push(port);
+        self.deny_tcp()
+    }
+
+    /// Restrict TCP: binding and connecting is denied on the ports not allowed with
+    /// [`allow_bind`](Self::allow_bind) and [`allow_connect`](Self::allow_connect). Other
+    /// protocols are not restricted by Landlock.
+    pub fn deny_tcp(mut self) -> Self {
+        self.restrict_tcp = true;
+        self
+    }
+
+    /// Set what happens when the kernel cannot enforce the whole ruleset, defaults to
+    /// [`Compatibility::BestEffort`].
+    pub fn compatibility(mut self, compatibility: Compatibility) -> Self {
+        self.compatibility = compatibility;
+        self
+    }
+
+    /// Create the ruleset in the kernel, or return `None` if it cannot be enforced in
+    /// [best-effort](Compatibility::BestEffort) mode.
+    ///
+    /// Returns `E2BIG` if too many paths or ports were added.
+    pub(crate) fn create(&self) -> Result<Option<Fd>, Errno> {
+        #[cfg(not(feature = "alloc"))]
+        if self.read_only.overflowed()
+            || self.read_write.overflowed()
+            || self.bind_ports.overflowed()
+            || self.connect_ports.overflowed()
+        {
+            return Err(Errno::E2BIG);
+        }
+        let strict = self.compatibility == Compatibility::Strict;
+        let abi = match abi_version() {
+            Ok(abi) => abi,
+            Err(Errno::ENOSYS | Errno::EOPNOTSUPP) if !strict => return Ok(None),
+            Err(errno) => return Err(errno),
+        };
+        let restrict_tcp = match self.restrict_tcp && abi < 4 {
+            true if strict => return Err(Errno::EOPNOTSUPP),
+            true => false,
+            false => self.restrict_tcp,
+        };
+        let attr = RulesetAttr {
+            handled_access_fs: handled_access_fs(abi),
+            handled_access_net: match restrict_tcp {
+                true => ACCESS_NET_BIND_TCP | ACCESS_NET_CONNECT_TCP,
+                false => 0,
+            },
+        };
+        let ruleset = Fd::from_raw(cvt(unsafe {
+            syscalls::landlock_create_ruleset(
+                (&attr as *const RulesetAttr).cast_mut().cast(),
+                size_of::<RulesetAttr>(),
+                0,
+            )
+        })?);
+        // read-write paths get every handled right
+        for (paths, access) in [
+            (&self.read_only, ACCESS_FS_READ),
+            (&self.read_write, u64::MAX),
+        ] {
+            for &path in paths.as_slice() {
+                add_path_rule(&ruleset, path, access & attr.handled_access_fs)?;
+            }
+        }
+        if restrict_tcp {
+            for (ports, access) in [
+                (&self.bind_ports, ACCESS_NET_BIND_TCP),
+                (&self.connect_ports, ACCESS_NET_CONNECT_TCP),
+            ] {
+                for &port in ports.as_slice() {
+                    let rule = NetPortAttr {
+                        allowed_access: access,
+                        port: port as u64,
+                    };
+                    add_rule(&ruleset, LANDLOCK_RULE_NET_PORT, &rule)?;
+                }
+            }
+        }
+        Ok(Some(ruleset))
+    }
+}
+
+/// `struct landlock_ruleset_attr`, without the `scoped` field of ABI 6. Older kernels accept
+/// it as long as the fields they do not know are zero.
+#[repr(C)]
+struct RulesetAttr {
+    handled_access_fs: u64,
+    handled_access_net: u64,
+}
+
+/// `struct landlock_path_beneath_attr`
+#[repr(C, packed)]
+struct PathBeneathAttr {
+    allowed_access: u64,
+    parent_fd: i32,
+}
+
+/// `struct landlock_net_port_attr`
+#[repr(C)]
+struct NetPortAttr {
+    allowed_access: u64,
+    port: u64,
+}
+
+/// Returns the version of the Landlock ABI supported by the running kernel.
+///
+/// Fails with `ENOSYS` if the kernel was built without Landlock, or `EOPNOTSUPP` if it was
+/// disabled at boot.
+pub fn abi_version() -> Result<u32, Errno> {
+    cvt(unsafe {
+        syscalls::landlock_create_ruleset(core::ptr::null_mut(), 0, LANDLOCK_CREATE_RULESET_VERSION)
+    })
+    .map(|abi| abi as u32)
+}
+
+/// Filesystem rights known to the ABI `abi`, all denied unless granted by a rule.
+const fn handled_access_fs(abi: u32) -> u64 {
+    match abi {
+        0 | 1 => ACCESS_FS_V1,
+        2 => ACCESS_FS_V1 | ACCESS_FS_REFER,
+        3 | 4 => ACCESS_FS_V1 | ACCESS_FS_REFER | ACCESS_FS_TRUNCATE,
+        _ => ACCESS_FS_V1 | ACCESS_FS_REFER | ACCESS_FS_TRUNCATE | ACCESS_FS_IOCTL_DEV,
+    }
+}
+
+/// Grant `access` beneath `path`, only keeping the file rights if it is not a directory.
+fn add_path_rule(ruleset: &Fd, path: &CStr, access: u64) -> Result<(), Errno> {
+    let (parent, is_dir) = fd::open_path(path)?;
+    let rule = PathBeneathAttr {
+        allowed_access: match is_dir {
+            true => access,
+            false => access & ACCESS_FS_FILE,
+        },
+        parent_fd: parent.raw(),
+    };
+    add_rule(ruleset, LANDLOCK_RULE_PATH_BENEATH, &rule)
+}
+
+fn add_rule<T>(ruleset: &Fd, rule_type: u32, rule: &T) -> Result<(), Errno> {
+    cvt(unsafe {
+        syscalls::landlock_add_rule(
+            ruleset.raw(),
+            rule_type,
+            (rule as *const T).cast_mut().cast(),
+            0,
+        )
+    })?;
+    Ok(())
+}
+
+/// Enforce the ruleset on the calling thread, setting `PR_SET_NO_NEW_PRIVS` first as required
+/// without `CAP_SYS_ADMIN`. Only issues system calls.
+pub(crate) fn restrict(ruleset: i32) -> Result<(), Errno> {
+    cvt(unsafe { syscalls::prctl(PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) })?;
+    cvt(unsafe { syscalls::landlock_restrict_self(ruleset, 0) })?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_handled_access_fs() {
+        assert_eq!(handled_access_fs(1), 0x1fff);
+        assert_eq!(handled_access_fs(2), 0x3fff);
+        assert_eq!(handled_access_fs(3), 0x7fff);
+        assert_eq!(handled_access_fs(4), 0x7fff);
+        assert_eq!(handled_access_fs(5), 0xffff);
+        assert_eq!(handled_access_fs(7), 0xffff);
+        // the read-only rights are all file rights, granted on files as well
+        assert_eq!(
+            ACCESS_FS_READ & ACCESS_FS_FILE,
+            ACCESS_FS_EXECUTE | ACCESS_FS_READ_FILE
+        );
+        assert_eq!(size_of::<PathBeneathAttr>(), 12);
+    }
+
+    #[test]
+    fn test_create_ruleset() {
+        let ruleset = Ruleset::new()
+            .read_only(c"/usr")
+            .read_only(c"/bin/sh")
+            .read_write(c"/tmp")
+            .allow_bind(8080)
+            .allow_connect(443);
+        let strict = ruleset.clone().compatibility(Compatibility::Strict);
+        match abi_version() {
+            Ok(abi) => {
+                assert!(abi >= 1);
+                assert!(ruleset.create().unwrap().is_some());
+                match abi {
+                    1..=3 => assert_eq!(strict.create().err(), Some(Errno::EOPNOTSUPP)),
+                    _ => assert!(strict.create().unwrap().is_some()),
+                }
+                let missing = Ruleset::new().read_only(c"/memfd-runner-missing");
+                assert_eq!(missing.create().err(), Some(Errno::ENOENT));
+            }
+            Err(errno) => {
+                assert!(ruleset.create().unwrap().is_none());
+                assert_eq!(strict.create().err(), Some(errno));
+            }
+        }
+    }
+}
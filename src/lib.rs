@@ -4,14 +4,25 @@
 //!
 //! This library provides a simple interface to load and execute ELF binaries directly from memory
 //! without writing them to disk. It uses Linux's `memfd_create` system call to create an anonymous
-//! file in memory, writes the ELF data to it, then executes it via the `/proc/self/fd/` interface.
+//! file in memory, writes the ELF data to it, then executes it with `execveat` (or via the
+//! `/proc/self/fd/` interface on older kernels).
 //!
 //! ## Features
 //!
-//! - **Minimal** - <400 lines of code, 1 dependency ([syscaller](https://github.com/mathyslv/syscaller))
+//! - **Lightweight** - 1 dependency ([syscaller](https://github.com/mathyslv/syscaller))
 //! - **Two execution modes** - fork child process or replace current process
 //! - **`no_std`** - works in embedded and kernel environments  
 //!
+//! ## Cargo features
+//!
+//! - **`alloc`** - builds argv and envp in a heap buffer sized to the actual input, lifting the
+//!   argument and environment size limits of the default zero-allocation stack storage, and
+//!   enables capturing the payload output with [`output`]
+//! - **`std`** - implies `alloc`, adds conversions into `std::process` types and the std I/O
+//!   traits on the pipe handles
+//! - **`users`** - `no_std` parser for `/etc/passwd` and `/etc/group` in [`users`], to run
+//!   payloads as a named user
+//!
 //! ## Platform Support
 //!
 //! - **Linux only** - requires `memfd_create` system call (Linux 3.17+)
@@ -25,8 +36,20 @@
 //! use memfd_runner::run;
 //!
 //! let elf_bytes = std::fs::read("/usr/bin/echo").unwrap();
-//! let exit_code = run(&elf_bytes).unwrap();
-//! println!("Process exited with code: {}", exit_code);
+//! let status = run(&elf_bytes).unwrap();
+//! println!("Process exited with: {}", status);
+//! ```
+//!
+//! ### Non-blocking execution
+//!
+//! ```rust,no_run
+//! use memfd_runner::RunOptions;
+//!
+//! let elf_bytes = std::fs::read("/usr/bin/sleep").unwrap();
+//! let mut child = RunOptions::new().with_args(&["1"]).spawn(&elf_bytes).unwrap();
+//! while child.try_wait().unwrap().is_none() {
+//!     // do other work while the payload is running
+//! }
 //! ```
 //!
 //! ### Replace current process
@@ -48,7 +71,7 @@
 //! let options = RunOptions::new()
 //!     .with_args(&["Hello", "World!"])  // Just the arguments, not the program name
 //!     .with_env(&["PATH=/usr/bin", "HOME=/tmp"]);
-//! let exit_code = run_with_options(&elf_bytes, options).unwrap();
+//! let status = run_with_options(&elf_bytes, options).unwrap();
 //! // Executes: /proc/self/fd/X "Hello" "World!"
 //! ```
 //!
@@ -61,7 +84,7 @@
 //! let options = RunOptions::new()
 //!     .with_argv0("my-echo")  // Custom program name
 //!     .with_args(&["Hello", "World!"]);
-//! let exit_code = run_with_options(&elf_bytes, options).unwrap();
+//! let status = run_with_options(&elf_bytes, options).unwrap();
 //! // The program sees argv[0] as "my-echo" instead of "/proc/self/fd/X"
 //! ```
 //!
@@ -72,7 +95,7 @@
 //!
 //! let invalid_elf = b"not an elf file";
 //! match run(invalid_elf) {
-//!     Ok(exit_code) => println!("Success: {}", exit_code),
+//!     Ok(status) => println!("Success: {}", status),
 //!     Err(RunError::InvalidElfFormat) => println!("Invalid ELF format"),
 //!     Err(RunError::FdCreationFailed(errno)) => println!("Failed to create memfd: {}", errno),
 //!     Err(e) => println!("Other error: {:?}", e),
@@ -82,16 +105,78 @@
 //! ## Limitations
 //!
 //! - Linux-specific
-//! - Maximum 32 command line arguments (256 chars each)
-//! - Maximum 64 environment variables (256 chars each)
+//! - Maximum 32 command line arguments (256 chars each) without the `alloc` feature
+//! - Maximum 64 environment variables (256 chars each) without the `alloc` feature
 //! - Very basic ELF validation only (magic bytes, minimum size)
 //! - No support for complex ELF features or dynamic linking validation
 
 #![no_std]
 
+#[cfg(feature = "alloc")]
+extern crate alloc;
+#[cfg(feature = "std")]
+extern crate std;
+
+mod capabilities;
+mod cgroup;
+mod child;
+mod credentials;
+mod env;
+mod errno;
+mod fd;
+mod file_actions;
+pub mod landlock;
+mod list;
+mod namespaces;
+#[cfg(feature = "alloc")]
+mod output;
+mod pidfd;
+mod rlimit;
+mod rusage;
+pub mod seccomp;
+mod status;
+mod stdio;
 mod syscalls;
+mod time;
+#[cfg(feature = "users")]
+pub mod users;
+mod vfork;
+
+pub use capabilities::{Capabilities, SecureBits};
+pub use cgroup::{Cgroup, CgroupStats};
+pub use child::{Child, KillStep};
+pub use errno::Errno;
+pub use file_actions::FileAction;
+pub use namespaces::{IdMap, Namespaces};
+#[cfg(feature = "alloc")]
+pub use output::Output;
+pub use pidfd::PidFd;
+pub use rlimit::{RLIM_INFINITY, Resource};
+pub use rusage::{ResourceUsage, RunReport};
+pub use status::ExitStatus;
+pub use stdio::{ChildStderr, ChildStdin, ChildStdout, Stdio};
+
+#[cfg(feature = "alloc")]
+use alloc::vec::Vec;
+use capabilities::CapabilitySetup;
+use cgroup::CgroupDir;
+use core::cell::Cell;
+use core::convert::Infallible;
+use core::ffi::CStr;
+use core::fmt;
+use core::time::Duration;
+use credentials::Credentials;
+use env::EnvOp;
+use fd::{Fd, MsgSlot};
+use landlock::Ruleset;
+use list::List;
+use namespaces::{IdMaps, NamespaceSetup};
+use seccomp::{Notifier, Program, RunnerCalls, SeccompFilter};
+use stdio::StdioSetup;
+use syscalls::cvt;
 
 const MFD_CLOEXEC: u8 = 0x1;
+const AT_EMPTY_PATH: i32 = 0x1000;
 
 #[used]
 pub static EMPTY_STRING: [u8; 8] = [0; 8];
@@ -100,30 +185,141 @@ pub static EMPTY_STRING: [u8; 8] = [0; 8];
 #[derive(Debug)]
 pub enum RunError {
     /// Failed to create memory file descriptor via memfd_create()
-    FdCreationFailed(i32),
+    FdCreationFailed(Errno),
+    /// write() system call failed while copying the ELF bytes to memory file
+    WriteError(Errno),
     /// Failed to write all ELF bytes to memory file
     BytesNotWritten(usize, usize),
     /// execve() system call failed
-    ExecError(i32),
-    /// fork() system call failed
-    ForkError(i32),
+    ExecError(Errno),
+    /// clone3() or fork() system call failed
+    ForkError(Errno),
     /// wait4() system call failed while waiting for child process
-    WaitError(i32),
+    WaitError(Errno),
+    /// kill() system call failed while signaling child process
+    KillError(Errno),
+    /// pipe2() system call failed while setting up the child process
+    PipeError(Errno),
+    /// Failed to read the parent environment from /proc/self/environ
+    EnvReadError(Errno),
+    /// Failed to set up the standard streams of the payload (opening /dev/null, pipe2() or dup2())
+    StdioError(Errno),
+    /// Failed to open or change to the [working directory](RunOptions::with_current_dir) of the
+    /// payload
+    CurrentDirError(Errno),
+    /// Failed to create the [namespaces](RunOptions::with_namespaces) of the payload, or to write
+    /// the id maps of its user namespace
+    NamespaceError(Errno),
+    /// Failed to set a [resource limit](RunOptions::with_rlimit) of the payload
+    RlimitError(Errno),
+    /// Failed to switch to the [user](RunOptions::with_uid) or [groups](RunOptions::with_gid) of
+    /// the payload, or the switch did not take full effect
+    CredentialsError(Errno),
+    /// Failed to change the [capabilities](RunOptions::with_ambient_capabilities),
+    /// [securebits](RunOptions::with_securebits) or
+    /// [`no_new_privs`](RunOptions::with_no_new_privs) flag of the payload
+    CapabilitiesError(Errno),
+    /// A [file action](RunOptions::with_file_actions) failed, or the other descriptors could not be
+    /// [closed](RunOptions::with_close_other_fds)
+    FileActionError(Errno),
+    /// A [pre-exec hook](RunOptions::pre_exec) returned an error
+    PreExecError(Errno),
+    /// Failed to compile or install the [seccomp filter](RunOptions::with_seccomp) of the payload
+    SeccompError(Errno),
+    /// Failed to create or enforce the [Landlock ruleset](RunOptions::with_landlock) of the
+    /// payload
+    LandlockError(Errno),
+    /// Failed to create the [cgroup](RunOptions::with_cgroup) of the payload, to set its limits,
+    /// or to move the current process to it in replace mode
+    CgroupError(Errno),
+    /// poll() or read() system call failed while capturing the payload output
+    OutputError(Errno),
+    /// The payload outlived the timeout and was terminated, its final exit status is attached
+    TimedOut(ExitStatus),
     /// ELF validation failed - invalid magic bytes or insufficient size
     InvalidElfFormat,
-    /// Too many command line arguments provided (limit: 32)
+    /// Too many command line arguments provided (limit: 32, lifted by the `alloc` feature)
     TooManyArgs,
-    /// Too many environment variables provided (limit: 64)
+    /// Too many environment variables provided (limit: 64, lifted by the `alloc` feature)
     TooManyEnvVars,
-    /// Command line argument too long (limit: 256 characters)
+    /// Command line argument too long (limit: 256 characters, lifted by the `alloc` feature)
     ArgTooLong,
-    /// Environment variable too long (limit: 256 characters)
+    /// Environment variable too long (limit: 256 characters, lifted by the `alloc` feature)
     EnvVarTooLong,
+    /// Too many pre-exec hooks registered (limit: 8, lifted by the `alloc` feature)
+    TooManyPreExecHooks,
+}
+
+impl fmt::Display for RunError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RunError::FdCreationFailed(errno) => write!(f, "memfd_create() failed: {errno}"),
+            RunError::WriteError(errno) => write!(f, "failed to write ELF to memfd: {errno}"),
+            RunError::BytesNotWritten(written, expected) => {
+                write!(f, "only {written} of {expected} ELF bytes written to memfd")
+            }
+            RunError::ExecError(errno) => write!(f, "execve() failed: {errno}"),
+            RunError::ForkError(errno) => write!(f, "fork() failed: {errno}"),
+            RunError::WaitError(errno) => write!(f, "wait4() failed: {errno}"),
+            RunError::KillError(errno) => write!(f, "kill() failed: {errno}"),
+            RunError::PipeError(errno) => write!(f, "pipe2() failed: {errno}"),
+            RunError::EnvReadError(errno) => {
+                write!(f, "failed to read the parent environment: {errno}")
+            }
+            RunError::StdioError(errno) => {
+                write!(f, "failed to set up the standard streams: {errno}")
+            }
+            RunError::CurrentDirError(errno) => {
+                write!(f, "failed to change the working directory: {errno}")
+            }
+            RunError::NamespaceError(errno) => {
+                write!(f, "failed to set up the namespaces: {errno}")
+            }
+            RunError::RlimitError(errno) => write!(f, "failed to set a resource limit: {errno}"),
+            RunError::CredentialsError(errno) => {
+                write!(f, "failed to change the user or groups: {errno}")
+            }
+            RunError::CapabilitiesError(errno) => {
+                write!(f, "failed to change the capabilities: {errno}")
+            }
+            RunError::FileActionError(errno) => write!(f, "file action failed: {errno}"),
+            RunError::PreExecError(errno) => write!(f, "pre-exec hook failed: {errno}"),
+            RunError::SeccompError(errno) => {
+                write!(f, "failed to install the seccomp filter: {errno}")
+            }
+            RunError::LandlockError(errno) => {
+                write!(f, "failed to enforce the Landlock ruleset: {errno}")
+            }
+            RunError::CgroupError(errno) => write!(f, "failed to set up the cgroup: {errno}"),
+            RunError::OutputError(errno) => write!(f, "failed to capture the output: {errno}"),
+            RunError::TimedOut(status) => write!(f, "timed out and terminated ({status})"),
+            RunError::InvalidElfFormat => f.write_str("invalid ELF format"),
+            RunError::TooManyArgs => write!(f, "too many arguments (limit: {MAX_ARGS})"),
+            RunError::TooManyEnvVars => {
+                write!(f, "too many environment variables (limit: {MAX_ENV})")
+            }
+            RunError::ArgTooLong => write!(f, "argument too long (limit: {MAX_STRING_LEN})"),
+            RunError::EnvVarTooLong => {
+                write!(f, "environment variable too long (limit: {MAX_STRING_LEN})")
+            }
+            RunError::TooManyPreExecHooks => {
+                write!(f, "too many pre-exec hooks (limit: {MAX_PRE_EXEC})")
+            }
+        }
+    }
 }
 
+impl core::error::Error for RunError {}
+
 const MAX_ARGS: usize = 32;
 const MAX_ENV: usize = 64;
 const MAX_STRING_LEN: usize = 256;
+const MAX_PRE_EXEC: usize = 8;
+/// Maximum number of inherited environment variables, in addition to the `MAX_ENV` provided ones
+#[cfg(not(feature = "alloc"))]
+const MAX_INHERITED_ENV: usize = 256;
+#[cfg(not(feature = "alloc"))]
+const MAX_ENVP: usize = MAX_ENV + MAX_INHERITED_ENV;
 
 /// Options which can be used to customize arguments, environment and how the ELF is executed.
 #[derive(Clone, Default)]
@@ -131,7 +327,61 @@ pub struct RunOptions<'a> {
     replace: bool,
     args: Option<&'a [&'a str]>,
     env: Option<&'a [&'a str]>,
+    env_clear: bool,
+    env_ops: List<EnvOp<'a>, MAX_ENV>,
     argv0: Option<&'a str>,
+    backend: ExecBackend,
+    stdio: [Stdio; 3],
+    stdin_bytes: Option<&'a [u8]>,
+    timeout: Option<Duration>,
+    kill_sequence: &'a [KillStep],
+    spawn_method: SpawnMethod,
+    pre_exec: List<PreExecHook<'a>, MAX_PRE_EXEC>,
+    file_actions: &'a [FileAction<'a>],
+    close_other_fds: bool,
+    current_dir: Option<&'a CStr>,
+    umask: Option<u32>,
+    credentials: Credentials<'a>,
+    capabilities: CapabilitySetup,
+    rlimits: rlimit::Limits,
+    namespaces: Namespaces,
+    uid_map: &'a [IdMap],
+    gid_map: &'a [IdMap],
+    seccomp: Option<&'a SeccompFilter<'a>>,
+    landlock: Option<&'a Ruleset<'a>>,
+    cgroup: Option<Cgroup<'a>>,
+}
+
+/// A closure run in the child between `fork()` and `execve()`, see [`RunOptions::pre_exec`].
+pub type PreExecHook<'a> = &'a dyn Fn() -> Result<(), Errno>;
+
+/// How the in-memory file descriptor is handed to the kernel for execution.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ExecBackend {
+    /// Use `execveat` when the kernel supports it, fall back to `/proc/self/fd/N` otherwise.
+    #[default]
+    Auto,
+    /// Always use `execveat(fd, "", argv, envp, AT_EMPTY_PATH)` (Linux 3.19+).
+    /// Works in chroots, minimal containers and initramfs where /proc is not mounted.
+    Execveat,
+    /// Always use `execve("/proc/self/fd/N", argv, envp)`, which requires /proc to be mounted.
+    ProcSelfFd,
+}
+
+/// How the child process running the payload is created.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SpawnMethod {
+    /// `clone3`/`fork`: the child gets a copy-on-write copy of the parent address space.
+    #[default]
+    Fork,
+    /// `clone(CLONE_VM | CLONE_VFORK)`, like `posix_spawn`: the child shares the parent memory
+    /// and runs on a small dedicated stack until `execve`, while the calling thread is suspended.
+    ///
+    /// No page tables are copied, so spawning stays fast in parents with a large resident memory.
+    /// Falls back to `Fork` when [pre-exec hooks](RunOptions::pre_exec), new
+    /// [namespaces](RunOptions::with_namespaces) or a [cgroup](RunOptions::with_cgroup) are
+    /// requested.
+    Vfork,
 }
 
 impl<'a> RunOptions<'a> {
@@ -160,7 +410,7 @@ impl<'a> RunOptions<'a> {
     ///
     /// let elf_bytes = std::fs::read("/usr/bin/echo").unwrap();
     /// let options = RunOptions::new().with_args(&["Hello", "World!"]);
-    /// let exit_code = run_with_options(&elf_bytes, options).unwrap();
+    /// let status = run_with_options(&elf_bytes, options).unwrap();
     /// // This executes: /proc/self/fd/X "Hello" "World!"
     /// ```
     pub fn with_args(mut self, args: &'a [&'a str]) -> Self {
@@ -171,19 +421,60 @@ impl<'a> RunOptions<'a> {
     /// Set environment variables for the executed binary.
     /// Environment variables should be in "KEY=value" format.
     ///
+    /// This list replaces the inherited environment, and can still be adjusted with
+    /// [`env`](Self::env) and [`env_remove`](Self::env_remove).
+    ///
     /// # Example
     /// ```rust,no_run
     /// use memfd_runner::{run_with_options, RunOptions};
     ///
     /// let elf_bytes = std::fs::read("/usr/bin/env").unwrap();
     /// let options = RunOptions::new().with_env(&["PATH=/usr/bin", "HOME=/tmp"]);
-    /// let exit_code = run_with_options(&elf_bytes, options).unwrap();
+    /// let status = run_with_options(&elf_bytes, options).unwrap();
     /// ```
     pub fn with_env(mut self, env: &'a [&'a str]) -> Self {
         self.env = Some(env);
         self
     }
 
+    /// Set or override a single environment variable for the executed binary.
+    ///
+    /// By default the payload inherits the environment of the current process (read from
+    /// `environ` with the `std` feature, from `/proc/self/environ` otherwise). Overridden
+    /// variables keep their position, new ones are appended.
+    ///
+    /// # Example
+    /// ```rust,no_run
+    /// use memfd_runner::{run_with_options, RunOptions};
+    ///
+    /// let elf_bytes = std::fs::read("/usr/bin/env").unwrap();
+    /// let options = RunOptions::new()
+    ///     .env("LANG", "C")
+    ///     .env_remove("HISTFILE");
+    /// let status = run_with_options(&elf_bytes, options).unwrap();
+    /// ```
+    pub fn env(mut self, key: &'a str, value: &'a str) -> Self {
+        self.env_ops.push(EnvOp::Set(key, value));
+        self
+    }
+
+    /// Remove an environment variable from the environment of the executed binary.
+    pub fn env_remove(mut self, key: &'a str) -> Self {
+        self.env_ops.push(EnvOp::Remove(key));
+        self
+    }
+
+    /// Start the executed binary with an empty environment instead of inheriting the current one.
+    ///
+    /// This also discards the variables configured so far, including those given to
+    /// [`with_env`](Self::with_env).
+    pub fn env_clear(mut self) -> Self {
+        self.env = None;
+        self.env_clear = true;
+        self.env_ops.clear();
+        self
+    }
+
     /// Set a custom argv[0] for the executed binary.
     ///
     /// By default, argv[0] is set to the memfd path (`/proc/self/fd/N`). This method
@@ -197,20 +488,608 @@ impl<'a> RunOptions<'a> {
     /// let options = RunOptions::new()
     ///     .with_argv0("my-custom-program")
     ///     .with_args(&["Hello", "World!"]);
-    /// let exit_code = run_with_options(&elf_bytes, options).unwrap();
+    /// let status = run_with_options(&elf_bytes, options).unwrap();
     /// // The program sees argv[0] as "my-custom-program"
     /// ```
     pub fn with_argv0(mut self, argv0: &'a str) -> Self {
         self.argv0 = Some(argv0);
         self
     }
+
+    /// Select how the memory file descriptor is executed.
+    ///
+    /// Defaults to [`ExecBackend::Auto`], which prefers `execveat` and falls back to the
+    /// `/proc/self/fd/N` path on kernels without it.
+    ///
+    /// # Example
+    /// ```rust,no_run
+    /// use memfd_runner::{run_with_options, ExecBackend, RunOptions};
+    ///
+    /// let elf_bytes = std::fs::read("/usr/bin/uname").unwrap();
+    /// // inside a chroot without /proc
+    /// let options = RunOptions::new().with_exec_backend(ExecBackend::Execveat);
+    /// let status = run_with_options(&elf_bytes, options).unwrap();
+    /// ```
+    pub fn with_exec_backend(mut self, backend: ExecBackend) -> Self {
+        self.backend = backend;
+        self
+    }
+
+    /// Configure the stdin of the executed binary. Defaults to [`Stdio::Inherit`].
+    ///
+    /// With [`Stdio::Piped`], the writing end is available as [`Child::stdin`].
+    pub fn with_stdin(mut self, stdin: Stdio) -> Self {
+        self.stdio[0] = stdin;
+        self.stdin_bytes = None;
+        self
+    }
+
+    /// Feed `bytes` to the stdin of the executed binary, replacing any [`with_stdin`](Self::with_stdin)
+    /// configuration.
+    ///
+    /// The bytes are copied into a sealed memory file which becomes the payload's stdin, so the
+    /// payload can seek in it and no pipe has to be serviced while waiting. This works with
+    /// [`run_with_options`] and in replace mode.
+    ///
+    /// # Example
+    /// ```rust,no_run
+    /// use memfd_runner::{run_with_options, RunOptions};
+    ///
+    /// let elf_bytes = std::fs::read("/usr/bin/wc").unwrap();
+    /// let options = RunOptions::new()
+    ///     .with_args(&["-l"])
+    ///     .with_stdin_bytes(b"one\ntwo\n");
+    /// let status = run_with_options(&elf_bytes, options).unwrap(); // prints 2
+    /// ```
+    pub fn with_stdin_bytes(mut self, bytes: &'a [u8]) -> Self {
+        self.stdin_bytes = Some(bytes);
+        self
+    }
+
+    /// Configure the stdout of the executed binary. Defaults to [`Stdio::Inherit`].
+    ///
+    /// With [`Stdio::Piped`], the reading end is available as [`Child::stdout`].
+    ///
+    /// # Example
+    /// ```rust,no_run
+    /// use memfd_runner::{RunOptions, Stdio};
+    ///
+    /// let elf_bytes = std::fs::read("/usr/bin/uname").unwrap();
+    /// let mut child = RunOptions::new()
+    ///     .with_stdout(Stdio::Piped)
+    ///     .with_stderr(Stdio::Null)
+    ///     .spawn(&elf_bytes)
+    ///     .unwrap();
+    /// let mut buf = [0u8; 256];
+    /// let len = child.stdout.as_mut().unwrap().read(&mut buf).unwrap();
+    /// child.wait().unwrap();
+    /// ```
+    pub fn with_stdout(mut self, stdout: Stdio) -> Self {
+        self.stdio[1] = stdout;
+        self
+    }
+
+    /// Configure the stderr of the executed binary. Defaults to [`Stdio::Inherit`].
+    ///
+    /// With [`Stdio::Piped`], the reading end is available as [`Child::stderr`].
+    /// `Stdio::Fd(1)` merges stderr into the configured stdout, like `2>&1`.
+    pub fn with_stderr(mut self, stderr: Stdio) -> Self {
+        self.stdio[2] = stderr;
+        self
+    }
+
+    /// Bound the wall-clock run time of the executed binary when it is waited for by
+    /// [`run_with_options`].
+    ///
+    /// Once `timeout` elapses, the payload is shut down with the
+    /// [kill sequence](Self::with_kill_sequence) and [`RunError::TimedOut`] is returned with its
+    /// final exit status. Ignored in replace mode.
+    ///
+    /// # Example
+    /// ```rust,no_run
+    /// use core::time::Duration;
+    /// use memfd_runner::{run_with_options, KillStep, RunError, RunOptions};
+    ///
+    /// let elf_bytes = std::fs::read("/usr/bin/sleep").unwrap();
+    /// let sequence = [KillStep::new(15, Duration::from_secs(2))]; // SIGTERM, 2s grace, SIGKILL
+    /// let options = RunOptions::new()
+    ///     .with_args(&["60"])
+    ///     .with_timeout(Duration::from_secs(5))
+    ///     .with_kill_sequence(&sequence);
+    /// match run_with_options(&elf_bytes, options) {
+    ///     Err(RunError::TimedOut(status)) => println!("deadline fired, payload {status}"),
+    ///     result => println!("finished in time: {result:?}"),
+    /// }
+    /// ```
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Set the signals sent to a payload which outlived its [timeout](Self::with_timeout).
+    ///
+    /// Each [`KillStep`] sends a signal, then gives the payload a grace period to exit. A payload
+    /// still running after the last step is killed with `SIGKILL`, which is also what happens
+    /// right away with the default empty sequence.
+    pub fn with_kill_sequence(mut self, sequence: &'a [KillStep]) -> Self {
+        self.kill_sequence = sequence;
+        self
+    }
+
+    /// Select how the child process is created. Defaults to [`SpawnMethod::Fork`].
+    ///
+    /// [`SpawnMethod::Vfork`] avoids copying the page tables of the parent, which takes
+    /// milliseconds in multi-gigabyte processes. Ignored in replace mode.
+    ///
+    /// # Example
+    /// ```rust,no_run
+    /// use memfd_runner::{run_with_options, RunOptions, SpawnMethod};
+    ///
+    /// let elf_bytes = std::fs::read("/usr/bin/true").unwrap();
+    /// let options = RunOptions::new().with_spawn_method(SpawnMethod::Vfork);
+    /// let status = run_with_options(&elf_bytes, options).unwrap();
+    /// ```
+    pub fn with_spawn_method(mut self, method: SpawnMethod) -> Self {
+        self.spawn_method = method;
+        self
+    }
+
+    /// Set up the descriptor table of the payload with `posix_spawn`-style file actions.
+    ///
+    /// Actions are applied in order in the child, after the standard streams are set up and
+    /// the other descriptors are [closed](Self::with_close_other_fds). A failing action is
+    /// reported as [`RunError::FileActionError`].
+    ///
+    /// # Example
+    /// ```rust,no_run
+    /// use memfd_runner::{run_with_options, FileAction, RunOptions};
+    /// use std::os::fd::AsRawFd;
+    ///
+    /// let elf_bytes = std::fs::read("/usr/bin/sh").unwrap();
+    /// let socket = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    /// let actions = [
+    ///     FileAction::Dup2 { src: socket.as_raw_fd(), dst: 3 },
+    ///     FileAction::open_write(4, c"/tmp/payload.log"),
+    ///     FileAction::Close(0),
+    /// ];
+    /// let options = RunOptions::new()
+    ///     .with_args(&["-c", "ls -l /proc/self/fd >&4"])
+    ///     .with_file_actions(&actions);
+    /// let status = run_with_options(&elf_bytes, options).unwrap();
+    /// ```
+    pub fn with_file_actions(mut self, actions: &'a [FileAction<'a>]) -> Self {
+        self.file_actions = actions;
+        self
+    }
+
+    /// Only let the payload inherit its standard streams and the descriptors installed by
+    /// [file actions](Self::with_file_actions), instead of every descriptor of the current
+    /// process not marked close-on-exec.
+    ///
+    /// The descriptors above stderr are marked close-on-exec with `close_range()` (Linux 5.11+),
+    /// or one by one from the `/proc/self/fd` listing on older kernels.
+    pub fn with_close_other_fds(mut self, close: bool) -> Self {
+        self.close_other_fds = close;
+        self
+    }
+
+    /// Set the working directory of the payload.
+    ///
+    /// The directory is opened before forking, so a missing one is reported as
+    /// [`RunError::CurrentDirError`] without starting a child, which then changes to it with
+    /// `fchdir()`. Relative paths are resolved from the working directory of the current process.
+    ///
+    /// # Example
+    /// ```rust,no_run
+    /// use memfd_runner::{run_with_options, RunOptions};
+    ///
+    /// let elf_bytes = std::fs::read("/usr/bin/ls").unwrap();
+    /// let options = RunOptions::new().with_current_dir(c"/var/log");
+    /// let status = run_with_options(&elf_bytes, options).unwrap();
+    /// ```
+    pub fn with_current_dir(mut self, path: &'a CStr) -> Self {
+        self.current_dir = Some(path);
+        self
+    }
+
+    /// Set the file mode creation mask of the payload, like `umask(027)` in a shell.
+    ///
+    /// The mask is applied in the child before the [file actions](Self::with_file_actions), so
+    /// the files they create get the restricted permissions as well.
+    pub fn with_umask(mut self, mask: u32) -> Self {
+        self.umask = Some(mask);
+        self
+    }
+
+    /// Run the payload in new `namespaces`, like `unshare(1)` or bubblewrap.
+    ///
+    /// In fork mode, the child is created in the namespaces by `clone3()`: with
+    /// [`Namespaces::PID`], the payload is pid 1 in its namespace while [`Child::id`] still
+    /// reports its pid in the current one. In replace mode, the current process joins them with
+    /// `unshare()`, and only its children enter a new pid namespace. A [`SpawnMethod::Vfork`]
+    /// spawn falls back to a regular fork.
+    ///
+    /// With [`Namespaces::USER`], the [uid map](Self::with_uid_map) and
+    /// [gid map](Self::with_gid_map) are written before the child goes on with its setup, so
+    /// the [user](Self::with_uid) to switch to is an id inside the namespace. With
+    /// [`Namespaces::MOUNT`], every mount is made private to the new namespace. Failures are
+    /// reported as [`RunError::NamespaceError`].
+    ///
+    /// # Example
+    /// ```rust,no_run
+    /// use memfd_runner::{run_with_options, Namespaces, RunOptions};
+    ///
+    /// let elf_bytes = std::fs::read("/usr/bin/sh").unwrap();
+    /// let options = RunOptions::new()
+    ///     .with_args(&["-c", "echo $$"]) // prints 1
+    ///     .with_namespaces(Namespaces::USER | Namespaces::PID | Namespaces::NET);
+    /// let status = run_with_options(&elf_bytes, options).unwrap();
+    /// ```
+    pub fn with_namespaces(mut self, namespaces: Namespaces) -> Self {
+        self.namespaces = namespaces;
+        self
+    }
+
+    /// Set the uid map of the new [user namespace](Namespaces::USER).
+    ///
+    /// By default the effective uid of the current process is mapped to itself. Without
+    /// `CAP_SETUID`, only that uid can be mapped, to any id inside.
+    ///
+    /// # Example
+    /// ```rust,no_run
+    /// use memfd_runner::{IdMap, Namespaces, RunOptions};
+    ///
+    /// // root inside the namespace, the current user outside of it
+    /// let uid_map = [IdMap::new(0, 1000, 1)];
+    /// let gid_map = [IdMap::new(0, 1000, 1)];
+    /// let options = RunOptions::new()
+    ///     .with_namespaces(Namespaces::USER)
+    ///     .with_uid_map(&uid_map)
+    ///     .with_gid_map(&gid_map);
+    /// ```
+    pub fn with_uid_map(mut self, map: &'a [IdMap]) -> Self {
+        self.uid_map = map;
+        self
+    }
+
+    /// Set the gid map of the new [user namespace](Namespaces::USER), see
+    /// [`with_uid_map`](Self::with_uid_map).
+    ///
+    /// Without `CAP_SETGID`, `setgroups()` is denied in the namespace to be allowed to write it.
+    pub fn with_gid_map(mut self, map: &'a [IdMap]) -> Self {
+        self.gid_map = map;
+        self
+    }
+
+    /// Filter the system calls of the payload with seccomp, see the [`seccomp`] module.
+    ///
+    /// The filter is compiled before forking, then installed in the child after the
+    /// [pre-exec hooks](Self::pre_exec), right before `execve`. `PR_SET_NO_NEW_PRIVS` is set
+    /// first, so set-user-ID binaries run by the payload do not gain privileges. The `execve` of
+    /// the payload itself is always let through, even by a filter denying it. Failures are
+    /// reported as [`RunError::SeccompError`].
+    ///
+    /// In replace mode, the filter stays installed on the current process if `execve` fails.
+    ///
+    /// # Example
+    /// ```rust,no_run
+    /// use memfd_runner::seccomp::{profile, Action, SeccompFilter};
+    /// use memfd_runner::{run_with_options, RunOptions};
+    ///
+    /// let elf_bytes = std::fs::read("/usr/bin/curl").unwrap();
+    /// let filter = SeccompFilter::new(Action::Allow)
+    ///     .rules(profile::NO_NETWORK)
+    ///     .rules(profile::NO_EXEC);
+    /// let options = RunOptions::new()
+    ///     .with_args(&["https://example.com"]) // fails to create its socket
+    ///     .with_seccomp(&filter);
+    /// let status = run_with_options(&elf_bytes, options).unwrap();
+    /// ```
+    pub fn with_seccomp(mut self, filter: &'a SeccompFilter<'a>) -> Self {
+        self.seccomp = Some(filter);
+        self
+    }
+
+    /// Confine the files and TCP ports the payload can access with Landlock, see the
+    /// [`landlock`] module.
+    ///
+    /// The ruleset is created before forking, so that a missing path is reported right away,
+    /// then enforced in the child after the [file actions](Self::with_file_actions) and
+    /// [pre-exec hooks](Self::pre_exec), which can still open files outside of it.
+    /// `PR_SET_NO_NEW_PRIVS` is set first, as Landlock requires without `CAP_SYS_ADMIN`. The
+    /// payload needs read access to its dynamic loader and libraries, and failures are reported
+    /// as [`RunError::LandlockError`].
+    ///
+    /// In replace mode, the ruleset stays enforced on the current process if `execve` fails.
+    ///
+    /// # Example
+    /// ```rust,no_run
+    /// use memfd_runner::landlock::Ruleset;
+    /// use memfd_runner::{run_with_options, RunOptions};
+    ///
+    /// let elf_bytes = std::fs::read("/usr/bin/sh").unwrap();
+    /// let ruleset = Ruleset::new()
+    ///     .read_only(c"/usr")
+    ///     .read_only(c"/lib")
+    ///     .read_write(c"/tmp/sandbox");
+    /// let options = RunOptions::new()
+    ///     .with_args(&["-c", "cat /etc/passwd"]) // permission denied
+    ///     .with_landlock(&ruleset);
+    /// let status = run_with_options(&elf_bytes, options).unwrap();
+    /// ```
+    pub fn with_landlock(mut self, ruleset: &'a Ruleset<'a>) -> Self {
+        self.landlock = Some(ruleset);
+        self
+    }
+
+    /// Spawn the payload into a cgroup v2, which accounts and limits it along with every
+    /// process it starts, even those which leave its process tree.
+    ///
+    /// The cgroup is opened or created and its limits are set before forking, then the child is
+    /// created in it by `clone3(CLONE_INTO_CGROUP)` (Linux 5.7+). A [`SpawnMethod::Vfork`]
+    /// spawn falls back to a regular fork. In replace mode, the current process moves itself to
+    /// the cgroup. Its resource usage is read into [`RunReport::cgroup`] once the payload is
+    /// reaped, its processes can be killed with [`Child::kill_tree`], and
+    /// [`run_with_options`] kills them when the payload times out. Failures are reported as
+    /// [`RunError::CgroupError`].
+    ///
+    /// In replace mode, the current process enters the cgroup right before the rest of the
+    /// setup and `execve`, and it is not moved back if one of them fails: it stays in the
+    /// cgroup, and a [created](Cgroup::create) cgroup cannot be removed while it is there.
+    ///
+    /// # Example
+    /// ```rust,no_run
+    /// use core::time::Duration;
+    /// use memfd_runner::{run_with_report, Cgroup, RunOptions};
+    ///
+    /// let elf_bytes = std::fs::read("/usr/bin/make").unwrap();
+    /// let cgroup = Cgroup::create(c"/sys/fs/cgroup/runner", c"build-42")
+    ///     .with_cpu_max(Duration::from_millis(200), Duration::from_millis(100))
+    ///     .with_memory_max(1 << 30)
+    ///     .with_pids_max(256);
+    /// let options = RunOptions::new().with_args(&["-j4"]).with_cgroup(cgroup);
+    /// let report = run_with_report(&elf_bytes, options).unwrap();
+    /// let stats = report.cgroup.unwrap();
+    /// println!("{:?} CPU, peak memory {:?}", stats.cpu_time, stats.memory_peak);
+    /// ```
+    pub fn with_cgroup(mut self, cgroup: Cgroup<'a>) -> Self {
+        self.cgroup = Some(cgroup);
+        self
+    }
+
+    /// Limit a resource of the payload, with `prlimit64()` in the child (or the current process in
+    /// replace mode). Use [`RLIM_INFINITY`] to lift a limit.
+    ///
+    /// Limits are applied before the [user](Self::with_uid) is changed, so a privileged parent
+    /// can raise hard limits. Setting the same resource again replaces the previous values. A
+    /// payload killed for exceeding its CPU or file size limit is reported by
+    /// [`ExitStatus::exceeded_limit`].
+    ///
+    /// # Example
+    /// ```rust,no_run
+    /// use memfd_runner::{run_with_options, Resource, RunOptions};
+    ///
+    /// let elf_bytes = std::fs::read("/usr/bin/yes").unwrap();
+    /// let options = RunOptions::new()
+    ///     .with_rlimit(Resource::Cpu, 1, 2)
+    ///     .with_rlimit(Resource::As, 256 << 20, 256 << 20)
+    ///     .with_rlimit(Resource::Core, 0, 0);
+    /// let status = run_with_options(&elf_bytes, options).unwrap();
+    /// assert_eq!(status.exceeded_limit(), Some(Resource::Cpu));
+    /// ```
+    pub fn with_rlimit(mut self, resource: Resource, soft: u64, hard: u64) -> Self {
+        self.rlimits[resource.index()] = Some(rlimit::Limit::new(soft, hard));
+        self
+    }
+
+    /// Run the payload as the user `uid`, with `setresuid()` in the child.
+    ///
+    /// Unless [`with_groups`](Self::with_groups) is used, the supplementary groups are cleared
+    /// as well. The new ids are read back before `execve`, and a dropped root uid must not be
+    /// recoverable: otherwise the run fails with [`RunError::CredentialsError`]. The
+    /// [file actions](Self::with_file_actions) and [pre-exec hooks](Self::pre_exec) run with the
+    /// new identity, while the [working directory](Self::with_current_dir) is entered before.
+    ///
+    /// # Example
+    /// ```rust,no_run
+    /// use memfd_runner::{run_with_options, RunOptions};
+    ///
+    /// let elf_bytes = std::fs::read("/usr/bin/id").unwrap();
+    /// let options = RunOptions::new().with_uid(65534).with_gid(65534);
+    /// let status = run_with_options(&elf_bytes, options).unwrap(); // uid=65534 gid=65534
+    /// ```
+    pub fn with_uid(mut self, uid: u32) -> Self {
+        self.credentials.uid = Some(uid);
+        self
+    }
+
+    /// Run the payload with `gid` as its group, with `setresgid()` in the child before the uid
+    /// is changed.
+    pub fn with_gid(mut self, gid: u32) -> Self {
+        self.credentials.gid = Some(gid);
+        self
+    }
+
+    /// Set the supplementary groups of the payload, with `setgroups()` in the child. An empty
+    /// slice removes them all.
+    ///
+    /// The groups are read back before `execve`, which limits the list to 1024 ids: a longer
+    /// one fails with [`RunError::CredentialsError`] (`EINVAL`).
+    pub fn with_groups(mut self, groups: &'a [u32]) -> Self {
+        self.credentials.groups = Some(groups);
+        self
+    }
+
+    /// Run the payload as `user`, setting both its [uid](Self::with_uid) and its primary
+    /// [gid](Self::with_gid). See [`users`] to resolve a user name.
+    #[cfg(feature = "users")]
+    pub fn with_user(self, user: &users::User<'_>) -> Self {
+        self.with_uid(user.uid).with_gid(user.gid)
+    }
+
+    /// Keep `capabilities` across the [uid switch](Self::with_uid), and give them to the payload
+    /// as ambient capabilities.
+    ///
+    /// `PR_SET_KEEPCAPS` is set before the switch, then the permitted, effective and
+    /// inheritable sets are reduced to `capabilities`, which are raised in the ambient set:
+    /// the only one preserved by `execve` for a program without file capabilities. The parent
+    /// must hold them, and failures are reported as [`RunError::CapabilitiesError`].
+    ///
+    /// # Example
+    /// ```rust,no_run
+    /// use memfd_runner::{run_with_options, Capabilities, RunOptions};
+    ///
+    /// // a daemon listening on port 80 without running as root
+    /// let elf_bytes = std::fs::read("/usr/sbin/httpd").unwrap();
+    /// let options = RunOptions::new()
+    ///     .with_uid(33)
+    ///     .with_gid(33)
+    ///     .with_ambient_capabilities(Capabilities::NET_BIND_SERVICE)
+    ///     .with_bounding_set(Capabilities::NET_BIND_SERVICE)
+    ///     .with_no_new_privs(true);
+    /// let status = run_with_options(&elf_bytes, options).unwrap();
+    /// ```
+    pub fn with_ambient_capabilities(mut self, capabilities: Capabilities) -> Self {
+        self.capabilities.ambient = Some(capabilities);
+        self
+    }
+
+    /// Drop every capability but `capabilities` from the bounding set of the payload, with
+    /// `PR_CAPBSET_DROP` before the [uid switch](Self::with_uid).
+    ///
+    /// The bounding set limits the capabilities a program can gain, through file capabilities
+    /// or by running as root, and can never be raised again. Dropping requires `CAP_SETPCAP`.
+    pub fn with_bounding_set(mut self, capabilities: Capabilities) -> Self {
+        self.capabilities.bounding = Some(capabilities);
+        self
+    }
+
+    /// Set the securebits of the payload, with `PR_SET_SECUREBITS` before the
+    /// [uid switch](Self::with_uid), which requires `CAP_SETPCAP`.
+    pub fn with_securebits(mut self, securebits: SecureBits) -> Self {
+        self.capabilities.securebits = Some(securebits);
+        self
+    }
+
+    /// Set `PR_SET_NO_NEW_PRIVS` in the child, after the [uid switch](Self::with_uid): the
+    /// payload and its descendants cannot gain privileges through set-user-ID programs or file
+    /// capabilities.
+    ///
+    /// It is always set along with a [seccomp filter](Self::with_seccomp) or a
+    /// [Landlock ruleset](Self::with_landlock).
+    pub fn with_no_new_privs(mut self, no_new_privs: bool) -> Self {
+        self.capabilities.no_new_privs = no_new_privs;
+        self
+    }
+
+    /// Register a closure to run in the child after `fork()` and right before `execve()`, like
+    /// `std::os::unix::process::CommandExt::pre_exec`.
+    ///
+    /// Hooks run in registration order, after the standard streams are set up, and also in
+    /// replace mode. The first error is reported to the parent as [`RunError::PreExecError`] and
+    /// the payload is not executed. At most 8 hooks can be registered without the `alloc` feature.
+    /// Registering a hook makes [`SpawnMethod::Vfork`] fall back to a regular fork.
+    ///
+    /// # Safety
+    ///
+    /// The closure runs in a copy of a possibly multi-threaded process, where other threads may
+    /// have held locks (including the allocator's) when the fork happened. It must only perform
+    /// async-signal-safe operations, such as raw system calls.
+    ///
+    /// # Example
+    /// ```rust,no_run
+    /// use memfd_runner::{run_with_options, Errno, RunOptions};
+    ///
+    /// let elf_bytes = std::fs::read("/usr/bin/uname").unwrap();
+    /// // computed in the parent, the hook itself only reads it
+    /// let allowed = std::env::var_os("ALLOW_UNAME").is_some();
+    /// let check = move || if allowed { Ok(()) } else { Err(Errno::EPERM) };
+    /// let options = unsafe { RunOptions::new().pre_exec(&check) };
+    /// let status = run_with_options(&elf_bytes, options).unwrap();
+    /// ```
+    pub unsafe fn pre_exec(mut self, hook: PreExecHook<'a>) -> Self {
+        self.pre_exec.push(hook);
+        self
+    }
+
+    /// Executes an in-memory ELF binary in a forked child process without waiting for it.
+    ///
+    /// The replace mode toggle is ignored: a child process is always created. The returned
+    /// [`Child`] can be polled with [`Child::try_wait`], reaped with [`Child::wait`] or
+    /// terminated with [`Child::kill`].
+    ///
+    /// # Example
+    /// ```rust,no_run
+    /// use memfd_runner::RunOptions;
+    ///
+    /// let elf_bytes = std::fs::read("/usr/bin/sleep").unwrap();
+    /// let mut child = RunOptions::new().with_args(&["10"]).spawn(&elf_bytes).unwrap();
+    /// println!("Spawned child with pid {}", child.id());
+    /// child.kill().unwrap();
+    /// child.wait().unwrap();
+    /// ```
+    pub fn spawn<B: AsRef<[u8]>>(&self, bytes: B) -> Result<Child, RunError> {
+        let fd = create_fd(self)?;
+        write_bytes(fd, bytes.as_ref())?;
+        let child = spawn(fd, self);
+        // The child owns its own copy of the memfd, the parent does not need it anymore
+        unsafe { syscalls::close(fd as i32) };
+        child
+    }
+
+    /// Replaces the current process with an in-memory ELF binary.
+    ///
+    /// The replace mode toggle is ignored: the current process is always replaced.
+    /// This function only returns if something went wrong, which is reflected by its
+    /// [`Infallible`] success type.
+    ///
+    /// # Example
+    /// ```rust,no_run
+    /// use memfd_runner::RunOptions;
+    ///
+    /// let elf_bytes = std::fs::read("/usr/bin/uname").unwrap();
+    /// let err = RunOptions::new().exec(&elf_bytes).unwrap_err();
+    /// eprintln!("Failed to execute: {:?}", err);
+    /// ```
+    pub fn exec<B: AsRef<[u8]>>(&self, bytes: B) -> Result<Infallible, RunError> {
+        let fd = create_fd(self)?;
+        write_bytes(fd, bytes.as_ref())?;
+        let err = exec(fd, self);
+        unsafe { syscalls::close(fd as i32) };
+        err
+    }
+
+    /// Executes an in-memory ELF binary in a forked child process and collects everything it
+    /// prints.
+    ///
+    /// stdout and stderr are always piped and read concurrently until the payload closes them,
+    /// the other options (including stdin) are used as configured.
+    ///
+    /// # Example
+    /// ```rust,no_run
+    /// use memfd_runner::RunOptions;
+    ///
+    /// let elf_bytes = std::fs::read("/usr/bin/uname").unwrap();
+    /// let output = RunOptions::new().with_args(&["-r"]).output(&elf_bytes).unwrap();
+    /// assert!(output.status.success());
+    /// println!("kernel release: {}", String::from_utf8_lossy(&output.stdout));
+    /// ```
+    #[cfg(feature = "alloc")]
+    pub fn output<B: AsRef<[u8]>>(&self, bytes: B) -> Result<Output, RunError> {
+        let options = self
+            .clone()
+            .with_stdout(Stdio::Piped)
+            .with_stderr(Stdio::Piped);
+        options.spawn(bytes)?.wait_with_output()
+    }
 }
 
 /// Executes an in-memory ELF binary by creating a child process.
 ///
 /// This is the simplest way to execute an ELF binary from memory. It does a very basic ELF header verification,
 /// creates a memory file descriptor, writes the ELF data to it, then forks a child process
-/// and executes the binary via `/proc/self/fd/{fd}`.
+/// and executes the binary with the default [`ExecBackend`]: `execveat` on the descriptor,
+/// or `/proc/self/fd/{fd}` on kernels without it.
 ///
 /// # Arguments
 ///
@@ -218,7 +1097,7 @@ impl<'a> RunOptions<'a> {
 ///
 /// # Returns
 ///
-/// * `Ok(exit_code)` - The exit code of the executed process (0-255)
+/// * `Ok(status)` - The exit status of the executed process
 /// * `Err(RunError)` - Various error conditions during execution
 ///
 /// # Examples
@@ -226,13 +1105,29 @@ impl<'a> RunOptions<'a> {
 /// ```rust,no_run
 /// // Execute /usr/bin/ls from memory
 /// let elf_bytes = std::fs::read("/usr/bin/ls").unwrap();
-/// let exit_code = memfd_runner::run(&elf_bytes).unwrap();
-/// println!("Process exited with code: {}", exit_code);
+/// let status = memfd_runner::run(&elf_bytes).unwrap();
+/// println!("Process exited with code: {:?}", status.code());
 /// ```
-pub fn run<B: AsRef<[u8]>>(bytes: B) -> Result<i32, RunError> {
+pub fn run<B: AsRef<[u8]>>(bytes: B) -> Result<ExitStatus, RunError> {
     run_with_options(bytes, RunOptions::default())
 }
 
+/// Executes an in-memory ELF binary in a child process and collects its stdout and stderr.
+///
+/// See [`RunOptions::output`] to pass arguments or environment variables.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// let elf_bytes = std::fs::read("/usr/bin/uname").unwrap();
+/// let output = memfd_runner::output(&elf_bytes).unwrap();
+/// println!("{}", String::from_utf8_lossy(&output.stdout));
+/// ```
+#[cfg(feature = "alloc")]
+pub fn output<B: AsRef<[u8]>>(bytes: B) -> Result<Output, RunError> {
+    RunOptions::default().output(bytes)
+}
+
 /// Executes an in-memory ELF binary with configurable options.
 ///
 /// This function provides more control over the execution process compared to [`run`].
@@ -246,8 +1141,9 @@ pub fn run<B: AsRef<[u8]>>(bytes: B) -> Result<i32, RunError> {
 ///
 /// # Returns
 ///
-/// * `Ok(exit_code)` - The exit code of the executed process (fork mode only)
-/// * `Err(RunError)` - Various error conditions during execution
+/// * `Ok(status)` - The exit status of the executed process (fork mode only)
+/// * `Err(RunError)` - Various error conditions during execution, including
+///   [`RunError::TimedOut`] when the payload outlived [`RunOptions::with_timeout`]
 /// * **Never returns** in `replace` mode on successful execution
 ///
 /// # Examples
@@ -264,20 +1160,63 @@ pub fn run<B: AsRef<[u8]>>(bytes: B) -> Result<i32, RunError> {
 pub fn run_with_options<B: AsRef<[u8]>>(
     bytes: B,
     options: RunOptions<'_>,
-) -> Result<i32, RunError> {
-    let fd = create_fd()?;
-    let bytes = bytes.as_ref();
-    write_bytes(fd, bytes)?;
-    execute(fd, options)
+) -> Result<ExitStatus, RunError> {
+    run_with_report(bytes, options).map(|report| report.status)
 }
 
-fn create_fd() -> Result<u16, RunError> {
-    // Safety: EMPTY_STRING is a valid null-terminated string
-    let fd = unsafe { syscalls::memfd_create(EMPTY_STRING, MFD_CLOEXEC as u32) };
-    if fd == -1 {
-        return Err(RunError::FdCreationFailed(-1)); // TODO: get actual errno
+/// Executes an in-memory ELF binary like [`run_with_options`], and reports the resources the
+/// payload consumed along with its wall-clock run time.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use memfd_runner::{run_with_report, RunOptions};
+///
+/// let elf_bytes = std::fs::read("/usr/bin/ls").unwrap();
+/// let report = run_with_report(&elf_bytes, RunOptions::new()).unwrap();
+/// println!(
+///     "{} in {:?} ({:?} CPU), peak RSS {} KiB",
+///     report.status,
+///     report.wall_time,
+///     report.rusage.user_time + report.rusage.system_time,
+///     report.rusage.max_rss_kib,
+/// );
+/// ```
+pub fn run_with_report<B: AsRef<[u8]>>(
+    bytes: B,
+    options: RunOptions<'_>,
+) -> Result<RunReport, RunError> {
+    if options.replace {
+        match options.exec(bytes)? {}
     }
-    Ok(fd as _)
+    let mut child = options.spawn(bytes)?;
+    let Some(timeout) = options.timeout else {
+        return child.wait_report();
+    };
+    match child.wait_timeout(timeout)? {
+        Some(_) => child.wait_report(),
+        None => {
+            let status = child.terminate(options.kill_sequence)?;
+            // the processes the payload started may still be running in its cgroup
+            if options.cgroup.is_some() {
+                child.kill_tree()?;
+            }
+            Err(RunError::TimedOut(status))
+        }
+    }
+}
+
+/// Create the memfd, out of the way of the file actions of `options`.
+fn create_fd(options: &RunOptions<'_>) -> Result<u16, RunError> {
+    // Safety: EMPTY_STRING is a valid null-terminated string
+    let fd = cvt(unsafe { syscalls::memfd_create(EMPTY_STRING, MFD_CLOEXEC as u32) })
+        .map_err(RunError::FdCreationFailed)?;
+    let fd = Fd::from_raw(fd)
+        .at_least(file_actions::first_free_fd(options.file_actions))
+        .map_err(RunError::FdCreationFailed)?;
+    u16::try_from(fd.raw())
+        .map(|_| fd.into_raw() as u16)
+        .map_err(|_| RunError::FdCreationFailed(Errno::EMFILE))
 }
 
 fn validate_elf_header(bytes: &[u8]) -> bool {
@@ -294,16 +1233,30 @@ fn write_bytes(fd: u16, bytes: &[u8]) -> Result<(), RunError> {
         unsafe { syscalls::close(fd as i32) };
         return Err(RunError::InvalidElfFormat);
     }
-    let written = unsafe { syscalls::write(fd as _, bytes.as_ptr().cast_mut(), bytes.len()) };
-    if written != bytes.len() as _ {
+    // write() transfers at most ~2GiB per call, so keep writing until everything is copied
+    let mut written = 0;
+    while written < bytes.len() {
+        let remaining = &bytes[written..];
+        let ret =
+            unsafe { syscalls::write(fd as _, remaining.as_ptr().cast_mut(), remaining.len()) };
+        let error = match cvt(ret) {
+            Ok(0) => RunError::BytesNotWritten(written, bytes.len()),
+            Ok(n) => {
+                written += n as usize;
+                continue;
+            }
+            Err(Errno::EINTR) => continue,
+            Err(errno) => RunError::WriteError(errno),
+        };
         unsafe { syscalls::close(fd as i32) };
-        return Err(RunError::BytesNotWritten(written as usize, bytes.len()));
+        return Err(error);
     }
     Ok(())
 }
 
 /// Prepare argv directly in provided stack storage
 /// Returns the number of arguments prepared
+#[cfg(not(feature = "alloc"))]
 fn prepare_argv(
     fd: u16,
     options: &RunOptions<'_>,
@@ -349,93 +1302,613 @@ fn prepare_argv(
     for i in 0..arg_count {
         ptrs[i] = storage[i].as_ptr();
     }
-    ptrs[arg_count] = core::ptr::null();
+    ptrs[arg_count] = core::ptr::null();
+
+    Ok(arg_count)
+}
+
+/// Prepare envp directly in provided stack storage
+/// Returns the number of environment variables prepared
+///
+/// Inherited variables are referenced in place, only the variables provided through
+/// [`RunOptions`] are copied (and subject to the size limits).
+#[cfg(not(feature = "alloc"))]
+fn prepare_envp(
+    options: &RunOptions<'_>,
+    inherited: &[u8],
+    storage: &mut [[u8; MAX_STRING_LEN]; MAX_ENV],
+    ptrs: &mut [*const u8; MAX_ENVP + 1],
+) -> Result<usize, RunError> {
+    if options.env.is_some_and(|user_env| user_env.len() > MAX_ENV) || options.env_ops.overflowed()
+    {
+        return Err(RunError::TooManyEnvVars);
+    }
+
+    let mut env_count = 0;
+    let mut copied = 0;
+    for entry in env::merged(options, inherited) {
+        if env_count == MAX_ENVP {
+            return Err(RunError::TooManyEnvVars);
+        }
+        ptrs[env_count] = match entry {
+            // already null terminated in the inherited environment
+            env::EnvEntry::Inherited(env_var) => env_var.as_ptr(),
+            entry => {
+                if copied == MAX_ENV {
+                    return Err(RunError::TooManyEnvVars);
+                }
+                let slot = &mut storage[copied];
+                let mut len = 0;
+                for part in entry.parts() {
+                    if len + part.len() >= MAX_STRING_LEN {
+                        return Err(RunError::EnvVarTooLong);
+                    }
+                    slot[len..len + part.len()].copy_from_slice(part);
+                    len += part.len();
+                }
+                slot[len] = 0; // null terminate
+                copied += 1;
+                slot.as_ptr()
+            }
+        };
+        env_count += 1;
+    }
+    ptrs[env_count] = core::ptr::null();
+    Ok(env_count)
+}
+
+/// Prepare argv in a single heap buffer sized to the actual arguments
+/// Returns the number of arguments prepared
+#[cfg(feature = "alloc")]
+fn prepare_argv(
+    fd: u16,
+    options: &RunOptions<'_>,
+    storage: &mut Vec<u8>,
+    ptrs: &mut Vec<*const u8>,
+) -> Result<usize, RunError> {
+    // Set argv[0] - either custom or default memfd path
+    let path = build_path(fd);
+    let argv0 = match options.argv0 {
+        Some(custom_argv0) => custom_argv0.as_bytes(),
+        None => &path[..path.iter().position(|&b| b == 0).unwrap()],
+    };
+    let user_args = options.args.unwrap_or_default();
+    let args = core::iter::once(argv0).chain(user_args.iter().map(|arg| arg.as_bytes()));
+    Ok(fill_heap_strings(args.map(|arg| [arg]), storage, ptrs))
+}
+
+/// Prepare envp in a single heap buffer sized to the actual environment
+/// Returns the number of environment variables prepared
+#[cfg(feature = "alloc")]
+fn prepare_envp(
+    options: &RunOptions<'_>,
+    inherited: &[u8],
+    storage: &mut Vec<u8>,
+    ptrs: &mut Vec<*const u8>,
+) -> Result<usize, RunError> {
+    let env = env::merged(options, inherited).map(|entry| entry.parts());
+    Ok(fill_heap_strings(env, storage, ptrs))
+}
+
+/// Copy `strings`, each made of `N` concatenated parts, null terminated into `storage`, and
+/// point `ptrs` to each of them. `ptrs` ends with a null pointer. Returns the number of strings copied.
+#[cfg(feature = "alloc")]
+fn fill_heap_strings<'s, const N: usize>(
+    strings: impl Iterator<Item = [&'s [u8]; N]> + Clone,
+    storage: &mut Vec<u8>,
+    ptrs: &mut Vec<*const u8>,
+) -> usize {
+    let string_len = |parts: &[&[u8]; N]| parts.iter().map(|part| part.len()).sum::<usize>();
+    let (count, total_len) = strings.clone().fold((0, 0), |(count, len), parts| {
+        (count + 1, len + string_len(&parts) + 1)
+    });
+
+    storage.clear();
+    storage.reserve_exact(total_len);
+    for parts in strings.clone() {
+        for part in parts {
+            storage.extend_from_slice(part);
+        }
+        storage.push(0); // null terminate
+    }
+
+    // Set all pointers AFTER all data is prepared, so that no reallocation can move it
+    ptrs.clear();
+    ptrs.reserve_exact(count + 1);
+    let mut offset = 0;
+    for parts in strings {
+        ptrs.push(storage[offset..].as_ptr());
+        offset += string_len(&parts) + 1;
+    }
+    ptrs.push(core::ptr::null());
+    count
+}
+
+/// Everything the child needs to call `execve`, prepared before forking.
+#[derive(Clone, Copy)]
+struct ExecArgs<'p> {
+    fd: u16,
+    path: &'p [u8; EXEC_PATH_LEN],
+    argv: *const *const u8,
+    envp: *const *const u8,
+    backend: ExecBackend,
+    stdio: &'p StdioSetup,
+    file_actions: &'p [FileAction<'p>],
+    close_other_fds: bool,
+    current_dir: Option<i32>,
+    umask: Option<u32>,
+    credentials: Credentials<'p>,
+    capabilities: CapabilitySetup,
+    rlimits: &'p rlimit::Limits,
+    namespaces: NamespaceSetup<'p>,
+    pre_exec: &'p [PreExecHook<'p>],
+    landlock: Option<i32>,
+    seccomp: Option<&'p Program<'p>>,
+}
+
+/// Prepare argv and envp in storage, then hand the stable pointers to `f`.
+///
+/// Everything that can fail or needs to copy memory happens here, so that the code running
+/// between `fork()` and `execve()` only has to issue system calls.
+fn with_exec_args<R>(
+    fd: u16,
+    options: &RunOptions<'_>,
+    stdio: &StdioSetup,
+    f: impl FnOnce(&ExecArgs<'_>) -> R,
+) -> Result<R, RunError> {
+    #[cfg(not(feature = "alloc"))]
+    if options.pre_exec.overflowed() {
+        return Err(RunError::TooManyPreExecHooks);
+    }
+    // Opened here so that a missing directory is reported before forking
+    let current_dir = options
+        .current_dir
+        .map(fd::open_dir)
+        .transpose()
+        .map_err(RunError::CurrentDirError)?;
+    // Created here as well, and kept above the descriptors of the file actions applied before it
+    let landlock = match options.landlock {
+        Some(ruleset) => ruleset
+            .create()
+            .and_then(|ruleset| {
+                ruleset
+                    .map(|fd| fd.at_least(file_actions::first_free_fd(options.file_actions)))
+                    .transpose()
+            })
+            .map_err(RunError::LandlockError)?,
+        None => None,
+    };
+    let id_maps = match options.namespaces.contains(Namespaces::USER) {
+        true => Some(
+            IdMaps::prepare(options.uid_map, options.gid_map).map_err(RunError::NamespaceError)?,
+        ),
+        false => None,
+    };
+    let path = build_path(fd);
+
+    // Stack-allocated storage
+    #[cfg(not(feature = "alloc"))]
+    let (mut argv_storage, mut argv, mut envp_storage, mut envp) = (
+        [[0u8; MAX_STRING_LEN]; MAX_ARGS],
+        [core::ptr::null::<u8>(); MAX_ARGS + 1],
+        [[0u8; MAX_STRING_LEN]; MAX_ENV],
+        [core::ptr::null::<u8>(); MAX_ENVP + 1],
+    );
+    #[cfg(not(feature = "alloc"))]
+    let mut environ_buf = [0u8; env::ENVIRON_BUF_LEN];
+    #[cfg(not(feature = "alloc"))]
+    let inherited = match env::inherits(options) {
+        true => env::inherited_environment(&mut environ_buf)?,
+        false => &[],
+    };
+
+    // Heap-allocated storage, sized to the actual arguments and environment
+    #[cfg(feature = "alloc")]
+    let (mut argv_storage, mut argv, mut envp_storage, mut envp) =
+        (Vec::new(), Vec::new(), Vec::new(), Vec::new());
+    #[cfg(feature = "alloc")]
+    let environ = match env::inherits(options) {
+        true => env::inherited_environment()?,
+        false => Vec::new(),
+    };
+    #[cfg(feature = "alloc")]
+    let inherited = environ.as_slice();
+
+    // Prepare arguments and environment directly in storage
+    prepare_argv(fd, options, &mut argv_storage, &mut argv)?;
+    prepare_envp(options, inherited, &mut envp_storage, &mut envp)?;
+
+    Ok(f(&ExecArgs {
+        fd,
+        path: &path,
+        argv: argv.as_ptr(),
+        envp: envp.as_ptr(),
+        backend: options.backend,
+        stdio,
+        file_actions: options.file_actions,
+        close_other_fds: options.close_other_fds,
+        current_dir: current_dir.as_ref().map(Fd::raw),
+        umask: options.umask,
+        credentials: options.credentials,
+        capabilities: options.capabilities,
+        rlimits: &options.rlimits,
+        namespaces: NamespaceSetup {
+            namespaces: options.namespaces,
+            unshare: options.namespaces,
+            id_maps: id_maps.as_ref(),
+            maps_written: None,
+        },
+        pre_exec: options.pre_exec.as_slice(),
+        landlock: landlock.as_ref().map(Fd::raw),
+        // compiled once the descriptors the child uses after installing it are known
+        seccomp: None,
+    }))
+}
+
+/// Step of the child setup which failed, reported to the parent along with the errno
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u32)]
+enum ChildStage {
+    Exec = 0,
+    Stdio = 1,
+    PreExec = 2,
+    FileActions = 3,
+    CurrentDir = 4,
+    Credentials = 5,
+    Rlimits = 6,
+    Namespaces = 7,
+    Seccomp = 8,
+    Landlock = 9,
+    Capabilities = 10,
+}
+
+/// Failure of the child between `fork()` and a successful `execve()`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct ChildError {
+    stage: ChildStage,
+    errno: Errno,
+}
+
+impl ChildError {
+    const LEN: usize = 8;
+
+    /// Attach `stage` to the errno of a failed step
+    fn at(stage: ChildStage) -> impl Fn(Errno) -> ChildError {
+        move |errno| ChildError { stage, errno }
+    }
+
+    fn to_bytes(self) -> [u8; Self::LEN] {
+        let mut bytes = [0u8; Self::LEN];
+        bytes[..4].copy_from_slice(&(self.stage as u32).to_ne_bytes());
+        bytes[4..].copy_from_slice(&self.errno.raw().to_ne_bytes());
+        bytes
+    }
+
+    fn from_bytes(bytes: [u8; Self::LEN]) -> Self {
+        let [s0, s1, s2, s3, e0, e1, e2, e3] = bytes;
+        let stage = match u32::from_ne_bytes([s0, s1, s2, s3]) {
+            1 => ChildStage::Stdio,
+            2 => ChildStage::PreExec,
+            3 => ChildStage::FileActions,
+            4 => ChildStage::CurrentDir,
+            5 => ChildStage::Credentials,
+            6 => ChildStage::Rlimits,
+            7 => ChildStage::Namespaces,
+            8 => ChildStage::Seccomp,
+            9 => ChildStage::Landlock,
+            10 => ChildStage::Capabilities,
+            _ => ChildStage::Exec,
+        };
+        let errno = Errno::from_raw(i32::from_ne_bytes([e0, e1, e2, e3]));
+        Self { stage, errno }
+    }
+}
+
+impl From<ChildError> for RunError {
+    fn from(error: ChildError) -> Self {
+        match error.stage {
+            ChildStage::Exec => RunError::ExecError(error.errno),
+            ChildStage::Stdio => RunError::StdioError(error.errno),
+            ChildStage::PreExec => RunError::PreExecError(error.errno),
+            ChildStage::FileActions => RunError::FileActionError(error.errno),
+            ChildStage::CurrentDir => RunError::CurrentDirError(error.errno),
+            ChildStage::Credentials => RunError::CredentialsError(error.errno),
+            ChildStage::Rlimits => RunError::RlimitError(error.errno),
+            ChildStage::Namespaces => RunError::NamespaceError(error.errno),
+            ChildStage::Seccomp => RunError::SeccompError(error.errno),
+            ChildStage::Landlock => RunError::LandlockError(error.errno),
+            ChildStage::Capabilities => RunError::CapabilitiesError(error.errno),
+        }
+    }
+}
+
+/// Set up the process for the payload, then execute it. Only returns if something failed.
+fn execute_child(args: &ExecArgs<'_>) -> ChildError {
+    match setup_child(args) {
+        Err(error) => error,
+        Ok(()) => ChildError {
+            stage: ChildStage::Exec,
+            errno: execute_payload(args),
+        },
+    }
+}
+
+/// Apply the configured process attributes, in the child (or the current process in replace mode)
+fn setup_child(args: &ExecArgs<'_>) -> Result<(), ChildError> {
+    args.namespaces
+        .apply()
+        .map_err(ChildError::at(ChildStage::Namespaces))?;
+    args.stdio
+        .apply()
+        .map_err(ChildError::at(ChildStage::Stdio))?;
+    // before the file actions, so that they resolve relative paths and create files accordingly
+    if let Some(dir) = args.current_dir {
+        cvt(unsafe { syscalls::fchdir(dir) }).map_err(ChildError::at(ChildStage::CurrentDir))?;
+    }
+    if let Some(mask) = args.umask {
+        // umask() cannot fail, it returns the previous mask
+        unsafe { syscalls::umask(mask) };
+    }
+    // while still privileged, raising a hard limit requires CAP_SYS_RESOURCE
+    rlimit::apply(args.rlimits).map_err(ChildError::at(ChildStage::Rlimits))?;
+    // the bounding set and securebits can only be changed with CAP_SETPCAP
+    args.capabilities
+        .before_uid_switch()
+        .map_err(ChildError::at(ChildStage::Capabilities))?;
+    args.credentials
+        .apply()
+        .map_err(ChildError::at(ChildStage::Credentials))?;
+    args.capabilities
+        .after_uid_switch()
+        .map_err(ChildError::at(ChildStage::Capabilities))?;
+    if args.close_other_fds {
+        file_actions::cloexec_other_fds().map_err(ChildError::at(ChildStage::FileActions))?;
+    }
+    file_actions::apply(args.file_actions).map_err(ChildError::at(ChildStage::FileActions))?;
+    for hook in args.pre_exec {
+        hook().map_err(ChildError::at(ChildStage::PreExec))?;
+    }
+    // after the file actions and hooks, which may still open files outside of it
+    if let Some(ruleset) = args.landlock {
+        landlock::restrict(ruleset).map_err(ChildError::at(ChildStage::Landlock))?;
+    }
+    // last, so that it only restricts the payload and the calls the runner lets through
+    if let Some(program) = args.seccomp {
+        program
+            .install()
+            .map_err(ChildError::at(ChildStage::Seccomp))?;
+    }
+    Ok(())
+}
+
+/// Execute the payload, returning the errno if `execve` failed
+fn execute_payload(args: &ExecArgs<'_>) -> Errno {
+    // Execute with stable pointers using direct syscall
+    match args.backend {
+        ExecBackend::Auto => match execute_fd(args) {
+            // execveat() is missing (Linux < 3.19), go through /proc instead
+            Errno::ENOSYS => execute_proc_path(args),
+            errno => errno,
+        },
+        ExecBackend::Execveat => execute_fd(args),
+        ExecBackend::ProcSelfFd => execute_proc_path(args),
+    }
+}
 
-    Ok(arg_count)
+/// `execveat(fd, "", argv, envp, AT_EMPTY_PATH)`: runs the memfd itself, no /proc needed
+fn execute_fd(args: &ExecArgs<'_>) -> Errno {
+    let ret = unsafe {
+        syscalls::execveat(
+            args.fd as i32,
+            // borrowed, so that the path is the static one the seccomp filter lets through
+            EMPTY_STRING.as_slice(),
+            args.argv as *mut u8,
+            args.envp as *mut u8,
+            AT_EMPTY_PATH,
+        )
+    };
+    Errno::from_ret(ret as isize)
 }
 
-/// Prepare envp directly in provided stack storage
-/// Returns the number of environment variables prepared
-fn prepare_envp(
-    env: Option<&[&str]>,
-    storage: &mut [[u8; MAX_STRING_LEN]; MAX_ENV],
-    ptrs: &mut [*const u8; MAX_ENV + 1],
-) -> Result<usize, RunError> {
-    let mut env_count = 0;
+/// `execve("/proc/self/fd/N", argv, envp)`: requires /proc to be mounted
+fn execute_proc_path(args: &ExecArgs<'_>) -> Errno {
+    let ret = unsafe { syscalls::execve(args.path, args.argv as *mut u8, args.envp as *mut u8) };
+    Errno::from_ret(ret as isize)
+}
 
-    if let Some(user_env) = env {
-        if user_env.len() > MAX_ENV {
-            return Err(RunError::TooManyEnvVars);
+fn exec(fd: u16, options: &RunOptions<'_>) -> Result<Infallible, RunError> {
+    // Like std, pipes requested in replace mode are created but their other end is closed by execve
+    let stdio = StdioSetup::prepare(options).map_err(RunError::StdioError)?;
+    let cgroup = prepare_cgroup(options)?;
+    let error = with_exec_args(fd, options, &stdio, |args| {
+        let seccomp = compile_seccomp(options, args, None, None)?;
+        // after everything which can fail before the setup, so that a failed preparation leaves
+        // the current process where it was, a failed setup or execve leaves it in the cgroup
+        if let Some(cgroup) = &cgroup {
+            cgroup.enter().map_err(RunError::CgroupError)?;
         }
+        Ok::<_, RunError>(execute_child(&ExecArgs {
+            seccomp: seccomp.as_ref(),
+            ..*args
+        }))
+    })??;
+    Err(error.into())
+}
 
-        for (i, &env_var) in user_env.iter().enumerate() {
-            let env_bytes = env_var.as_bytes();
-            if env_bytes.len() >= MAX_STRING_LEN {
-                return Err(RunError::EnvVarTooLong);
+fn spawn(fd: u16, options: &RunOptions<'_>) -> Result<Child, RunError> {
+    let stdio = StdioSetup::prepare(options).map_err(RunError::StdioError)?;
+    let cgroup = prepare_cgroup(options)?;
+    let (pid, pidfd, started, notifier) = with_exec_args(fd, options, &stdio, |args| {
+        // The write end is closed by a successful execve, which the parent observes as EOF
+        let (read_end, write_end) = fd::pipe().map_err(RunError::PipeError)?;
+        let write_end = write_end
+            .at_least(file_actions::first_free_fd(args.file_actions))
+            .map_err(RunError::PipeError)?;
+
+        // clone3 creates the namespaces, and the parent maps the ids of a user namespace while
+        // the child waits on this pipe
+        let maps_pipe = match args.namespaces.id_maps {
+            Some(_) => Some(fd::pipe().map_err(RunError::PipeError)?),
+            None => None,
+        };
+        // the child sends the listener of a filter notifying calls over this socket
+        let listener_socket = match options.seccomp.is_some_and(SeccompFilter::notifies) {
+            true => {
+                let (parent_end, child_end) = fd::socketpair().map_err(RunError::SeccompError)?;
+                let child_end = child_end
+                    .at_least(file_actions::first_free_fd(args.file_actions))
+                    .map_err(RunError::SeccompError)?;
+                Some((parent_end, child_end))
+            }
+            false => None,
+        };
+        // the failure and the listener are sent from here, addresses the seccomp filter can check
+        let report = Cell::new([0u8; ChildError::LEN]);
+        let listener_msg = MsgSlot::new();
+        let seccomp = compile_seccomp(
+            options,
+            args,
+            Some((write_end.raw(), report.as_ptr().cast(), ChildError::LEN)),
+            listener_socket
+                .as_ref()
+                .map(|(_, child_end)| (child_end.raw(), &listener_msg)),
+        )?;
+        let args = &ExecArgs {
+            seccomp: seccomp.as_ref(),
+            namespaces: NamespaceSetup {
+                unshare: Namespaces::empty(),
+                maps_written: maps_pipe
+                    .as_ref()
+                    .map(|(read_end, write_end)| (read_end.raw(), write_end.raw())),
+                ..args.namespaces
+            },
+            ..*args
+        };
+
+        let run_child = || report_child_failure(&write_end, &report, execute_child(args));
+        let started = time::monotonic_now();
+        let forked = match options.spawn_method {
+            // hooks may write to memory, which must not be shared with the parent, and the child
+            // must not wait for a parent suspended until it calls execve
+            SpawnMethod::Vfork
+                if args.pre_exec.is_empty()
+                    && options.namespaces.is_empty()
+                    && cgroup.is_none() =>
+            {
+                // the child runs `run_child` on its own stack and never comes back here
+                vfork::spawn(&run_child)
+            }
+            SpawnMethod::Fork | SpawnMethod::Vfork => {
+                pidfd::fork(options.namespaces, cgroup.as_ref().map(CgroupDir::raw))
             }
+        };
 
-            storage[i][..env_bytes.len()].copy_from_slice(env_bytes);
-            storage[i][env_bytes.len()] = 0; // null terminate
-            ptrs[i] = storage[i].as_ptr();
-            env_count += 1;
+        // if child, set up the process and call execve
+        if matches!(forked, Ok((0, _))) {
+            run_child();
         }
-    }
-    ptrs[env_count] = core::ptr::null();
-    Ok(env_count)
-}
-
-/// Execute the child process
-fn execute_child(fd: u16, options: &RunOptions<'_>) -> Result<i32, RunError> {
-    let path = build_path(fd);
-
-    // Stack-allocated storage
-    let mut argv_storage: [[u8; MAX_STRING_LEN]; MAX_ARGS] = [[0; MAX_STRING_LEN]; MAX_ARGS];
-    let mut argv: [*const u8; MAX_ARGS + 1] = [core::ptr::null(); MAX_ARGS + 1];
 
-    let mut envp_storage: [[u8; MAX_STRING_LEN]; MAX_ENV] = [[0; MAX_STRING_LEN]; MAX_ENV];
-    let mut envp: [*const u8; MAX_ENV + 1] = [core::ptr::null(); MAX_ENV + 1];
+        drop(write_end);
+        let listener_socket = listener_socket.map(|(parent_end, _)| parent_end);
+        let (pid, pidfd) = forked.map_err(RunError::ForkError)?;
+        if let (Some(id_maps), Some((_, maps_written))) = (args.namespaces.id_maps, maps_pipe) {
+            if let Err(errno) = write_id_maps(pid, id_maps, maps_written) {
+                // The child still waits for the maps, which are not coming
+                unsafe { syscalls::kill(pid, child::SIGKILL) };
+                let _ = child::wait_pid(pid, 0);
+                return Err(RunError::NamespaceError(errno));
+            }
+        }
+        if let Some(error) = read_child_result(&read_end) {
+            // The child already exited with status 127, reap it so it does not linger as a zombie
+            let _ = child::wait_pid(pid, 0);
+            return Err(RunError::from(error));
+        }
+        // sent before execve, so it is already there
+        match listener_socket
+            .map(|socket| fd::recv_fd(&socket))
+            .transpose()
+        {
+            Ok(listener) => Ok((pid, pidfd, started, listener.map(Notifier::new))),
+            Err(errno) => {
+                // Without the listener, the notified calls of the payload would wait forever
+                unsafe { syscalls::kill(pid, child::SIGKILL) };
+                let _ = child::wait_pid(pid, 0);
+                Err(RunError::SeccompError(errno))
+            }
+        }
+    })??;
+    // Dropping the setup closes the child ends of the pipes in the parent
+    Ok(Child::new(pid, pidfd, started, stdio, notifier, cgroup))
+}
 
-    // Prepare arguments and environment directly in stack storage
-    prepare_argv(fd, options, &mut argv_storage, &mut argv)?;
-    prepare_envp(options.env, &mut envp_storage, &mut envp)?;
+/// Open or create the cgroup of the payload, if any, and set its limits.
+fn prepare_cgroup(options: &RunOptions<'_>) -> Result<Option<CgroupDir>, RunError> {
+    options
+        .cgroup
+        .map(|cgroup| cgroup.prepare())
+        .transpose()
+        .map_err(RunError::CgroupError)
+}
 
-    // Execute with stable pointers using direct syscall
+/// Compile the seccomp filter, if any, letting through the system calls the child still makes
+/// once it is installed: `execve`, reporting its failure with `report` and sending the
+/// listener of the notifications on `listener_socket`.
+fn compile_seccomp<'a>(
+    options: &RunOptions<'_>,
+    args: &ExecArgs<'_>,
+    report: Option<(i32, *const u8, usize)>,
+    listener_socket: Option<(i32, &'a MsgSlot)>,
+) -> Result<Option<Program<'a>>, RunError> {
+    let runner = RunnerCalls {
+        fd: args.fd as i32,
+        path: args.path.as_ptr(),
+        argv: args.argv,
+        envp: args.envp,
+        report,
+        listener_socket,
+    };
+    options
+        .seccomp
+        .map(|filter| Program::compile(filter, &runner))
+        .transpose()
+        .map_err(RunError::SeccompError)
+}
 
-    let ret = unsafe { syscalls::execve(path, argv.as_ptr() as *mut u8, envp.as_ptr() as *mut u8) };
+/// Write the id maps of the user namespace of the child `pid`, then let it go on.
+fn write_id_maps(pid: i32, id_maps: &IdMaps, maps_written: Fd) -> Result<(), Errno> {
+    id_maps.write(pid)?;
+    maps_written.write(&[1])?;
+    Ok(())
+}
 
-    if ret == -1 {
-        return Err(RunError::ExecError(-1)); // TODO: get actual errno
+/// Send `error` to the parent from `report` and terminate the child without running any parent
+/// code.
+fn report_child_failure(
+    write_end: &Fd,
+    report: &Cell<[u8; ChildError::LEN]>,
+    error: ChildError,
+) -> ! {
+    report.set(error.to_bytes());
+    unsafe {
+        syscalls::write(write_end.raw(), report.as_ptr().cast(), ChildError::LEN);
+        syscalls::exit_group(127);
     }
-    unreachable!("execve should not return on success");
+    unreachable!("exit_group should not return");
 }
 
-fn execute(fd: u16, options: RunOptions<'_>) -> Result<i32, RunError> {
-    let pid = match options.replace {
-        true => 0, // simulate we are the child
-        false => unsafe { syscalls::fork() },
-    };
-
-    // if child, call execve
-    match pid {
-        0 => execute_child(fd, &options),
-        -1 => Err(RunError::ForkError(-1)), // TODO: get actual errno
-        _ => {
-            let mut status: i32 = 0;
-            let waited_pid = unsafe {
-                syscalls::wait4(
-                    pid,
-                    &mut status as *mut i32 as *mut u8,
-                    0,
-                    core::ptr::null_mut(),
-                )
-            };
-            if waited_pid == -1 {
-                return Err(RunError::WaitError(-1)); // TODO: get actual errno
-            }
-            // Extract exit code using WEXITSTATUS equivalent: (status >> 8) & 0xff
-            Ok((status >> 8) & 0xff)
+/// Read the error sent by a child which failed before or during `execve`.
+/// Returns `None` when the pipe was closed by a successful `execve`.
+fn read_child_result(read_end: &Fd) -> Option<ChildError> {
+    let mut bytes = [0u8; ChildError::LEN];
+    let mut filled = 0;
+    while filled < bytes.len() {
+        match read_end.read(&mut bytes[filled..]) {
+            Ok(0) | Err(_) => break,
+            Ok(n) => filled += n,
         }
     }
+    (filled == bytes.len()).then(|| ChildError::from_bytes(bytes))
 }
 
 const EXEC_PATH: [u8; 20] = *b"/proc/self/fd/\0\0\0\0\0\0";
@@ -473,16 +1946,35 @@ mod tests {
     use std::format;
 
     // Helper functions for testing the new inline API
+    #[cfg(not(feature = "alloc"))]
     fn test_prepare_argv(fd: u16, options: &RunOptions<'_>) -> Result<usize, RunError> {
         let mut storage: [[u8; MAX_STRING_LEN]; MAX_ARGS] = [[0; MAX_STRING_LEN]; MAX_ARGS];
         let mut ptrs: [*const u8; MAX_ARGS + 1] = [core::ptr::null(); MAX_ARGS + 1];
         prepare_argv(fd, options, &mut storage, &mut ptrs)
     }
 
+    fn env_options<'a>(env: Option<&'a [&'a str]>) -> RunOptions<'a> {
+        match env {
+            Some(env) => RunOptions::new().with_env(env),
+            None => RunOptions::new().env_clear(),
+        }
+    }
+
+    #[cfg(not(feature = "alloc"))]
     fn test_prepare_envp(env: Option<&[&str]>) -> Result<usize, RunError> {
         let mut storage: [[u8; MAX_STRING_LEN]; MAX_ENV] = [[0; MAX_STRING_LEN]; MAX_ENV];
-        let mut ptrs: [*const u8; MAX_ENV + 1] = [core::ptr::null(); MAX_ENV + 1];
-        prepare_envp(env, &mut storage, &mut ptrs)
+        let mut ptrs: [*const u8; MAX_ENVP + 1] = [core::ptr::null(); MAX_ENVP + 1];
+        prepare_envp(&env_options(env), &[], &mut storage, &mut ptrs)
+    }
+
+    #[cfg(feature = "alloc")]
+    fn test_prepare_argv(fd: u16, options: &RunOptions<'_>) -> Result<usize, RunError> {
+        prepare_argv(fd, options, &mut Vec::new(), &mut Vec::new())
+    }
+
+    #[cfg(feature = "alloc")]
+    fn test_prepare_envp(env: Option<&[&str]>) -> Result<usize, RunError> {
+        prepare_envp(&env_options(env), &[], &mut Vec::new(), &mut Vec::new())
     }
 
     // RunOptions Tests
@@ -492,6 +1984,7 @@ mod tests {
         assert!(!options.replace);
         assert!(options.args.is_none());
         assert!(options.env.is_none());
+        assert_eq!(options.backend, ExecBackend::Auto);
     }
 
     #[test]
@@ -577,6 +2070,7 @@ mod tests {
         assert_eq!(result.unwrap(), 3);
     }
 
+    #[cfg(not(feature = "alloc"))]
     #[test]
     fn test_prepare_argv_too_many_args() {
         let mut args = std::vec::Vec::new();
@@ -588,6 +2082,7 @@ mod tests {
         assert!(matches!(result, Err(RunError::TooManyArgs)));
     }
 
+    #[cfg(not(feature = "alloc"))]
     #[test]
     fn test_prepare_argv_arg_too_long() {
         let long_arg = "a".repeat(MAX_STRING_LEN);
@@ -614,6 +2109,7 @@ mod tests {
         assert_eq!(result.unwrap(), 3);
     }
 
+    #[cfg(not(feature = "alloc"))]
     #[test]
     fn test_prepare_argv_custom_argv0_too_long() {
         let long_argv0 = "a".repeat(MAX_STRING_LEN);
@@ -638,6 +2134,7 @@ mod tests {
         assert_eq!(result.unwrap(), 2);
     }
 
+    #[cfg(not(feature = "alloc"))]
     #[test]
     fn test_prepare_envp_too_many_vars() {
         let mut env = std::vec::Vec::new();
@@ -648,6 +2145,7 @@ mod tests {
         assert!(matches!(result, Err(RunError::TooManyEnvVars)));
     }
 
+    #[cfg(not(feature = "alloc"))]
     #[test]
     fn test_prepare_envp_var_too_long() {
         let long_var = format!("VAR={}", "a".repeat(MAX_STRING_LEN));
@@ -656,6 +2154,1031 @@ mod tests {
         assert!(matches!(result, Err(RunError::EnvVarTooLong)));
     }
 
+    #[test]
+    fn test_prepare_envp_inherited_and_overrides() {
+        let inherited = b"PATH=/usr/bin\0HOME=/root\0";
+        let options = RunOptions::new().env("HOME", "/tmp").env("LANG", "C");
+        let strings: std::vec::Vec<std::vec::Vec<u8>>;
+        #[cfg(not(feature = "alloc"))]
+        {
+            let mut storage: [[u8; MAX_STRING_LEN]; MAX_ENV] = [[0; MAX_STRING_LEN]; MAX_ENV];
+            let mut ptrs: [*const u8; MAX_ENVP + 1] = [core::ptr::null(); MAX_ENVP + 1];
+            let count = prepare_envp(&options, inherited, &mut storage, &mut ptrs).unwrap();
+            assert_eq!(count, 3);
+            // inherited variables are not copied
+            assert_eq!(ptrs[0], inherited.as_ptr());
+            strings = ptrs[..count].iter().map(|&p| cstr_bytes(p)).collect();
+        }
+        #[cfg(feature = "alloc")]
+        {
+            let (mut storage, mut ptrs) = (Vec::new(), Vec::new());
+            let count = prepare_envp(&options, inherited, &mut storage, &mut ptrs).unwrap();
+            assert_eq!(count, 3);
+            strings = ptrs[..count].iter().map(|&p| cstr_bytes(p)).collect();
+        }
+        assert_eq!(strings, [&b"PATH=/usr/bin"[..], b"HOME=/tmp", b"LANG=C"]);
+    }
+
+    #[cfg(not(feature = "alloc"))]
+    #[test]
+    fn test_prepare_envp_too_many_overrides() {
+        let mut options = RunOptions::new();
+        for _i in 0..MAX_ENV + 1 {
+            options = options.env("VAR", "value");
+        }
+        let mut storage: [[u8; MAX_STRING_LEN]; MAX_ENV] = [[0; MAX_STRING_LEN]; MAX_ENV];
+        let mut ptrs: [*const u8; MAX_ENVP + 1] = [core::ptr::null(); MAX_ENVP + 1];
+        let result = prepare_envp(&options, &[], &mut storage, &mut ptrs);
+        assert!(matches!(result, Err(RunError::TooManyEnvVars)));
+    }
+
+    fn cstr_bytes(ptr: *const u8) -> std::vec::Vec<u8> {
+        unsafe { core::ffi::CStr::from_ptr(ptr.cast()) }
+            .to_bytes()
+            .to_vec()
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_prepare_heap_lifts_limits() {
+        let long_arg = "a".repeat(4 * MAX_STRING_LEN);
+        let mut args = std::vec![long_arg.as_str()];
+        args.extend(core::iter::repeat_n("arg", 10 * MAX_ARGS));
+        let options = RunOptions::new().with_args(&args);
+        assert_eq!(test_prepare_argv(123, &options).unwrap(), args.len() + 1);
+
+        let long_var = format!("VAR={}", "a".repeat(4 * MAX_STRING_LEN));
+        let mut env = std::vec![long_var.as_str()];
+        env.extend(core::iter::repeat_n("VAR=value", 10 * MAX_ENV));
+        assert_eq!(test_prepare_envp(Some(&env)).unwrap(), env.len());
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_prepare_heap_pointers() {
+        let args = ["", "second", "third"];
+        let options = RunOptions::new().with_argv0("first").with_args(&args);
+        let mut storage = Vec::new();
+        let mut ptrs = Vec::new();
+        assert_eq!(
+            prepare_argv(1, &options, &mut storage, &mut ptrs).unwrap(),
+            4
+        );
+        assert_eq!(storage, b"first\0\0second\0third\0");
+        assert_eq!(storage.capacity(), storage.len());
+        assert_eq!(ptrs.len(), 5);
+        let strings: std::vec::Vec<_> = ptrs[..4]
+            .iter()
+            .map(|&ptr| unsafe { core::ffi::CStr::from_ptr(ptr.cast()) }.to_bytes())
+            .collect();
+        assert_eq!(strings, [&b"first"[..], b"", b"second", b"third"]);
+        assert!(ptrs[4].is_null());
+    }
+
+    #[test]
+    fn test_spawn_environment() {
+        let elf_bytes = std::fs::read("/bin/sh").unwrap();
+        let script = r#"test "$CARGO_PKG_NAME" = memfd-runner && test "$FOO" = bar && test -z "${CARGO_MANIFEST_DIR+set}""#;
+        let args = ["-c", script];
+        let options = RunOptions::new()
+            .with_args(&args)
+            .env("FOO", "bar")
+            .env_remove("CARGO_MANIFEST_DIR");
+        assert!(run_with_options(&elf_bytes, options).unwrap().success());
+
+        let args = ["-c", r#"test -z "${CARGO_PKG_NAME+set}""#];
+        let options = RunOptions::new().with_args(&args).env_clear();
+        assert!(run_with_options(&elf_bytes, options).unwrap().success());
+    }
+
+    // spawn Tests
+    #[test]
+    fn test_spawn_wait_exit_code() {
+        let elf_bytes = std::fs::read("/bin/sh").unwrap();
+        let args = ["-c", "exit 3"];
+        let mut child = RunOptions::new()
+            .with_args(&args)
+            .spawn(&elf_bytes)
+            .unwrap();
+        assert!(child.id() > 0);
+        assert_eq!(child.wait().unwrap().code(), Some(3));
+        // a reaped child keeps reporting its exit status
+        assert_eq!(child.wait().unwrap().code(), Some(3));
+        assert_eq!(child.try_wait().unwrap().and_then(|s| s.code()), Some(3));
+    }
+
+    #[test]
+    fn test_spawn_try_wait_and_kill() {
+        let elf_bytes = std::fs::read("/bin/sleep").unwrap();
+        let args = ["10"];
+        let mut child = RunOptions::new()
+            .with_args(&args)
+            .spawn(&elf_bytes)
+            .unwrap();
+        assert_eq!(child.try_wait().unwrap(), None);
+        child.kill().unwrap();
+        let status = child.wait().unwrap();
+        assert_eq!(status.signal(), Some(9));
+        assert_eq!(status.code(), None);
+        assert_eq!(status.shell_code(), 137);
+        // killing an already reaped child is a no-op
+        assert!(child.kill().is_ok());
+    }
+
+    #[test]
+    fn test_run_with_report() {
+        let elf_bytes = std::fs::read("/bin/sh").unwrap();
+        // burn some CPU in the shell itself, then sleep
+        let args = [
+            "-c",
+            "i=0; while [ $i -lt 20000 ]; do i=$((i+1)); done; sleep 0.1",
+        ];
+        let options = RunOptions::new().with_args(&args);
+        let report = run_with_report(&elf_bytes, options).unwrap();
+        assert!(report.status.success());
+        assert!(report.wall_time >= Duration::from_millis(100));
+        assert!(report.rusage.user_time + report.rusage.system_time > Duration::ZERO);
+        assert!(report.rusage.max_rss_kib > 0);
+        assert!(report.rusage.minor_faults > 0);
+    }
+
+    #[test]
+    fn test_spawn_pidfd() {
+        let elf_bytes = std::fs::read("/bin/sleep").unwrap();
+        let args = ["10"];
+        let mut child = RunOptions::new()
+            .with_args(&args)
+            .spawn(&elf_bytes)
+            .unwrap();
+        let pidfd = child.pidfd().expect("pidfds are supported since Linux 5.3");
+        pidfd.send_signal(15).unwrap();
+        assert_eq!(child.wait().unwrap().signal(), Some(15));
+        // the pidfd outlives the child without ever referring to another process
+        assert_eq!(child.pidfd().unwrap().send_signal(15), Err(Errno::ESRCH));
+    }
+
+    #[test]
+    fn test_spawn_vfork() {
+        let elf_bytes = std::fs::read("/bin/sh").unwrap();
+        let args = ["-c", "echo hi; exit 3"];
+        let mut child = RunOptions::new()
+            .with_args(&args)
+            .with_spawn_method(SpawnMethod::Vfork)
+            .with_stdout(Stdio::Piped)
+            .spawn(&elf_bytes)
+            .unwrap();
+        assert!(child.pidfd().is_some());
+        let stdout = child.stdout.as_mut().unwrap();
+        assert_eq!(read_to_end(|buf| stdout.read(buf)), b"hi\n");
+        assert_eq!(child.wait().unwrap().code(), Some(3));
+
+        // failures in the child are reported the same way as with fork
+        let result = RunOptions::new()
+            .with_spawn_method(SpawnMethod::Vfork)
+            .with_stdout(Stdio::Fd(4096))
+            .spawn(&elf_bytes);
+        assert!(matches!(result, Err(RunError::StdioError(Errno::EBADF))));
+    }
+
+    #[test]
+    fn test_spawn_pre_exec() {
+        let elf_bytes = std::fs::read("/bin/sh").unwrap();
+        let args = ["-c", "echo after"];
+        // runs after the standard streams are installed, so it writes into the pipe
+        let hook = || {
+            let message = b"before\n";
+            cvt(unsafe { syscalls::write(1, message.as_ptr().cast_mut(), message.len()) })?;
+            Ok(())
+        };
+        let mut child = unsafe {
+            RunOptions::new()
+                .with_args(&args)
+                .with_stdout(Stdio::Piped)
+                .pre_exec(&hook)
+        }
+        .spawn(&elf_bytes)
+        .unwrap();
+        let stdout = child.stdout.as_mut().unwrap();
+        assert_eq!(read_to_end(|buf| stdout.read(buf)), b"before\nafter\n");
+        assert!(child.wait().unwrap().success());
+    }
+
+    #[test]
+    fn test_pre_exec_error() {
+        let elf_bytes = std::fs::read("/bin/sh").unwrap();
+        let fail = || Err(Errno::EPERM);
+        for method in [SpawnMethod::Fork, SpawnMethod::Vfork] {
+            let result = unsafe { RunOptions::new().pre_exec(&fail) }
+                .with_spawn_method(method)
+                .spawn(&elf_bytes);
+            assert!(matches!(result, Err(RunError::PreExecError(Errno::EPERM))));
+        }
+        // in replace mode the current process is kept when a hook fails
+        let result = unsafe { RunOptions::new().pre_exec(&fail) }.exec(&elf_bytes);
+        assert!(matches!(result, Err(RunError::PreExecError(Errno::EPERM))));
+    }
+
+    #[test]
+    fn test_spawn_file_actions() {
+        let elf_bytes = std::fs::read("/bin/sh").unwrap();
+        let (read_end, write_end) = fd::pipe().unwrap();
+        let log = std::env::temp_dir().join(format!("memfd-runner-actions-{}", std::process::id()));
+        let log_path = std::ffi::CString::new(log.to_str().unwrap()).unwrap();
+        let actions = [
+            FileAction::Dup2 {
+                src: write_end.raw(),
+                dst: 5,
+            },
+            FileAction::open_write(7, &log_path),
+            FileAction::Close(0),
+        ];
+        let args = ["-c", "echo piped >&5; echo logged >&7; cat <&0 || exit 3"];
+        let mut child = RunOptions::new()
+            .with_args(&args)
+            .with_file_actions(&actions)
+            .spawn(&elf_bytes)
+            .unwrap();
+        drop(write_end);
+        assert_eq!(read_to_end(|buf| read_end.read(buf)), b"piped\n");
+        // stdin was closed, so cat fails
+        assert_eq!(child.wait().unwrap().code(), Some(3));
+        assert_eq!(std::fs::read(&log).unwrap(), b"logged\n");
+        std::fs::remove_file(&log).unwrap();
+
+        let actions = [FileAction::Close(4096)];
+        let result = RunOptions::new()
+            .with_file_actions(&actions)
+            .spawn(&elf_bytes);
+        assert!(matches!(
+            result,
+            Err(RunError::FileActionError(Errno::EBADF))
+        ));
+    }
+
+    const F_DUPFD: i32 = 0;
+
+    #[test]
+    fn test_spawn_close_other_fds() {
+        let elf_bytes = std::fs::read("/bin/sh").unwrap();
+        // an inheritable descriptor, like the ones leaked by code unaware of O_CLOEXEC
+        let leaked = cvt(unsafe { syscalls::fcntl(2, F_DUPFD, 600) }).unwrap();
+        let leaked = Fd::from_raw(leaked);
+        let leaked_arg = format!("{}", leaked.raw());
+        let args = [
+            "-c",
+            "[ -e /proc/$$/fd/9 ] && [ -e /proc/$$/fd/$1 ]",
+            "sh",
+            &leaked_arg,
+        ];
+        let actions = [FileAction::Dup2 {
+            src: leaked.raw(),
+            dst: 9,
+        }];
+        for close in [false, true] {
+            let status = RunOptions::new()
+                .with_args(&args)
+                .with_file_actions(&actions)
+                .with_close_other_fds(close)
+                .spawn(&elf_bytes)
+                .unwrap()
+                .wait()
+                .unwrap();
+            // descriptors installed by file actions are kept either way
+            assert_eq!(status.success(), !close);
+        }
+    }
+
+    #[test]
+    fn test_spawn_current_dir_and_umask() {
+        let elf_bytes = std::fs::read("/bin/sh").unwrap();
+        let args = ["-c", "pwd; umask"];
+        let mut child = RunOptions::new()
+            .with_args(&args)
+            .with_current_dir(c"/tmp")
+            .with_umask(0o027)
+            .with_stdout(Stdio::Piped)
+            .spawn(&elf_bytes)
+            .unwrap();
+        let stdout = child.stdout.as_mut().unwrap();
+        assert_eq!(read_to_end(|buf| stdout.read(buf)), b"/tmp\n0027\n");
+        assert!(child.wait().unwrap().success());
+    }
+
+    #[test]
+    fn test_current_dir_error() {
+        let elf_bytes = std::fs::read("/bin/sh").unwrap();
+        let result = RunOptions::new()
+            .with_current_dir(c"/nonexistent")
+            .spawn(&elf_bytes);
+        assert!(matches!(
+            result,
+            Err(RunError::CurrentDirError(Errno::ENOENT))
+        ));
+        let result = RunOptions::new()
+            .with_current_dir(c"/bin/sh")
+            .exec(&elf_bytes);
+        assert!(matches!(
+            result,
+            Err(RunError::CurrentDirError(Errno::ENOTDIR))
+        ));
+    }
+
+    fn run_id(options: RunOptions<'_>) -> Result<std::vec::Vec<u8>, RunError> {
+        let elf_bytes = std::fs::read("/bin/sh").unwrap();
+        let args = ["-c", "id -u; id -g; id -G"];
+        let mut child = options
+            .with_args(&args)
+            .with_stdout(Stdio::Piped)
+            .spawn(&elf_bytes)?;
+        let stdout = child.stdout.as_mut().unwrap();
+        let output = read_to_end(|buf| stdout.read(buf));
+        assert!(child.wait().unwrap().success());
+        Ok(output)
+    }
+
+    #[test]
+    fn test_spawn_credentials() {
+        let mut ids = [0u32; 3];
+        let [real, effective, saved] = ids.each_mut().map(|id| (id as *mut u32).cast());
+        cvt(unsafe { syscalls::getresuid(real, effective, saved) }).unwrap();
+        let options = RunOptions::new()
+            .with_uid(65534)
+            .with_gid(65534)
+            .with_groups(&[65534, 100]);
+        if ids[1] != 0 {
+            // only root can switch to another user
+            assert!(matches!(
+                run_id(options),
+                Err(RunError::CredentialsError(Errno::EPERM))
+            ));
+            return;
+        }
+        assert_eq!(run_id(options).unwrap(), b"65534\n65534\n65534 100\n");
+        // the supplementary groups of the parent are not inherited by another user
+        let options = RunOptions::new().with_uid(65534);
+        assert_eq!(run_id(options).unwrap(), b"65534\n0\n0\n");
+    }
+
+    #[test]
+    fn test_spawn_rlimits() {
+        let elf_bytes = std::fs::read("/bin/sh").unwrap();
+        let args = ["-c", "ulimit -n; ulimit -c"];
+        let mut child = RunOptions::new()
+            .with_args(&args)
+            .with_rlimit(Resource::Nofile, 64, 64)
+            .with_rlimit(Resource::Core, 0, 0)
+            .with_stdout(Stdio::Piped)
+            .spawn(&elf_bytes)
+            .unwrap();
+        let stdout = child.stdout.as_mut().unwrap();
+        assert_eq!(read_to_end(|buf| stdout.read(buf)), b"64\n0\n");
+        assert!(child.wait().unwrap().success());
+
+        // the soft limit cannot exceed the hard limit
+        let result = RunOptions::new()
+            .with_rlimit(Resource::Nofile, 64, 32)
+            .spawn(&elf_bytes);
+        assert!(matches!(result, Err(RunError::RlimitError(Errno::EINVAL))));
+    }
+
+    #[test]
+    fn test_run_exceeded_limits() {
+        let elf_bytes = std::fs::read("/bin/sh").unwrap();
+        let output =
+            std::env::temp_dir().join(format!("memfd-runner-fsize-{}", std::process::id()));
+        let script = format!("exec head -c 8192 /dev/zero > {}", output.display());
+        let args = ["-c", script.as_str()];
+        let options =
+            RunOptions::new()
+                .with_args(&args)
+                .with_rlimit(Resource::Fsize, 4096, RLIM_INFINITY);
+        let status = run_with_options(&elf_bytes, options).unwrap();
+        assert_eq!(status.exceeded_limit(), Some(Resource::Fsize));
+        assert_eq!(std::fs::metadata(&output).unwrap().len(), 4096);
+        std::fs::remove_file(&output).unwrap();
+
+        let args = ["-c", "while :; do :; done"];
+        let options =
+            RunOptions::new()
+                .with_args(&args)
+                .with_rlimit(Resource::Cpu, 1, RLIM_INFINITY);
+        let status = run_with_options(&elf_bytes, options).unwrap();
+        assert_eq!(status.exceeded_limit(), Some(Resource::Cpu));
+    }
+
+    #[test]
+    fn test_spawn_namespaces() {
+        let elf_bytes = std::fs::read("/bin/sh").unwrap();
+        let euid = unsafe { syscalls::geteuid() } as u32;
+        let egid = unsafe { syscalls::getegid() } as u32;
+        let uid_map = [IdMap::new(0, euid, 1)];
+        let gid_map = [IdMap::new(0, egid, 1)];
+        // pid, uid, and the header lines plus a lone loopback interface
+        let args = ["-c", "echo $$; id -u; wc -l < /proc/net/dev"];
+        let namespaces = Namespaces::USER
+            | Namespaces::PID
+            | Namespaces::NET
+            | Namespaces::MOUNT
+            | Namespaces::IPC
+            | Namespaces::UTS
+            | Namespaces::CGROUP;
+        let options = RunOptions::new()
+            .with_args(&args)
+            .with_namespaces(namespaces)
+            .with_uid_map(&uid_map)
+            .with_gid_map(&gid_map);
+        spawn_each_method(&elf_bytes, options, |mut child, mut stdout| {
+            assert!(child.id() > 1);
+            assert_eq!(read_to_end(|buf| stdout.read(buf)), b"1\n0\n3\n");
+            assert!(child.wait().unwrap().success());
+        });
+    }
+
+    #[test]
+    fn test_spawn_id_map_error() {
+        let elf_bytes = std::fs::read("/bin/sh").unwrap();
+        // overlapping ranges are rejected by the kernel
+        let uid_map = [IdMap::new(0, 0, 10), IdMap::new(5, 100, 10)];
+        let result = RunOptions::new()
+            .with_namespaces(Namespaces::USER)
+            .with_uid_map(&uid_map)
+            .spawn(&elf_bytes);
+        assert!(matches!(
+            result,
+            Err(RunError::NamespaceError(Errno::EINVAL))
+        ));
+    }
+
+    #[test]
+    fn test_spawn_seccomp() {
+        use seccomp::{Action, profile};
+
+        let elf_bytes = std::fs::read("/bin/sh").unwrap();
+        // the shell itself starts, but cannot run another program
+        let args = ["-c", "/bin/true 2>/dev/null; echo $?"];
+        let filter = SeccompFilter::new(Action::Allow).rules(profile::NO_EXEC);
+        for backend in [ExecBackend::Execveat, ExecBackend::ProcSelfFd] {
+            let options = RunOptions::new()
+                .with_args(&args)
+                .with_seccomp(&filter)
+                .with_exec_backend(backend);
+            spawn_each_method(&elf_bytes, options, |mut child, mut stdout| {
+                assert_eq!(read_to_end(|buf| stdout.read(buf)), b"126\n");
+                assert!(child.wait().unwrap().success());
+            });
+        }
+    }
+
+    #[test]
+    fn test_seccomp_kill() {
+        use seccomp::{Action, Rule, x86_64};
+
+        const SIGSYS: i32 = 31;
+        let elf_bytes = std::fs::read("/bin/sh").unwrap();
+        let args = ["-c", "exec uname"];
+        let filter = SeccompFilter::new(Action::Allow).rule(Rule::new(x86_64::UNAME, Action::Kill));
+        let options = RunOptions::new()
+            .with_args(&args)
+            .with_stdout(Stdio::Null)
+            .with_seccomp(&filter);
+        let status = run_with_options(&elf_bytes, options).unwrap();
+        assert_eq!(status.signal(), Some(SIGSYS));
+        // the payload is executed, then killed by its first system call
+        let filter = SeccompFilter::new(Action::Kill);
+        let options = RunOptions::new().with_seccomp(&filter);
+        let status = run_with_options(&elf_bytes, options).unwrap();
+        assert_eq!(status.signal(), Some(SIGSYS));
+    }
+
+    #[test]
+    fn test_seccomp_report_fd_reused() {
+        use seccomp::{Action, x86_64};
+
+        let elf_bytes = std::fs::read("/bin/sh").unwrap();
+        let dir = std::env::temp_dir().join(format!("memfd-runner-reuse-{}", std::process::id()));
+        std::fs::create_dir(&dir).unwrap();
+        std::fs::write(dir.join("input"), b"12345678").unwrap();
+        let dir_path = std::ffi::CString::new(dir.to_str().unwrap()).unwrap();
+        // tee gets the numbers of the close-on-exec descriptors of the runner for its files,
+        // and writes the 8 bytes of the input to each of them
+        let files: std::string::String = (1..=64).map(|n| format!(" {n}")).collect();
+        let script = format!("tee{files} < input > /dev/null");
+        let args = ["-c", script.as_str()];
+        let filter = SeccompFilter::new(Action::Allow).deny(x86_64::WRITE, Errno::EBADF);
+        let options = RunOptions::new()
+            .with_args(&args)
+            .with_current_dir(&dir_path)
+            .with_seccomp(&filter);
+        spawn_each_method(&elf_bytes, options, |mut child, _| {
+            assert!(!child.wait().unwrap().success());
+            for n in 1..=64 {
+                let written = std::fs::read(dir.join(format!("{n}"))).unwrap();
+                assert_eq!(written, b"", "file {n}");
+            }
+        });
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        // the runner still reports a failed execve
+        let mut bogus_elf = std::vec![0u8; 64];
+        bogus_elf[..4].copy_from_slice(b"\x7fELF");
+        let result = RunOptions::new().with_seccomp(&filter).spawn(&bogus_elf);
+        assert!(matches!(result, Err(RunError::ExecError(Errno::ENOEXEC))));
+    }
+
+    #[test]
+    fn test_spawn_seccomp_notify() {
+        use seccomp::{Action, Response, Rule, x86_64};
+
+        let elf_bytes = std::fs::read("/bin/sh").unwrap();
+        let args = [
+            "-c",
+            "echo $$; mkdir /memfd-runner-denied 2>/dev/null; echo $?",
+        ];
+        let filter = SeccompFilter::new(Action::Allow)
+            .rule(Rule::new(x86_64::GETPID, Action::Notify))
+            .rule(Rule::new(x86_64::MKDIR, Action::Notify))
+            .rule(Rule::new(x86_64::MKDIRAT, Action::Notify));
+        let options = RunOptions::new().with_args(&args).with_seccomp(&filter);
+        spawn_each_method(&elf_bytes, options, |mut child, mut stdout| {
+            assert!(child.notifier().is_some());
+            let mut denied = std::vec::Vec::new();
+            let status = child
+                .supervise(|notifier, call| match call.syscall {
+                    // emulated, the shell prints it as its pid
+                    x86_64::GETPID => Response::Return(4242),
+                    syscall => {
+                        let path = if syscall == x86_64::MKDIR {
+                            call.args[0]
+                        } else {
+                            call.args[1]
+                        };
+                        let mut buf = [0u8; 64];
+                        let path = notifier.read_c_str(call, path, &mut buf).unwrap();
+                        denied.push(std::string::String::from(path.to_str().unwrap()));
+                        Response::Errno(Errno::EACCES)
+                    }
+                })
+                .unwrap();
+            assert!(status.success());
+            assert_eq!(read_to_end(|buf| stdout.read(buf)), b"4242\n1\n");
+            assert_eq!(denied, ["/memfd-runner-denied"]);
+        });
+        // nothing to supervise without a notifying rule
+        let mut child = RunOptions::new()
+            .with_args(&["-c", "exit 3"])
+            .spawn(&elf_bytes)
+            .unwrap();
+        assert!(child.notifier().is_none());
+        let status = child.supervise(|_, _| Response::Continue).unwrap();
+        assert_eq!(status.code(), Some(3));
+    }
+
+    #[test]
+    fn test_seccomp_error() {
+        use seccomp::{Action, ArgCmp, Cmp, Rule, x86_64};
+
+        let elf_bytes = std::fs::read("/bin/sh").unwrap();
+        let args = [ArgCmp::new(6, Cmp::Eq(0))];
+        let filter = SeccompFilter::new(Action::Allow)
+            .rule(Rule::new(x86_64::READ, Action::Kill).with_args(&args));
+        let result = RunOptions::new().with_seccomp(&filter).spawn(&elf_bytes);
+        assert!(matches!(result, Err(RunError::SeccompError(Errno::EINVAL))));
+    }
+
+    #[test]
+    fn test_spawn_landlock() {
+        if let Err(errno) = landlock::abi_version() {
+            std::eprintln!("test_spawn_landlock skipped: Landlock is not available ({errno})");
+            return;
+        }
+        let elf_bytes = std::fs::read("/bin/sh").unwrap();
+        let dir =
+            std::env::temp_dir().join(format!("memfd-runner-landlock-{}", std::process::id()));
+        std::fs::create_dir(&dir).unwrap();
+        let dir_path = std::ffi::CString::new(dir.to_str().unwrap()).unwrap();
+        let log =
+            std::env::temp_dir().join(format!("memfd-runner-landlock-{}.log", std::process::id()));
+        let log_path = std::ffi::CString::new(log.to_str().unwrap()).unwrap();
+        let ruleset = Ruleset::new()
+            .read_only(c"/usr")
+            .read_only(c"/lib")
+            .read_only(c"/lib64")
+            .read_write(&dir_path);
+        // opened before the ruleset is enforced
+        let actions = [FileAction::open_write(5, &log_path)];
+        let script = "cat /etc/passwd; echo $?; echo ok > file && cat file; echo logged >&5";
+        let args = ["-c", script];
+        let options = RunOptions::new()
+            .with_args(&args)
+            .with_current_dir(&dir_path)
+            .with_file_actions(&actions)
+            .with_landlock(&ruleset)
+            .with_stderr(Stdio::Null);
+        spawn_each_method(&elf_bytes, options, |mut child, mut stdout| {
+            assert!(child.wait().unwrap().success());
+            assert_eq!(read_to_end(|buf| stdout.read(buf)), b"1\nok\n");
+            assert_eq!(std::fs::read(&log).unwrap(), b"logged\n");
+        });
+        std::fs::remove_file(&log).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        // the dynamic loader of the payload cannot be read
+        let ruleset = Ruleset::new();
+        let result = RunOptions::new().with_landlock(&ruleset).spawn(&elf_bytes);
+        assert!(matches!(result, Err(RunError::ExecError(Errno::EACCES))));
+        let ruleset = Ruleset::new().read_only(c"/memfd-runner-missing");
+        let result = RunOptions::new().with_landlock(&ruleset).spawn(&elf_bytes);
+        assert!(matches!(
+            result,
+            Err(RunError::LandlockError(Errno::ENOENT))
+        ));
+    }
+
+    #[test]
+    fn test_spawn_cgroup() {
+        // the cgroup v2 hierarchy, on its own or next to the v1 controllers
+        let Some(root) = ["/sys/fs/cgroup/unified", "/sys/fs/cgroup"]
+            .into_iter()
+            .map(std::path::Path::new)
+            .find(|root| root.join("cgroup.controllers").exists())
+        else {
+            std::eprintln!("test_spawn_cgroup skipped: no cgroup v2 hierarchy is mounted");
+            return;
+        };
+        let root_path = std::ffi::CString::new(root.to_str().unwrap()).unwrap();
+        let name = format!("memfd-runner-{}", std::process::id());
+        let name_path = std::ffi::CString::new(name.as_str()).unwrap();
+        let dir = root.join(&name);
+        let elf_bytes = std::fs::read("/bin/sh").unwrap();
+        let cgroup = Cgroup::create(&root_path, &name_path);
+        // created then removed right away
+        match cgroup.prepare() {
+            Err(errno @ (Errno::EACCES | Errno::EPERM | Errno::EROFS)) => {
+                std::eprintln!("test_spawn_cgroup skipped: cannot create a cgroup ({errno})");
+                return;
+            }
+            result => drop(result.unwrap()),
+        }
+        let args = ["-c", "cat /proc/self/cgroup; sleep 60 >/dev/null &"];
+        let options = RunOptions::new().with_args(&args).with_cgroup(cgroup);
+        spawn_each_method(&elf_bytes, options, |mut child, mut stdout| {
+            let report = child.wait_report().unwrap();
+            assert!(report.status.success());
+            assert!(report.cgroup.unwrap().cpu_time > Duration::ZERO);
+            let output = read_to_end(|buf| stdout.read(buf));
+            let output = std::string::String::from_utf8(output).unwrap();
+            assert!(output.contains(&format!("::/{name}\n")), "{output}");
+            // the orphaned sleep outlived the payload
+            let procs = std::fs::read_to_string(dir.join("cgroup.procs")).unwrap();
+            assert_eq!(procs.lines().count(), 1);
+            child.kill_tree().unwrap();
+            assert_eq!(
+                std::fs::read_to_string(dir.join("cgroup.procs")).unwrap(),
+                ""
+            );
+            drop(child);
+            assert!(!dir.exists());
+        });
+
+        std::fs::create_dir(&dir).unwrap();
+        let dir_path = std::ffi::CString::new(dir.to_str().unwrap()).unwrap();
+        let options = RunOptions::new()
+            .with_args(&["-c", "exit 3"])
+            .with_cgroup(Cgroup::existing(&dir_path));
+        let report = run_with_report(&elf_bytes, options).unwrap();
+        assert_eq!(report.status.code(), Some(3));
+        assert!(report.cgroup.is_some());
+        // left in place
+        std::fs::remove_dir(&dir).unwrap();
+
+        let mut child = RunOptions::new()
+            .with_stdin(Stdio::Null)
+            .spawn(&elf_bytes)
+            .unwrap();
+        assert!(matches!(
+            child.kill_tree(),
+            Err(RunError::KillError(Errno::EINVAL))
+        ));
+        child.wait().unwrap();
+        let missing = Cgroup::existing(c"/memfd-runner-missing");
+        let result = RunOptions::new().with_cgroup(missing).spawn(&elf_bytes);
+        assert!(matches!(result, Err(RunError::CgroupError(Errno::ENOENT))));
+        let invalid = Cgroup::create(&root_path, c"a/b");
+        let result = RunOptions::new().with_cgroup(invalid).spawn(&elf_bytes);
+        assert!(matches!(result, Err(RunError::CgroupError(Errno::EINVAL))));
+
+        // a replace failing before execve leaves the current process out of the cgroup
+        use seccomp::{Action, ArgCmp, Cmp, Rule, x86_64};
+        let own_cgroup = std::fs::read_to_string("/proc/self/cgroup").unwrap();
+        let args = [ArgCmp::new(6, Cmp::Eq(0))];
+        let filter = SeccompFilter::new(Action::Allow)
+            .rule(Rule::new(x86_64::READ, Action::Kill).with_args(&args));
+        let result = RunOptions::new()
+            .with_cgroup(cgroup)
+            .with_seccomp(&filter)
+            .exec(&elf_bytes);
+        assert!(matches!(result, Err(RunError::SeccompError(Errno::EINVAL))));
+        assert_eq!(
+            std::fs::read_to_string("/proc/self/cgroup").unwrap(),
+            own_cgroup
+        );
+        assert!(!dir.exists());
+    }
+
+    #[test]
+    fn test_spawn_capabilities() {
+        let elf_bytes = std::fs::read("/bin/sh").unwrap();
+        let options = || {
+            RunOptions::new()
+                .with_args(&[
+                    "-c",
+                    "grep -E '^(CapEff|CapBnd|CapAmb|NoNewPrivs)' /proc/self/status",
+                ])
+                .with_stdout(Stdio::Piped)
+        };
+        if unsafe { syscalls::geteuid() } != 0 {
+            let result = options()
+                .with_bounding_set(Capabilities::empty())
+                .spawn(&elf_bytes);
+            assert!(matches!(
+                result,
+                Err(RunError::CapabilitiesError(Errno::EPERM))
+            ));
+            return;
+        }
+        let keep_net_bind = options()
+            .with_uid(65534)
+            .with_gid(65534)
+            .with_ambient_capabilities(Capabilities::NET_BIND_SERVICE)
+            .with_bounding_set(Capabilities::NET_BIND_SERVICE)
+            .with_no_new_privs(true);
+        spawn_each_method(&elf_bytes, keep_net_bind, |mut child, mut stdout| {
+            assert!(child.wait().unwrap().success());
+            assert_eq!(
+                read_to_end(|buf| stdout.read(buf)),
+                b"CapEff:\t0000000000000400\nCapBnd:\t0000000000000400\nCapAmb:\t0000000000000400\nNoNewPrivs:\t1\n"
+            );
+        });
+
+        // root is not granted capabilities when running the payload
+        let mut child = options()
+            .with_securebits(SecureBits::NOROOT)
+            .spawn(&elf_bytes)
+            .unwrap();
+        let mut stdout = child.stdout.take().unwrap();
+        assert!(child.wait().unwrap().success());
+        let output = read_to_end(|buf| stdout.read(buf));
+        let output = std::string::String::from_utf8(output).unwrap();
+        assert!(output.contains("CapEff:\t0000000000000000\n"), "{output}");
+        assert!(output.contains("NoNewPrivs:\t0\n"), "{output}");
+
+        // the capabilities to keep must be held by the parent
+        let result = options()
+            .with_uid(65534)
+            .with_bounding_set(Capabilities::empty())
+            .with_ambient_capabilities(Capabilities::NET_BIND_SERVICE)
+            .spawn(&elf_bytes);
+        assert!(matches!(
+            result,
+            Err(RunError::CapabilitiesError(Errno::EPERM))
+        ));
+    }
+
+    #[test]
+    fn test_spawn_exec_backends() {
+        let elf_bytes = std::fs::read("/bin/sh").unwrap();
+        let args = ["-c", "exit 5"];
+        for backend in [
+            ExecBackend::Auto,
+            ExecBackend::Execveat,
+            ExecBackend::ProcSelfFd,
+        ] {
+            let options = RunOptions::new()
+                .with_args(&args)
+                .with_exec_backend(backend);
+            let status = run_with_options(&elf_bytes, options).unwrap();
+            assert_eq!(status.code(), Some(5), "backend {backend:?}");
+        }
+    }
+
+    /// Spawn `options` with its stdout piped once per spawn method, and pass each child and its
+    /// stdout to `check`, so that the child setup is covered on the vfork path as well.
+    fn spawn_each_method(
+        elf_bytes: &[u8],
+        options: RunOptions<'_>,
+        mut check: impl FnMut(Child, ChildStdout),
+    ) {
+        for method in [SpawnMethod::Fork, SpawnMethod::Vfork] {
+            let mut child = options
+                .clone()
+                .with_spawn_method(method)
+                .with_stdout(Stdio::Piped)
+                .spawn(elf_bytes)
+                .unwrap_or_else(|error| panic!("{method:?}: {error}"));
+            let stdout = child.stdout.take().unwrap();
+            check(child, stdout);
+        }
+    }
+
+    fn read_to_end(mut read: impl FnMut(&mut [u8]) -> Result<usize, Errno>) -> std::vec::Vec<u8> {
+        let mut output = std::vec::Vec::new();
+        let mut buf = [0u8; 64];
+        loop {
+            match read(&mut buf).unwrap() {
+                0 => return output,
+                n => output.extend_from_slice(&buf[..n]),
+            }
+        }
+    }
+
+    #[test]
+    fn test_spawn_piped_stdio() {
+        let elf_bytes = std::fs::read("/bin/sh").unwrap();
+        let args = ["-c", "read line; echo \"out:$line\"; echo err >&2"];
+        let mut child = RunOptions::new()
+            .with_args(&args)
+            .with_stdin(Stdio::Piped)
+            .with_stdout(Stdio::Piped)
+            .with_stderr(Stdio::Piped)
+            .spawn(&elf_bytes)
+            .unwrap();
+        child.stdin.as_mut().unwrap().write_all(b"hello\n").unwrap();
+        let stdout = child.stdout.as_mut().unwrap();
+        assert_eq!(read_to_end(|buf| stdout.read(buf)), b"out:hello\n");
+        let stderr = child.stderr.as_mut().unwrap();
+        assert_eq!(read_to_end(|buf| stderr.read(buf)), b"err\n");
+        assert!(child.wait().unwrap().success());
+        assert!(child.stdin.is_none());
+    }
+
+    #[test]
+    fn test_run_stdin_bytes() {
+        let elf_bytes = std::fs::read("/bin/sh").unwrap();
+        // a regular file rather than a pipe, read twice from the start
+        let script = r#"test -f /dev/stdin && read a && test "$a" = one && exec 0</dev/stdin && read a && test "$a" = one"#;
+        let args = ["-c", script];
+        let options = RunOptions::new()
+            .with_args(&args)
+            .with_stdin(Stdio::Piped)
+            .with_stdin_bytes(b"one\ntwo\n");
+        assert!(run_with_options(&elf_bytes, options).unwrap().success());
+    }
+
+    #[test]
+    fn test_spawn_null_and_fd_stdio() {
+        let elf_bytes = std::fs::read("/bin/sh").unwrap();
+        // stdin reads end of file, stderr is merged into stdout
+        let args = ["-c", "cat; echo err >&2"];
+        let mut child = RunOptions::new()
+            .with_args(&args)
+            .with_stdin(Stdio::Null)
+            .with_stdout(Stdio::Piped)
+            .with_stderr(Stdio::Fd(1))
+            .spawn(&elf_bytes)
+            .unwrap();
+        assert!(child.stdin.is_none() && child.stderr.is_none());
+        let stdout = child.stdout.as_mut().unwrap();
+        assert_eq!(read_to_end(|buf| stdout.read(buf)), b"err\n");
+        assert!(child.wait().unwrap().success());
+    }
+
+    #[test]
+    fn test_spawn_stdio_error_reported_to_parent() {
+        let elf_bytes = std::fs::read("/bin/sh").unwrap();
+        let result = RunOptions::new()
+            .with_stdout(Stdio::Fd(4096))
+            .spawn(&elf_bytes);
+        assert!(matches!(result, Err(RunError::StdioError(Errno::EBADF))));
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_output() {
+        let elf_bytes = std::fs::read("/bin/sh").unwrap();
+        // more than a pipe buffer on stderr while stdout is still open
+        let args = ["-c", "head -c 200000 /dev/zero >&2; echo out; exit 4"];
+        let output = RunOptions::new()
+            .with_args(&args)
+            .output(&elf_bytes)
+            .unwrap();
+        assert_eq!(output.status.code(), Some(4));
+        assert_eq!(output.stdout, b"out\n");
+        assert_eq!(output.stderr.len(), 200000);
+    }
+
+    #[test]
+    fn test_run_timeout() {
+        let elf_bytes = std::fs::read("/bin/sleep").unwrap();
+        let args = ["10"];
+        let options = RunOptions::new()
+            .with_args(&args)
+            .with_timeout(Duration::from_millis(100));
+        let result = run_with_options(&elf_bytes, options);
+        assert!(matches!(result, Err(RunError::TimedOut(status)) if status.signal() == Some(9)));
+
+        let args = ["0"];
+        let options = RunOptions::new()
+            .with_args(&args)
+            .with_timeout(Duration::from_secs(10));
+        assert!(run_with_options(&elf_bytes, options).unwrap().success());
+    }
+
+    #[test]
+    fn test_run_timeout_kill_sequence() {
+        let elf_bytes = std::fs::read("/bin/sh").unwrap();
+        // exits on its own on SIGTERM (15)
+        let args = ["-c", "trap 'exit 7' TERM; while :; do sleep 0.01; done"];
+        let sequence = [KillStep::new(15, Duration::from_secs(10))];
+        let options = RunOptions::new()
+            .with_args(&args)
+            .with_timeout(Duration::from_millis(100))
+            .with_kill_sequence(&sequence);
+        let result = run_with_options(&elf_bytes, options);
+        assert!(matches!(result, Err(RunError::TimedOut(status)) if status.code() == Some(7)));
+
+        // ignores SIGTERM, killed after the grace period
+        let args = ["-c", "trap '' TERM; while :; do sleep 0.01; done"];
+        let sequence = [KillStep::new(15, Duration::from_millis(100))];
+        let options = RunOptions::new()
+            .with_args(&args)
+            .with_timeout(Duration::from_millis(100))
+            .with_kill_sequence(&sequence);
+        let result = run_with_options(&elf_bytes, options);
+        assert!(matches!(result, Err(RunError::TimedOut(status)) if status.signal() == Some(9)));
+    }
+
+    #[test]
+    fn test_spawn_wait_timeout() {
+        let elf_bytes = std::fs::read("/bin/sleep").unwrap();
+        let args = ["10"];
+        let mut child = RunOptions::new()
+            .with_args(&args)
+            .spawn(&elf_bytes)
+            .unwrap();
+        assert_eq!(child.wait_timeout(Duration::from_millis(50)).unwrap(), None);
+        child.kill().unwrap();
+        let status = child.wait_timeout(Duration::from_secs(10)).unwrap();
+        assert_eq!(status.and_then(|status| status.signal()), Some(9));
+
+        // too far away to be a deadline: waits until the child exits
+        let args = ["0.1"];
+        let mut child = RunOptions::new()
+            .with_args(&args)
+            .spawn(&elf_bytes)
+            .unwrap();
+        let status = child.wait_timeout(Duration::MAX).unwrap();
+        assert!(status.unwrap().success());
+        let options = RunOptions::new()
+            .with_args(&args)
+            .with_timeout(Duration::MAX);
+        assert!(run_with_options(&elf_bytes, options).unwrap().success());
+    }
+
+    #[test]
+    fn test_child_error_round_trip() {
+        let error = ChildError {
+            stage: ChildStage::Stdio,
+            errno: Errno::EBADF,
+        };
+        assert_eq!(ChildError::from_bytes(error.to_bytes()), error);
+        assert!(matches!(
+            RunError::from(error),
+            RunError::StdioError(Errno::EBADF)
+        ));
+        for stage in [
+            ChildStage::PreExec,
+            ChildStage::FileActions,
+            ChildStage::CurrentDir,
+            ChildStage::Credentials,
+            ChildStage::Rlimits,
+            ChildStage::Namespaces,
+            ChildStage::Seccomp,
+            ChildStage::Landlock,
+            ChildStage::Capabilities,
+        ] {
+            let error = ChildError {
+                stage,
+                errno: Errno::EPERM,
+            };
+            assert_eq!(ChildError::from_bytes(error.to_bytes()), error);
+        }
+    }
+
+    #[test]
+    fn test_spawn_invalid_elf() {
+        let result = RunOptions::new().spawn(b"not an elf file");
+        assert!(matches!(result, Err(RunError::InvalidElfFormat)));
+    }
+
+    #[test]
+    fn test_spawn_exec_failure_reported_to_parent() {
+        // Valid magic bytes but garbage headers: the kernel rejects it with ENOEXEC
+        let mut bogus_elf = std::vec![0u8; 64];
+        bogus_elf[..4].copy_from_slice(b"\x7fELF");
+        let result = RunOptions::new().spawn(&bogus_elf);
+        assert!(matches!(result, Err(RunError::ExecError(Errno::ENOEXEC))));
+    }
+
     // New error types tests
     #[test]
     fn test_new_error_types() {
@@ -671,4 +3194,17 @@ mod tests {
             assert!(!debug_str.is_empty());
         }
     }
+
+    #[test]
+    fn test_error_display() {
+        let error = RunError::ExecError(Errno::ETXTBSY);
+        assert_eq!(
+            format!("{error}"),
+            "execve() failed: ETXTBSY: Text file busy"
+        );
+        let error = RunError::TooManyArgs;
+        assert_eq!(format!("{error}"), "too many arguments (limit: 32)");
+        let boxed: std::boxed::Box<dyn core::error::Error> = std::boxed::Box::new(error);
+        assert!(boxed.source().is_none());
+    }
 }
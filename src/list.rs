@@ -0,0 +1,126 @@
+#[cfg(not(feature = "alloc"))]
+use core::mem::MaybeUninit;
+
+#[cfg(feature = "alloc")]
+use alloc::vec::Vec;
+
+/// List of option values collected by the [`RunOptions`](crate::RunOptions) builder.
+///
+/// Without the `alloc` feature, items are stored inline and at most `N` of them are kept:
+/// pushing more marks the list as overflowed, which is reported when the payload is started.
+#[derive(Clone, Copy)]
+#[cfg(not(feature = "alloc"))]
+pub(crate) struct List<T: Copy, const N: usize> {
+    items: [MaybeUninit<T>; N],
+    len: usize,
+    overflowed: bool,
+}
+
+#[cfg(not(feature = "alloc"))]
+impl<T: Copy, const N: usize> List<T, N> {
+    pub(crate) const fn new() -> Self {
+        Self {
+            items: [MaybeUninit::uninit(); N],
+            len: 0,
+            overflowed: false,
+        }
+    }
+
+    pub(crate) fn push(&mut self, item: T) {
+        match self.items.get_mut(self.len) {
+            Some(slot) => {
+                slot.write(item);
+                self.len += 1;
+            }
+            None => self.overflowed = true,
+        }
+    }
+
+    pub(crate) fn as_slice(&self) -> &[T] {
+        // Safety: the first `len` items have been initialized by `push`
+        unsafe { core::slice::from_raw_parts(self.items.as_ptr().cast(), self.len) }
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.len = 0;
+        self.overflowed = false;
+    }
+
+    /// Returns `true` if more than `N` items were pushed since the last `clear`.
+    pub(crate) fn overflowed(&self) -> bool {
+        self.overflowed
+    }
+}
+
+/// List of option values collected by the [`RunOptions`](crate::RunOptions) builder.
+///
+/// With the `alloc` feature, the list grows on the heap and never overflows.
+#[derive(Clone)]
+#[cfg(feature = "alloc")]
+pub(crate) struct List<T: Copy, const N: usize> {
+    items: Vec<T>,
+}
+
+#[cfg(feature = "alloc")]
+impl<T: Copy, const N: usize> List<T, N> {
+    pub(crate) const fn new() -> Self {
+        Self { items: Vec::new() }
+    }
+
+    pub(crate) fn push(&mut self, item: T) {
+        self.items.push(item);
+    }
+
+    pub(crate) fn as_slice(&self) -> &[T] {
+        &self.items
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.items.clear();
+    }
+}
+
+impl<T: Copy, const N: usize> Default for List<T, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_list_push_and_clear() {
+        let mut list: List<u32, 2> = List::new();
+        assert!(list.as_slice().is_empty());
+        list.push(1);
+        list.push(2);
+        assert_eq!(list.as_slice(), &[1, 2]);
+        list.clear();
+        assert!(list.as_slice().is_empty());
+    }
+
+    #[cfg(not(feature = "alloc"))]
+    #[test]
+    fn test_list_overflow() {
+        let mut list: List<u32, 2> = List::new();
+        for i in 0..3 {
+            list.push(i);
+        }
+        assert!(list.overflowed());
+        assert_eq!(list.as_slice(), &[0, 1]);
+        list.clear();
+        assert!(!list.overflowed());
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_list_grows() {
+        let mut list: List<u32, 2> = List::new();
+        for i in 0..3 {
+            list.push(i);
+        }
+        assert_eq!(list.as_slice(), &[0, 1, 2]);
+    }
+}
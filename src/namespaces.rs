@@ -0,0 +1,326 @@
+//! Namespace isolation of the payload, see [`RunOptions::with_namespaces`](crate::RunOptions::with_namespaces).
+//!
+//! In fork mode the child is created directly in the new namespaces by `clone3`. The parent then
+//! writes the uid and gid maps of a new user namespace, while the child waits on a pipe before
+//! going on with its setup. In replace mode, the current process joins new namespaces with
+//! `unshare` and maps itself.
+
+use core::ops::{BitOr, BitOrAssign};
+
+use crate::Errno;
+use crate::fd::{AT_FDCWD, Fd, O_CLOEXEC};
+use crate::syscalls::{self, cvt};
+
+const O_WRONLY: i32 = 0o1;
+const MS_REC: u64 = 0x4000;
+const MS_PRIVATE: u64 = 1 << 18;
+/// The kernel rejects id maps written in more than one `write()`, or larger than a page
+const ID_MAP_LEN: usize = 4096;
+
+/// A set of namespaces the payload gets its own instance of.
+///
+/// # Example
+/// ```rust
+/// use memfd_runner::Namespaces;
+///
+/// let namespaces = Namespaces::USER | Namespaces::PID | Namespaces::NET;
+/// assert!(namespaces.contains(Namespaces::NET));
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Namespaces(u64);
+
+impl Namespaces {
+    /// `CLONE_NEWUSER`: own user and group ids and capabilities, see [`IdMap`]
+    pub const USER: Self = Self(0x10000000);
+    /// `CLONE_NEWPID`: own pid space, where the payload is pid 1
+    pub const PID: Self = Self(0x20000000);
+    /// `CLONE_NEWNS`: own mount table, with every mount made private to it
+    pub const MOUNT: Self = Self(0x00020000);
+    /// `CLONE_NEWNET`: own network stack, with only a loopback interface, which is down
+    pub const NET: Self = Self(0x40000000);
+    /// `CLONE_NEWIPC`: own System V IPC objects and POSIX message queues
+    pub const IPC: Self = Self(0x08000000);
+    /// `CLONE_NEWUTS`: own hostname and domain name
+    pub const UTS: Self = Self(0x04000000);
+    /// `CLONE_NEWCGROUP`: own view of the cgroup hierarchy, rooted at its current cgroup
+    pub const CGROUP: Self = Self(0x02000000);
+
+    /// No new namespace: the payload shares the namespaces of the current process.
+    pub const fn empty() -> Self {
+        Self(0)
+    }
+
+    /// Returns `true` if no namespace is set.
+    pub const fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    /// Returns `true` if all the namespaces of `other` are set.
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// The `CLONE_NEW*` flags of the set.
+    pub const fn bits(self) -> u64 {
+        self.0
+    }
+}
+
+impl BitOr for Namespaces {
+    type Output = Self;
+
+    fn bitor(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+}
+
+impl BitOrAssign for Namespaces {
+    fn bitor_assign(&mut self, other: Self) {
+        self.0 |= other.0;
+    }
+}
+
+/// A range of ids of a new user namespace, mapped to ids of the current one
+/// (a line of `/proc/<pid>/uid_map` or `gid_map`).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IdMap {
+    /// First id inside the namespace
+    pub inside: u32,
+    /// First id outside the namespace
+    pub outside: u32,
+    /// Number of consecutive ids mapped
+    pub count: u32,
+}
+
+impl IdMap {
+    /// Maps `count` ids starting at `outside` to ids starting at `inside`.
+    pub const fn new(inside: u32, outside: u32, count: u32) -> Self {
+        Self {
+            inside,
+            outside,
+            count,
+        }
+    }
+}
+
+/// Content of an id map file, formatted before forking.
+#[derive(Debug)]
+pub(crate) struct IdMapBuf {
+    buf: [u8; ID_MAP_LEN],
+    len: usize,
+}
+
+impl IdMapBuf {
+    /// Format `maps`, or map the id `current` to itself when empty.
+    pub(crate) fn format(maps: &[IdMap], current: u32) -> Result<Self, Errno> {
+        let mut map = Self {
+            buf: [0; ID_MAP_LEN],
+            len: 0,
+        };
+        let default = [IdMap::new(current, current, 1)];
+        let maps = if maps.is_empty() { &default[..] } else { maps };
+        for entry in maps {
+            for (index, id) in [entry.inside, entry.outside, entry.count]
+                .into_iter()
+                .enumerate()
+            {
+                map.push_u32(id)?;
+                map.push(if index == 2 { b'\n' } else { b' ' })?;
+            }
+        }
+        Ok(map)
+    }
+
+    fn push(&mut self, byte: u8) -> Result<(), Errno> {
+        *self.buf.get_mut(self.len).ok_or(Errno::EINVAL)? = byte;
+        self.len += 1;
+        Ok(())
+    }
+
+    fn push_u32(&mut self, value: u32) -> Result<(), Errno> {
+        decimal(value.into(), &mut [0; 20])
+            .iter()
+            .try_for_each(|&digit| self.push(digit))
+    }
+
+    fn as_bytes(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+}
+
+/// Uid and gid maps of a new user namespace.
+#[derive(Debug)]
+pub(crate) struct IdMaps {
+    pub(crate) uid: IdMapBuf,
+    pub(crate) gid: IdMapBuf,
+}
+
+impl IdMaps {
+    /// Format the maps, mapping the current effective ids to themselves when none are given.
+    pub(crate) fn prepare(uid_map: &[IdMap], gid_map: &[IdMap]) -> Result<Self, Errno> {
+        let (euid, egid) = unsafe { (syscalls::geteuid(), syscalls::getegid()) };
+        Ok(Self {
+            uid: IdMapBuf::format(uid_map, euid as u32)?,
+            gid: IdMapBuf::format(gid_map, egid as u32)?,
+        })
+    }
+
+    /// Write the maps of the process `pid`, or of the calling process when `pid` is 0.
+    ///
+    /// Without `CAP_SETGID` over the parent namespace, the kernel only accepts a gid map once
+    /// `setgroups()` is denied in the namespace, which is then done before retrying.
+    pub(crate) fn write(&self, pid: i32) -> Result<(), Errno> {
+        write_proc_file(pid, b"uid_map", self.uid.as_bytes())?;
+        match write_proc_file(pid, b"gid_map", self.gid.as_bytes()) {
+            Err(Errno::EPERM) => {
+                write_proc_file(pid, b"setgroups", b"deny")?;
+                write_proc_file(pid, b"gid_map", self.gid.as_bytes())
+            }
+            result => result,
+        }
+    }
+}
+
+/// What the child does to enter its namespaces, in the child (or the current process in
+/// replace mode).
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct NamespaceSetup<'a> {
+    /// All the namespaces requested
+    pub(crate) namespaces: Namespaces,
+    /// Namespaces to create with `unshare()`, those not already created by `clone3`
+    pub(crate) unshare: Namespaces,
+    /// Maps to write for a new user namespace
+    pub(crate) id_maps: Option<&'a IdMaps>,
+    /// Read and write ends of the pipe on which the parent sends a byte once it wrote the id
+    /// maps
+    pub(crate) maps_written: Option<(i32, i32)>,
+}
+
+impl NamespaceSetup<'_> {
+    /// Enter the namespaces. Only issues system calls.
+    pub(crate) fn apply(&self) -> Result<(), Errno> {
+        if !self.unshare.is_empty() {
+            cvt(unsafe { syscalls::unshare(self.unshare.bits()) })?;
+            if let Some(id_maps) = self
+                .id_maps
+                .filter(|_| self.unshare.contains(Namespaces::USER))
+            {
+                id_maps.write(0)?;
+            }
+        }
+        if let Some((fd, write_end)) = self.maps_written {
+            // only the parent holds the write end then, the read sees EOF once it is gone
+            unsafe { syscalls::close(write_end) };
+            let mut written = [0u8; 1];
+            loop {
+                match cvt(unsafe { syscalls::read(fd, written.as_mut_ptr(), 1) }) {
+                    Ok(1) => break,
+                    Err(Errno::EINTR) => continue,
+                    // the parent exited or closed the pipe without writing the maps
+                    _ => return Err(Errno::ECANCELED),
+                }
+            }
+        }
+        if self.namespaces.contains(Namespaces::MOUNT) {
+            // keep the mounts of the payload from propagating back to the parent namespace
+            cvt(unsafe {
+                syscalls::mount(
+                    b"none\0",
+                    b"/\0",
+                    core::ptr::null_mut(),
+                    MS_REC | MS_PRIVATE,
+                    core::ptr::null_mut(),
+                )
+            })?;
+        }
+        Ok(())
+    }
+}
+
+/// Format `value` in decimal at the end of `digits`.
+pub(crate) fn decimal(value: u64, digits: &mut [u8; 20]) -> &[u8] {
+    let mut start = digits.len();
+    let mut rest = value;
+    loop {
+        start -= 1;
+        digits[start] = b'0' + (rest % 10) as u8;
+        rest /= 10;
+        if rest == 0 {
+            return &digits[start..];
+        }
+    }
+}
+
+/// Write `content` to `/proc/<pid>/<name>` (or `/proc/self/<name>`) in a single `write()`.
+fn write_proc_file(pid: i32, name: &[u8], content: &[u8]) -> Result<(), Errno> {
+    let mut path = [0u8; 32];
+    let mut len = 0;
+    let mut append = |part: &[u8]| {
+        path[len..len + part.len()].copy_from_slice(part);
+        len += part.len();
+    };
+    append(b"/proc/");
+    match pid {
+        0 => append(b"self"),
+        pid => append(decimal(pid as u64, &mut [0; 20])),
+    }
+    append(b"/");
+    append(name);
+    let fd = cvt(unsafe { syscalls::openat(AT_FDCWD, &path[..=len], O_WRONLY | O_CLOEXEC, 0) })?;
+    let fd = Fd::from_raw(fd);
+    if fd.write(content)? != content.len() {
+        return Err(Errno::EIO);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_maps_never_written() {
+        // the parent is gone: the child must not wait for the maps forever
+        let (read_end, write_end) = crate::fd::pipe().unwrap();
+        let setup = NamespaceSetup {
+            namespaces: Namespaces::empty(),
+            unshare: Namespaces::empty(),
+            id_maps: None,
+            maps_written: Some((read_end.raw(), write_end.into_raw())),
+        };
+        assert_eq!(setup.apply(), Err(Errno::ECANCELED));
+    }
+
+    #[test]
+    fn test_namespaces_flags() {
+        let namespaces = Namespaces::USER | Namespaces::NET;
+        assert!(namespaces.contains(Namespaces::USER));
+        assert!(!namespaces.contains(Namespaces::PID));
+        assert!(!namespaces.contains(Namespaces::USER | Namespaces::PID));
+        assert!(Namespaces::empty().is_empty());
+        let mut all = Namespaces::empty();
+        for namespace in [
+            Namespaces::USER,
+            Namespaces::PID,
+            Namespaces::MOUNT,
+            Namespaces::NET,
+            Namespaces::IPC,
+            Namespaces::UTS,
+            Namespaces::CGROUP,
+        ] {
+            assert_eq!(all.bits() & namespace.bits(), 0);
+            all |= namespace;
+        }
+    }
+
+    #[test]
+    fn test_format_id_maps() {
+        let maps = [IdMap::new(0, 1000, 1), IdMap::new(1, 100000, 65536)];
+        let map = IdMapBuf::format(&maps, 1000).unwrap();
+        assert_eq!(map.as_bytes(), b"0 1000 1\n1 100000 65536\n");
+        let map = IdMapBuf::format(&[], 4294967294).unwrap();
+        assert_eq!(map.as_bytes(), b"4294967294 4294967294 1\n");
+        let too_many = [IdMap::new(u32::MAX, u32::MAX, u32::MAX); 200];
+        assert_eq!(IdMapBuf::format(&too_many, 0).unwrap_err(), Errno::EINVAL);
+    }
+}
@@ -0,0 +1,104 @@
+//! Capture of the payload output, see [`RunOptions::output`](crate::RunOptions::output).
+
+use alloc::vec::Vec;
+
+use crate::fd::{Fd, POLLIN, PollFd};
+use crate::syscalls::{self, cvt};
+use crate::{Errno, ExitStatus};
+
+/// The output of a finished payload, returned by [`RunOptions::output`](crate::RunOptions::output)
+/// and [`Child::wait_with_output`](crate::Child::wait_with_output).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Output {
+    /// How the payload terminated
+    pub status: ExitStatus,
+    /// Everything the payload wrote to its stdout
+    pub stdout: Vec<u8>,
+    /// Everything the payload wrote to its stderr
+    pub stderr: Vec<u8>,
+}
+
+#[cfg(feature = "std")]
+impl From<Output> for std::process::Output {
+    fn from(output: Output) -> Self {
+        std::process::Output {
+            status: output.status.into(),
+            stdout: output.stdout,
+            stderr: output.stderr,
+        }
+    }
+}
+
+/// Read `stdout` and `stderr` until both reach end of file.
+///
+/// Both pipes are polled together, so a payload filling one of them while the other is being
+/// read cannot deadlock.
+pub(crate) fn read2(stdout: Option<&Fd>, stderr: Option<&Fd>) -> Result<(Vec<u8>, Vec<u8>), Errno> {
+    let mut outputs = (Vec::new(), Vec::new());
+    // poll() ignores negative descriptors, which marks the pipes already closed
+    let pollfd = |fd: Option<&Fd>| PollFd {
+        fd: fd.map_or(-1, Fd::raw),
+        events: POLLIN,
+        revents: 0,
+    };
+    let mut fds = [pollfd(stdout), pollfd(stderr)];
+    let pipes = [stdout, stderr];
+
+    while fds.iter().any(|fd| fd.fd >= 0) {
+        let ret = unsafe { syscalls::poll(fds.as_mut_ptr().cast(), fds.len() as u32, -1) };
+        match cvt(ret) {
+            Ok(_) => {}
+            Err(Errno::EINTR) => continue,
+            Err(errno) => return Err(errno),
+        }
+        for (index, (fd, pipe)) in fds.iter_mut().zip(pipes).enumerate() {
+            let Some(pipe) = pipe.filter(|_| fd.fd >= 0 && fd.revents != 0) else {
+                continue;
+            };
+            let output = match index {
+                0 => &mut outputs.0,
+                _ => &mut outputs.1,
+            };
+            if read_chunk(pipe, output)? == 0 {
+                fd.fd = -1;
+            }
+        }
+    }
+    Ok(outputs)
+}
+
+/// Append one `read()` worth of data to `output`. Returns 0 at end of file.
+fn read_chunk(pipe: &Fd, output: &mut Vec<u8>) -> Result<usize, Errno> {
+    let mut chunk = [0u8; 4096];
+    let n = pipe.read(&mut chunk)?;
+    output.extend_from_slice(&chunk[..n]);
+    Ok(n)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fd;
+
+    #[test]
+    fn test_read2_both_pipes() {
+        let (stdout_read, stdout_write) = fd::pipe().unwrap();
+        let (stderr_read, stderr_write) = fd::pipe().unwrap();
+        stdout_write.write(b"out").unwrap();
+        stderr_write.write(b"err").unwrap();
+        drop((stdout_write, stderr_write));
+        let (stdout, stderr) = read2(Some(&stdout_read), Some(&stderr_read)).unwrap();
+        assert_eq!(stdout, b"out");
+        assert_eq!(stderr, b"err");
+    }
+
+    #[test]
+    fn test_read2_single_pipe() {
+        let (stderr_read, stderr_write) = fd::pipe().unwrap();
+        stderr_write.write(b"err").unwrap();
+        drop(stderr_write);
+        let (stdout, stderr) = read2(None, Some(&stderr_read)).unwrap();
+        assert!(stdout.is_empty());
+        assert_eq!(stderr, b"err");
+    }
+}
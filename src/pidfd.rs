@@ -0,0 +1,188 @@
+//! Process file descriptors, which refer to a child without the races of numeric pids.
+
+use crate::fd::Fd;
+use crate::syscalls::{self, cvt};
+use crate::{Errno, Namespaces};
+
+const CLONE_PIDFD: u64 = 0x1000;
+const CLONE_INTO_CGROUP: u64 = 0x200000000;
+const SIGCHLD: u64 = 17;
+
+/// A file descriptor referring to a child process (pidfd, Linux 5.3+).
+///
+/// Unlike a pid, a pidfd can never refer to another process once the child was reaped, so
+/// signals sent through it cannot hit a recycled pid. It becomes readable when the child exits,
+/// which allows registering it in an `epoll`/`poll` loop.
+#[derive(Debug)]
+pub struct PidFd {
+    fd: Fd,
+}
+
+impl PidFd {
+    pub(crate) fn from_raw(fd: i32) -> Self {
+        Self {
+            fd: Fd::from_raw(fd),
+        }
+    }
+
+    /// Sends `signal` to the process with `pidfd_send_signal`.
+    pub fn send_signal(&self, signal: i32) -> Result<(), Errno> {
+        cvt(unsafe {
+            syscalls::pidfd_send_signal(self.fd.raw(), signal, core::ptr::null_mut(), 0)
+        })?;
+        Ok(())
+    }
+
+    /// Returns the raw file descriptor.
+    pub fn as_raw_fd(&self) -> i32 {
+        self.fd.raw()
+    }
+
+    pub(crate) fn fd(&self) -> &Fd {
+        &self.fd
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::os::fd::AsRawFd for PidFd {
+    fn as_raw_fd(&self) -> std::os::fd::RawFd {
+        self.fd.raw()
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::os::fd::AsFd for PidFd {
+    fn as_fd(&self) -> std::os::fd::BorrowedFd<'_> {
+        // Safety: the descriptor stays open as long as `self` is alive
+        unsafe { std::os::fd::BorrowedFd::borrow_raw(self.fd.raw()) }
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<PidFd> for std::os::fd::OwnedFd {
+    fn from(pidfd: PidFd) -> Self {
+        use std::os::fd::FromRawFd;
+        // Safety: ownership of the descriptor is transferred
+        unsafe { std::os::fd::OwnedFd::from_raw_fd(pidfd.fd.into_raw()) }
+    }
+}
+
+/// `struct clone_args`
+#[repr(C)]
+#[derive(Default)]
+struct CloneArgs {
+    flags: u64,
+    pidfd: u64,
+    child_tid: u64,
+    parent_tid: u64,
+    exit_signal: u64,
+    stack: u64,
+    stack_size: u64,
+    tls: u64,
+    set_tid: u64,
+    set_tid_size: u64,
+    cgroup: u64,
+}
+
+/// Fork the current process into new `namespaces` and the `cgroup` directory, if any, also
+/// returning a pidfd for the child in the parent.
+///
+/// Uses `clone3(CLONE_PIDFD)` (Linux 5.3+), falling back to `clone` followed by `pidfd_open`
+/// without a cgroup, which requires `CLONE_INTO_CGROUP` (Linux 5.7+). The pidfd is `None` on
+/// kernels without pidfd support. Returns a pid of 0 in the child.
+pub(crate) fn fork(
+    namespaces: Namespaces,
+    cgroup: Option<i32>,
+) -> Result<(i32, Option<PidFd>), Errno> {
+    let mut pidfd: i32 = -1;
+    let mut args = CloneArgs {
+        flags: CLONE_PIDFD | namespaces.bits(),
+        pidfd: &mut pidfd as *mut i32 as u64,
+        exit_signal: SIGCHLD,
+        ..CloneArgs::default()
+    };
+    if let Some(cgroup) = cgroup {
+        args.flags |= CLONE_INTO_CGROUP;
+        args.cgroup = cgroup as u64;
+    }
+    let ret =
+        unsafe { syscalls::clone3((&mut args as *mut CloneArgs).cast(), size_of::<CloneArgs>()) };
+    match cvt(ret) {
+        Ok(0) => Ok((0, None)),
+        Ok(pid) => Ok((pid, Some(PidFd::from_raw(pidfd)))),
+        // clone3 is missing, or filtered by a container runtime
+        Err(Errno::ENOSYS | Errno::EPERM) if cgroup.is_none() => fork_then_open(namespaces),
+        Err(errno) => Err(errno),
+    }
+}
+
+/// `fork` followed by `pidfd_open`, which cannot race with pid reuse as long as the child is
+/// not reaped. The fork is a plain `clone`, which can also create the `namespaces`.
+fn fork_then_open(namespaces: Namespaces) -> Result<(i32, Option<PidFd>), Errno> {
+    let null = core::ptr::null_mut();
+    let pid = cvt(unsafe { syscalls::clone(namespaces.bits() | SIGCHLD, null, null, null, 0) })?;
+    if pid == 0 {
+        return Ok((0, None));
+    }
+    Ok((pid, open(pid).ok()))
+}
+
+/// Opens a pidfd for the process `pid` with `pidfd_open`.
+pub(crate) fn open(pid: i32) -> Result<PidFd, Errno> {
+    let fd = cvt(unsafe { syscalls::pidfd_open(pid, 0) })?;
+    Ok(PidFd::from_raw(fd))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn exit_child(pid: i32) {
+        if pid == 0 {
+            unsafe { syscalls::exit_group(0) };
+        }
+    }
+
+    fn reap(pid: i32) -> i32 {
+        let mut status = 0i32;
+        let ret = unsafe {
+            syscalls::wait4(
+                pid,
+                (&mut status as *mut i32).cast(),
+                0,
+                core::ptr::null_mut(),
+            )
+        };
+        assert_eq!(ret, pid);
+        status
+    }
+
+    #[test]
+    fn test_clone3_pidfd() {
+        let (pid, pidfd) = fork(Namespaces::empty(), None).unwrap();
+        exit_child(pid);
+        assert!(pidfd.unwrap().as_raw_fd() >= 0);
+        assert_eq!(reap(pid), 0);
+    }
+
+    #[test]
+    fn test_fork_then_open() {
+        let (pid, pidfd) = fork_then_open(Namespaces::empty()).unwrap();
+        exit_child(pid);
+        assert!(pidfd.is_some());
+        assert_eq!(reap(pid), 0);
+    }
+
+    #[test]
+    fn test_send_signal() {
+        let (pid, pidfd) = fork(Namespaces::empty(), None).unwrap();
+        if pid == 0 {
+            // the child waits to be killed
+            loop {
+                core::hint::spin_loop();
+            }
+        }
+        pidfd.unwrap().send_signal(9).unwrap();
+        assert_eq!(reap(pid), 9);
+    }
+}
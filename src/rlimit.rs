@@ -0,0 +1,128 @@
+//! Resource limits of the payload, see [`RunOptions::with_rlimit`](crate::RunOptions::with_rlimit).
+
+use crate::Errno;
+use crate::syscalls::{self, cvt};
+
+/// Value of a limit which does not restrict the resource.
+pub const RLIM_INFINITY: u64 = u64::MAX;
+
+/// A resource which can be limited with [`RunOptions::with_rlimit`](crate::RunOptions::with_rlimit).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Resource {
+    /// `RLIMIT_AS`: size of the virtual address space, in bytes
+    As,
+    /// `RLIMIT_CPU`: CPU time, in seconds. `SIGXCPU` is sent at the soft limit, `SIGKILL` at the
+    /// hard limit.
+    Cpu,
+    /// `RLIMIT_NOFILE`: one more than the highest file descriptor number
+    Nofile,
+    /// `RLIMIT_NPROC`: number of processes of the real user id
+    Nproc,
+    /// `RLIMIT_CORE`: size of core dumps, in bytes. 0 disables them.
+    Core,
+    /// `RLIMIT_FSIZE`: size of the files the payload writes, in bytes. Going over sends `SIGXFSZ`.
+    Fsize,
+    /// `RLIMIT_STACK`: size of the main thread stack, in bytes
+    Stack,
+}
+
+impl Resource {
+    pub(crate) const COUNT: usize = 7;
+
+    /// The `RLIMIT_*` number of the resource.
+    pub const fn raw(self) -> u32 {
+        match self {
+            Resource::Cpu => 0,
+            Resource::Fsize => 1,
+            Resource::Stack => 3,
+            Resource::Core => 4,
+            Resource::Nproc => 6,
+            Resource::Nofile => 7,
+            Resource::As => 9,
+        }
+    }
+
+    pub(crate) const fn index(self) -> usize {
+        self as usize
+    }
+
+    const ALL: [Resource; Self::COUNT] = [
+        Resource::As,
+        Resource::Cpu,
+        Resource::Nofile,
+        Resource::Nproc,
+        Resource::Core,
+        Resource::Fsize,
+        Resource::Stack,
+    ];
+}
+
+/// `struct rlimit64`
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct Limit {
+    soft: u64,
+    hard: u64,
+}
+
+impl Limit {
+    pub(crate) const fn new(soft: u64, hard: u64) -> Self {
+        Self { soft, hard }
+    }
+}
+
+/// The limits configured on the payload, indexed by [`Resource::index`].
+pub(crate) type Limits = [Option<Limit>; Resource::COUNT];
+
+/// Apply `limits` to the calling process with `prlimit64`. Only issues system calls.
+pub(crate) fn apply(limits: &Limits) -> Result<(), Errno> {
+    for (resource, limit) in Resource::ALL.into_iter().zip(limits) {
+        let Some(limit) = limit else {
+            continue;
+        };
+        cvt(unsafe {
+            syscalls::prlimit64(
+                0,
+                resource.raw(),
+                (limit as *const Limit).cast_mut().cast(),
+                core::ptr::null_mut(),
+            )
+        })?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resource_index() {
+        for (index, resource) in Resource::ALL.into_iter().enumerate() {
+            assert_eq!(resource.index(), index);
+        }
+    }
+
+    #[test]
+    fn test_apply_current_limit() {
+        let mut current = Limit::new(0, 0);
+        let ret = unsafe {
+            syscalls::prlimit64(
+                0,
+                Resource::Nofile.raw(),
+                core::ptr::null_mut(),
+                (&mut current as *mut Limit).cast(),
+            )
+        };
+        assert_eq!(ret, 0);
+        let mut limits = [None; Resource::COUNT];
+        // setting the current limit again is always allowed
+        limits[Resource::Nofile.index()] = Some(current);
+        assert_eq!(apply(&limits), Ok(()));
+        // the soft limit cannot exceed the hard limit
+        limits[Resource::Nofile.index()] = Some(Limit::new(current.hard, current.soft));
+        if current.soft != current.hard {
+            assert_eq!(apply(&limits), Err(Errno::EINVAL));
+        }
+    }
+}
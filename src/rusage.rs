@@ -0,0 +1,141 @@
+use core::time::Duration;
+
+use crate::{CgroupStats, ExitStatus};
+
+/// Resources consumed by a payload, as collected by the kernel and reported by `wait4`.
+///
+/// The counters include the descendants of the payload which it waited for.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ResourceUsage {
+    /// CPU time spent in user mode
+    pub user_time: Duration,
+    /// CPU time spent in kernel mode
+    pub system_time: Duration,
+    /// Peak resident set size, in kibibytes
+    pub max_rss_kib: u64,
+    /// Page faults served without I/O
+    pub minor_faults: u64,
+    /// Page faults which required I/O
+    pub major_faults: u64,
+    /// Block input operations
+    pub block_reads: u64,
+    /// Block output operations
+    pub block_writes: u64,
+    /// Context switches because the payload waited for a resource
+    pub voluntary_context_switches: u64,
+    /// Context switches because the payload was preempted
+    pub involuntary_context_switches: u64,
+}
+
+/// Everything known about a finished payload: how it terminated, the resources it consumed and
+/// how long it ran.
+///
+/// Returned by [`run_with_report`](crate::run_with_report) and
+/// [`Child::wait_report`](crate::Child::wait_report).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RunReport {
+    /// How the payload terminated
+    pub status: ExitStatus,
+    /// Resources consumed by the payload
+    pub rusage: ResourceUsage,
+    /// Wall-clock time from just before the fork until the payload was reaped
+    pub wall_time: Duration,
+    /// Resources consumed by the [cgroup](crate::RunOptions::with_cgroup) of the payload, unless
+    /// it was spawned without one or its `cpu.stat` could not be read
+    pub cgroup: Option<CgroupStats>,
+}
+
+/// `struct timeval`
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct Timeval {
+    tv_sec: i64,
+    tv_usec: i64,
+}
+
+impl Timeval {
+    fn duration(self) -> Duration {
+        Duration::new(self.tv_sec as u64, (self.tv_usec * 1000) as u32)
+    }
+}
+
+/// `struct rusage`, filled by `wait4`
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+pub(crate) struct RawRusage {
+    utime: Timeval,
+    stime: Timeval,
+    maxrss: i64,
+    ixrss: i64,
+    idrss: i64,
+    isrss: i64,
+    minflt: i64,
+    majflt: i64,
+    nswap: i64,
+    inblock: i64,
+    oublock: i64,
+    msgsnd: i64,
+    msgrcv: i64,
+    nsignals: i64,
+    nvcsw: i64,
+    nivcsw: i64,
+}
+
+impl From<RawRusage> for ResourceUsage {
+    fn from(raw: RawRusage) -> Self {
+        Self {
+            user_time: raw.utime.duration(),
+            system_time: raw.stime.duration(),
+            max_rss_kib: raw.maxrss as u64,
+            minor_faults: raw.minflt as u64,
+            major_faults: raw.majflt as u64,
+            block_reads: raw.inblock as u64,
+            block_writes: raw.oublock as u64,
+            voluntary_context_switches: raw.nvcsw as u64,
+            involuntary_context_switches: raw.nivcsw as u64,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rusage_layout() {
+        // struct rusage is 2 timevals followed by 14 longs on x86_64
+        assert_eq!(size_of::<RawRusage>(), 144);
+    }
+
+    #[test]
+    fn test_rusage_decode() {
+        let raw = RawRusage {
+            utime: Timeval {
+                tv_sec: 1,
+                tv_usec: 500_000,
+            },
+            stime: Timeval {
+                tv_sec: 0,
+                tv_usec: 250,
+            },
+            maxrss: 2048,
+            minflt: 10,
+            majflt: 1,
+            nvcsw: 3,
+            nivcsw: 4,
+            ..RawRusage::default()
+        };
+        let usage = ResourceUsage::from(raw);
+        assert_eq!(usage.user_time, Duration::from_millis(1500));
+        assert_eq!(usage.system_time, Duration::from_micros(250));
+        assert_eq!(usage.max_rss_kib, 2048);
+        assert_eq!((usage.minor_faults, usage.major_faults), (10, 1));
+        assert_eq!(
+            (
+                usage.voluntary_context_switches,
+                usage.involuntary_context_switches
+            ),
+            (3, 4)
+        );
+    }
+}
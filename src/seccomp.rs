@@ -0,0 +1,1017 @@
+//! System call filtering of the payload, see
+//! [`RunOptions::with_seccomp`](crate::RunOptions::with_seccomp).
+//!
+//! A [`SeccompFilter`] holds [`Rule`]s, matched in order against every system call of the
+//! payload, and a default [`Action`] for the calls no rule matches. It is compiled to a classic
+//! BPF program before forking, then installed in the child right before `execve`.
+//!
+//! Rules take the system call numbers of the architecture the crate is built for, listed in
+//! [`x86_64`]. Calls made through another ABI (the 32-bit `int 0x80` entry or x32), whose numbers
+//! differ, kill the process so that they cannot bypass the rules.
+//!
+//! ```rust
+//! use memfd_runner::Errno;
+//! use memfd_runner::seccomp::{profile, x86_64, Action, ArgCmp, Cmp, Rule, SeccompFilter};
+//!
+//! // only allow writes to stdout and stderr
+//! let to_stdio = [ArgCmp::new(0, Cmp::Le(2))];
+//! let filter = SeccompFilter::new(Action::Allow)
+//!     .rules(profile::NO_NETWORK)
+//!     .rule(Rule::new(x86_64::WRITE, Action::Allow).with_args(&to_stdio))
+//!     .deny(x86_64::WRITE, Errno::EBADF);
+//! ```
+
+use core::ffi::CStr;
+
+use crate::fd::{self, Fd, IoVec, MsgSlot};
+use crate::list::List;
+use crate::syscalls::{self, cvt};
+use crate::{EMPTY_STRING, Errno};
+
+const AUDIT_ARCH_X86_64: u32 = 0xc000_003e;
+const X32_SYSCALL_BIT: u32 = 0x4000_0000;
+/// Largest program accepted by the kernel
+const BPF_MAXINSNS: usize = 4096;
+const MAX_RULES: usize = 64;
+
+// Offsets in `struct seccomp_data`, arguments are 64-bit little-endian
+const DATA_NR: u32 = 0;
+const DATA_ARCH: u32 = 4;
+const DATA_ARGS: u32 = 16;
+
+// Classic BPF opcodes
+const BPF_LD_W_ABS: u16 = 0x20;
+const BPF_ALU_AND_K: u16 = 0x54;
+const BPF_JMP_JEQ_K: u16 = 0x15;
+const BPF_JMP_JGT_K: u16 = 0x25;
+const BPF_JMP_JGE_K: u16 = 0x35;
+const BPF_RET_K: u16 = 0x06;
+
+const SECCOMP_RET_KILL_PROCESS: u32 = 0x8000_0000;
+const SECCOMP_RET_TRAP: u32 = 0x0003_0000;
+const SECCOMP_RET_ERRNO: u32 = 0x0005_0000;
+const SECCOMP_RET_USER_NOTIF: u32 = 0x7fc0_0000;
+const SECCOMP_RET_LOG: u32 = 0x7ffc_0000;
+const SECCOMP_RET_ALLOW: u32 = 0x7fff_0000;
+const SECCOMP_RET_DATA: u32 = 0xffff;
+
+pub(crate) const PR_SET_NO_NEW_PRIVS: i32 = 38;
+const SECCOMP_SET_MODE_FILTER: u32 = 1;
+const SECCOMP_FILTER_FLAG_NEW_LISTENER: u32 = 1 << 3;
+const SECCOMP_USER_NOTIF_FLAG_CONTINUE: u32 = 1;
+const SECCOMP_IOCTL_NOTIF_RECV: u64 = 0xc050_2100;
+const SECCOMP_IOCTL_NOTIF_SEND: u64 = 0xc018_2101;
+/// `_IOR`, which kernels still accept, rather than the `_IOW` fixed in Linux 5.8
+const SECCOMP_IOCTL_NOTIF_ID_VALID: u64 = 0x8008_2102;
+const AT_EMPTY_PATH: u64 = 0x1000;
+
+/// System call numbers of x86_64, for the rules of a [`SeccompFilter`].
+pub mod x86_64 {
+    pub const READ: u32 = 0;
+    pub const WRITE: u32 = 1;
+    pub const OPEN: u32 = 2;
+    pub const CLOSE: u32 = 3;
+    pub const MMAP: u32 = 9;
+    pub const MPROTECT: u32 = 10;
+    pub const IOCTL: u32 = 16;
+    pub const GETPID: u32 = 39;
+    pub const SOCKET: u32 = 41;
+    pub const CONNECT: u32 = 42;
+    pub const ACCEPT: u32 = 43;
+    pub const SENDMSG: u32 = 46;
+    pub const RECVMSG: u32 = 47;
+    pub const BIND: u32 = 49;
+    pub const LISTEN: u32 = 50;
+    pub const CLONE: u32 = 56;
+    pub const FORK: u32 = 57;
+    pub const VFORK: u32 = 58;
+    pub const EXECVE: u32 = 59;
+    pub const EXIT: u32 = 60;
+    pub const KILL: u32 = 62;
+    pub const UNAME: u32 = 63;
+    pub const MKDIR: u32 = 83;
+    pub const PTRACE: u32 = 101;
+    pub const CHROOT: u32 = 161;
+    pub const MOUNT: u32 = 165;
+    pub const UMOUNT2: u32 = 166;
+    pub const REBOOT: u32 = 169;
+    pub const INIT_MODULE: u32 = 175;
+    pub const DELETE_MODULE: u32 = 176;
+    pub const EXIT_GROUP: u32 = 231;
+    pub const OPENAT: u32 = 257;
+    pub const MKDIRAT: u32 = 258;
+    pub const UNSHARE: u32 = 272;
+    pub const PROCESS_VM_READV: u32 = 310;
+    pub const PROCESS_VM_WRITEV: u32 = 311;
+    pub const FINIT_MODULE: u32 = 313;
+    pub const BPF: u32 = 321;
+    pub const EXECVEAT: u32 = 322;
+    pub const IO_URING_SETUP: u32 = 425;
+    pub const CLONE3: u32 = 435;
+    pub const PIDFD_GETFD: u32 = 438;
+}
+
+/// Built-in sets of rules, to add to a filter with [`SeccompFilter::rules`].
+pub mod profile {
+    use super::{Action, ArgCmp, Cmp, Rule, x86_64};
+    use crate::Errno;
+
+    const AF_UNIX: u64 = 1;
+    const NOT_UNIX: &[ArgCmp] = &[ArgCmp::new(0, Cmp::Ne(AF_UNIX))];
+
+    /// No new network sockets: `socket()` fails with `EAFNOSUPPORT` for any family but
+    /// `AF_UNIX`, and io_uring, which can create sockets on its own, is unavailable.
+    ///
+    /// Sockets inherited from the parent remain usable. Combine with
+    /// [`Namespaces::NET`](crate::Namespaces::NET) for a payload without any network access.
+    pub const NO_NETWORK: &[Rule<'static>] = &[
+        Rule::new(x86_64::SOCKET, Action::Errno(Errno::EAFNOSUPPORT)).with_args(NOT_UNIX),
+        Rule::new(x86_64::IO_URING_SETUP, Action::Errno(Errno::ENOSYS)),
+    ];
+
+    /// No program can be executed once the payload is started: `execve()` and `execveat()`
+    /// fail with `EPERM`. The execution of the payload itself is let through.
+    pub const NO_EXEC: &[Rule<'static>] = &[
+        Rule::new(x86_64::EXECVE, Action::Errno(Errno::EPERM)),
+        Rule::new(x86_64::EXECVEAT, Action::Errno(Errno::EPERM)),
+    ];
+
+    /// No access to the memory or descriptors of other processes: `ptrace()`,
+    /// `process_vm_readv()`, `process_vm_writev()` and `pidfd_getfd()` fail with `EPERM`.
+    pub const NO_PTRACE: &[Rule<'static>] = &[
+        Rule::new(x86_64::PTRACE, Action::Errno(Errno::EPERM)),
+        Rule::new(x86_64::PROCESS_VM_READV, Action::Errno(Errno::EPERM)),
+        Rule::new(x86_64::PROCESS_VM_WRITEV, Action::Errno(Errno::EPERM)),
+        Rule::new(x86_64::PIDFD_GETFD, Action::Errno(Errno::EPERM)),
+    ];
+}
+
+/// What happens to a system call matched by a [`Rule`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+    /// Run the system call
+    Allow,
+    /// Fail the system call with the errno, without running it
+    Errno(Errno),
+    /// Kill the process, as if by an uncaught `SIGSYS` (Linux 4.14+)
+    Kill,
+    /// Send `SIGSYS` to the calling thread, which can catch it
+    Trap,
+    /// Run the system call and log it to the audit log (Linux 4.14+)
+    Log,
+    /// Suspend the calling thread until the parent answers the call through the [`Notifier`]
+    /// of the payload, see [`Child::supervise`](crate::Child::supervise) (Linux 5.0+).
+    ///
+    /// Only available when the payload is spawned: a filter notifying calls is rejected with
+    /// `EINVAL` in replace mode.
+    Notify,
+}
+
+impl Action {
+    /// The `SECCOMP_RET_*` value returned by the filter.
+    const fn raw(self) -> u32 {
+        match self {
+            Action::Allow => SECCOMP_RET_ALLOW,
+            Action::Errno(errno) => SECCOMP_RET_ERRNO | (errno.raw() as u32 & SECCOMP_RET_DATA),
+            Action::Kill => SECCOMP_RET_KILL_PROCESS,
+            Action::Trap => SECCOMP_RET_TRAP,
+            Action::Log => SECCOMP_RET_LOG,
+            Action::Notify => SECCOMP_RET_USER_NOTIF,
+        }
+    }
+}
+
+/// Comparison of a system call argument, as an unsigned 64-bit value.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Cmp {
+    /// `arg == value`
+    Eq(u64),
+    /// `arg != value`
+    Ne(u64),
+    /// `arg < value`
+    Lt(u64),
+    /// `arg <= value`
+    Le(u64),
+    /// `arg > value`
+    Gt(u64),
+    /// `arg >= value`
+    Ge(u64),
+    /// `arg & mask == value`
+    MaskedEq { mask: u64, value: u64 },
+}
+
+/// A condition on an argument of the system call matched by a [`Rule`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ArgCmp {
+    /// Position of the argument, from 0 to 5
+    pub index: u8,
+    /// Comparison the argument must pass
+    pub cmp: Cmp,
+}
+
+impl ArgCmp {
+    /// Compares the argument at `index`.
+    pub const fn new(index: u8, cmp: Cmp) -> Self {
+        Self { index, cmp }
+    }
+}
+
+/// Applies an [`Action`] to a system call, when all its argument conditions hold.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Rule<'a> {
+    /// System call number, see [`x86_64`]
+    pub syscall: u32,
+    /// Conditions on the arguments, all of which must hold
+    pub args: &'a [ArgCmp],
+    /// Action taken when the rule matches
+    pub action: Action,
+}
+
+impl Rule<'static> {
+    /// Applies `action` to every call of `syscall`.
+    pub const fn new(syscall: u32, action: Action) -> Self {
+        Self {
+            syscall,
+            args: &[],
+            action,
+        }
+    }
+}
+
+impl Rule<'_> {
+    /// Only matches the calls whose arguments pass all of `args`.
+    pub const fn with_args(self, args: &[ArgCmp]) -> Rule<'_> {
+        Rule {
+            syscall: self.syscall,
+            args,
+            action: self.action,
+        }
+    }
+}
+
+/// Rules filtering the system calls of the payload, see the [module documentation](self).
+///
+/// The first matching rule decides, and calls matched by no rule get the default action.
+/// Without the `alloc` feature, at most 64 rules can be added.
+#[derive(Clone)]
+pub struct SeccompFilter<'a> {
+    default: Action,
+    rules: List<Rule<'a>, MAX_RULES>,
+}
+
+impl<'a> SeccompFilter<'a> {
+    /// A filter applying `default` to the calls matched by no rule: [`Action::Allow`] for a
+    /// deny list, or [`Action::Errno`] or [`Action::Kill`] for an allow list.
+    pub const fn new(default: Action) -> Self {
+        Self {
+            default,
+            rules: List::new(),
+        }
+    }
+
+    /// Add a rule, after the previous ones.
+    pub fn rule(mut self, rule: Rule<'a>) -> Self {
+        self.rules.push(rule);
+        self
+    }
+
+    /// Add rules, such as a [`profile`], after the previous ones.
+    pub fn rules(mut self, rules: &[Rule<'a>]) -> Self {
+        for &rule in rules {
+            self.rules.push(rule);
+        }
+        self
+    }
+
+    /// Run every call of `syscall`.
+    pub fn allow(self, syscall: u32) -> Self {
+        self.rule(Rule::new(syscall, Action::Allow))
+    }
+
+    /// Fail every call of `syscall` with `errno`.
+    pub fn deny(self, syscall: u32, errno: Errno) -> Self {
+        self.rule(Rule::new(syscall, Action::Errno(errno)))
+    }
+
+    /// Returns `true` if calls can be [notified](Action::Notify) to the parent.
+    pub(crate) fn notifies(&self) -> bool {
+        self.default == Action::Notify
+            || self
+                .rules
+                .as_slice()
+                .iter()
+                .any(|rule| rule.action == Action::Notify)
+    }
+}
+
+/// `struct sock_filter`
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct SockFilter {
+    code: u16,
+    jt: u8,
+    jf: u8,
+    k: u32,
+}
+
+/// `struct sock_fprog`
+#[repr(C)]
+struct SockFprog {
+    len: u16,
+    filter: *const SockFilter,
+}
+
+/// The system calls the child makes itself once the filter is installed, let through whatever
+/// the rules say. They are matched on their exact arguments, so that the payload cannot make
+/// the same calls.
+pub(crate) struct RunnerCalls<'a> {
+    /// Descriptor executed by `execveat`
+    pub(crate) fd: i32,
+    /// Path executed by `execve`
+    pub(crate) path: *const u8,
+    pub(crate) argv: *const *const u8,
+    pub(crate) envp: *const *const u8,
+    /// Pipe, buffer and length of the write reporting a failed `execve` to the parent
+    pub(crate) report: Option<(i32, *const u8, usize)>,
+    /// Socket on which the listener of the notifications is sent to the parent, and the slot
+    /// of the message header
+    pub(crate) listener_socket: Option<(i32, &'a MsgSlot)>,
+}
+
+/// Target of a jump in the instructions checking an argument
+#[derive(Clone, Copy)]
+enum Target {
+    /// The next instruction
+    Next,
+    /// The end of the check: the argument passed
+    Pass,
+    /// The next rule: the argument failed
+    Fail,
+}
+
+/// An instruction checking an argument, with symbolic jump targets
+type Check = (u16, Target, Target, u32);
+
+/// A compiled filter, ready to be installed in the child.
+pub(crate) struct Program<'a> {
+    insns: List<SockFilter, BPF_MAXINSNS>,
+    /// Socket on which the listener is sent, for a filter notifying calls
+    listener_socket: Option<(i32, &'a MsgSlot)>,
+}
+
+impl<'a> Program<'a> {
+    /// Compile `filter`, preceded by the rules letting `runner` through.
+    ///
+    /// Returns `E2BIG` if the filter has too many rules or instructions, and `EINVAL` for an
+    /// argument index over 5, or a filter notifying calls without a socket to send them on.
+    pub(crate) fn compile(
+        filter: &SeccompFilter<'_>,
+        runner: &RunnerCalls<'a>,
+    ) -> Result<Self, Errno> {
+        #[cfg(not(feature = "alloc"))]
+        if filter.rules.overflowed() {
+            return Err(Errno::E2BIG);
+        }
+        let listener_socket = match filter.notifies() {
+            true => Some(runner.listener_socket.ok_or(Errno::EINVAL)?),
+            false => None,
+        };
+        let mut program = Self {
+            insns: List::new(),
+            listener_socket,
+        };
+        program.push(BPF_LD_W_ABS, 0, 0, DATA_ARCH);
+        program.push(BPF_JMP_JEQ_K, 1, 0, AUDIT_ARCH_X86_64);
+        program.push(BPF_RET_K, 0, 0, SECCOMP_RET_KILL_PROCESS);
+        program.push(BPF_LD_W_ABS, 0, 0, DATA_NR);
+        program.push(BPF_JMP_JGE_K, 0, 1, X32_SYSCALL_BIT);
+        program.push(BPF_RET_K, 0, 0, SECCOMP_RET_KILL_PROCESS);
+
+        let (argv, envp) = (runner.argv as u64, runner.envp as u64);
+        let execveat = [
+            ArgCmp::new(0, Cmp::Eq(runner.fd as u64)),
+            ArgCmp::new(1, Cmp::Eq(EMPTY_STRING.as_ptr() as u64)),
+            ArgCmp::new(2, Cmp::Eq(argv)),
+            ArgCmp::new(3, Cmp::Eq(envp)),
+            ArgCmp::new(4, Cmp::Eq(AT_EMPTY_PATH)),
+        ];
+        let execve = [
+            ArgCmp::new(0, Cmp::Eq(runner.path as u64)),
+            ArgCmp::new(1, Cmp::Eq(argv)),
+            ArgCmp::new(2, Cmp::Eq(envp)),
+        ];
+        program.push_rule(&Rule::new(x86_64::EXECVEAT, Action::Allow).with_args(&execveat))?;
+        program.push_rule(&Rule::new(x86_64::EXECVE, Action::Allow).with_args(&execve))?;
+        if let Some((fd, buf, len)) = runner.report {
+            // the pipe is close-on-exec, the payload may get a descriptor with the same number
+            let report = [
+                ArgCmp::new(0, Cmp::Eq(fd as u64)),
+                ArgCmp::new(1, Cmp::Eq(buf as u64)),
+                ArgCmp::new(2, Cmp::Eq(len as u64)),
+            ];
+            program.push_rule(&Rule::new(x86_64::WRITE, Action::Allow).with_args(&report))?;
+        }
+        if let Some((fd, slot)) = listener_socket {
+            let listener = [
+                ArgCmp::new(0, Cmp::Eq(fd as u64)),
+                ArgCmp::new(1, Cmp::Eq(slot.as_ptr() as u64)),
+                ArgCmp::new(2, Cmp::Eq(fd::MSG_NOSIGNAL as u64)),
+            ];
+            program.push_rule(&Rule::new(x86_64::SENDMSG, Action::Allow).with_args(&listener))?;
+        }
+        program.push_rule(&Rule::new(x86_64::EXIT_GROUP, Action::Allow))?;
+
+        for rule in filter.rules.as_slice() {
+            program.push_rule(rule)?;
+        }
+        program.push(BPF_RET_K, 0, 0, filter.default.raw());
+
+        #[cfg(not(feature = "alloc"))]
+        if program.insns.overflowed() {
+            return Err(Errno::E2BIG);
+        }
+        if program.insns.as_slice().len() > BPF_MAXINSNS {
+            return Err(Errno::E2BIG);
+        }
+        Ok(program)
+    }
+
+    /// Set `PR_SET_NO_NEW_PRIVS` and install the program on the calling thread, then send the
+    /// listener of its notifications to the parent. Only issues system calls.
+    pub(crate) fn install(&self) -> Result<(), Errno> {
+        let insns = self.insns.as_slice();
+        let prog = SockFprog {
+            len: insns.len() as u16,
+            filter: insns.as_ptr(),
+        };
+        cvt(unsafe { syscalls::prctl(PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) })?;
+        let flags = match self.listener_socket {
+            Some(_) => SECCOMP_FILTER_FLAG_NEW_LISTENER,
+            None => 0,
+        };
+        let listener = cvt(unsafe {
+            syscalls::seccomp(
+                SECCOMP_SET_MODE_FILTER,
+                flags,
+                (&prog as *const SockFprog).cast_mut().cast(),
+            )
+        })?;
+        // the listener is close-on-exec, the copy of the parent is the only one left
+        if let Some((socket, slot)) = self.listener_socket {
+            fd::send_fd(socket, listener, slot)?;
+        }
+        Ok(())
+    }
+
+    fn push(&mut self, code: u16, jt: u8, jf: u8, k: u32) {
+        self.insns.push(SockFilter { code, jt, jf, k });
+    }
+
+    /// Check the system call number, then each argument, jumping to the next rule on the first
+    /// mismatch.
+    fn push_rule(&mut self, rule: &Rule<'_>) -> Result<(), Errno> {
+        let args_len: usize = rule.args.iter().map(|arg| check_len(arg.cmp)).sum();
+        self.push(BPF_LD_W_ABS, 0, 0, DATA_NR);
+        self.push(BPF_JMP_JEQ_K, 0, jump(args_len + 1)?, rule.syscall);
+        let mut remaining = args_len + 1;
+        for arg in rule.args {
+            remaining -= check_len(arg.cmp);
+            self.push_check(arg, remaining)?;
+        }
+        self.push(BPF_RET_K, 0, 0, rule.action.raw());
+        Ok(())
+    }
+
+    /// Emit the check of `arg`, followed by `remaining` instructions until the next rule.
+    fn push_check(&mut self, arg: &ArgCmp, remaining: usize) -> Result<(), Errno> {
+        use Target::{Fail, Next, Pass};
+
+        if arg.index > 5 {
+            return Err(Errno::EINVAL);
+        }
+        let low = DATA_ARGS + 8 * arg.index as u32;
+        let (load_high, load_low) = (
+            (BPF_LD_W_ABS, Next, Next, low + 4),
+            (BPF_LD_W_ABS, Next, Next, low),
+        );
+        let split = |value: u64| ((value >> 32) as u32, value as u32);
+        let checks: &[Check] = match arg.cmp {
+            Cmp::Eq(value) => {
+                let (high, low) = split(value);
+                &[
+                    load_high,
+                    (BPF_JMP_JEQ_K, Next, Fail, high),
+                    load_low,
+                    (BPF_JMP_JEQ_K, Next, Fail, low),
+                ]
+            }
+            Cmp::Ne(value) => {
+                let (high, low) = split(value);
+                &[
+                    load_high,
+                    (BPF_JMP_JEQ_K, Next, Pass, high),
+                    load_low,
+                    (BPF_JMP_JEQ_K, Fail, Next, low),
+                ]
+            }
+            Cmp::Gt(value) | Cmp::Ge(value) => {
+                let (high, low) = split(value);
+                let op = match arg.cmp {
+                    Cmp::Gt(_) => BPF_JMP_JGT_K,
+                    _ => BPF_JMP_JGE_K,
+                };
+                &[
+                    load_high,
+                    (BPF_JMP_JGT_K, Pass, Next, high),
+                    (BPF_JMP_JEQ_K, Next, Fail, high),
+                    load_low,
+                    (op, Next, Fail, low),
+                ]
+            }
+            // the negations of Ge and Gt
+            Cmp::Lt(value) | Cmp::Le(value) => {
+                let (high, low) = split(value);
+                let op = match arg.cmp {
+                    Cmp::Lt(_) => BPF_JMP_JGE_K,
+                    _ => BPF_JMP_JGT_K,
+                };
+                &[
+                    load_high,
+                    (BPF_JMP_JGT_K, Fail, Next, high),
+                    (BPF_JMP_JEQ_K, Next, Pass, high),
+                    load_low,
+                    (op, Fail, Next, low),
+                ]
+            }
+            Cmp::MaskedEq { mask, value } => {
+                let ((mask_high, mask_low), (high, low)) = (split(mask), split(value));
+                &[
+                    load_high,
+                    (BPF_ALU_AND_K, Next, Next, mask_high),
+                    (BPF_JMP_JEQ_K, Next, Fail, high),
+                    load_low,
+                    (BPF_ALU_AND_K, Next, Next, mask_low),
+                    (BPF_JMP_JEQ_K, Next, Fail, low),
+                ]
+            }
+        };
+        debug_assert_eq!(checks.len(), check_len(arg.cmp));
+        for (index, &(code, jt, jf, k)) in checks.iter().enumerate() {
+            let resolve = |target| match target {
+                Next => Ok(0),
+                Pass => jump(checks.len() - index - 1),
+                Fail => jump(checks.len() - index - 1 + remaining),
+            };
+            self.push(code, resolve(jt)?, resolve(jf)?, k);
+        }
+        Ok(())
+    }
+}
+
+/// `struct seccomp_notif`
+#[repr(C)]
+#[derive(Default)]
+struct RawNotif {
+    id: u64,
+    pid: u32,
+    flags: u32,
+    nr: u32,
+    arch: u32,
+    instruction_pointer: u64,
+    args: [u64; 6],
+}
+
+/// `struct seccomp_notif_resp`
+#[repr(C)]
+struct RawNotifResp {
+    id: u64,
+    val: i64,
+    error: i32,
+    flags: u32,
+}
+
+/// A system call of the payload matched by an [`Action::Notify`] rule, suspended until it is
+/// answered with [`Notifier::respond`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Notification {
+    /// Identifier of the suspended call
+    pub id: u64,
+    /// Thread making the call, in the pid namespace of the parent
+    pub pid: u32,
+    /// System call number, see [`x86_64`]
+    pub syscall: u32,
+    /// Raw arguments of the call
+    pub args: [u64; 6],
+    /// Address of the instruction making the call
+    pub instruction_pointer: u64,
+}
+
+/// The answer to a [`Notification`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Response {
+    /// Run the system call as if it was allowed (Linux 5.5+).
+    ///
+    /// Another thread of the payload can change the memory the arguments point to after it
+    /// was inspected: a path checked with [`Notifier::read_c_str`] is only trustworthy for a
+    /// single-threaded payload.
+    Continue,
+    /// Fail the system call with the errno, without running it
+    Errno(Errno),
+    /// Emulate the system call: it is not run, and returns the value
+    Return(i64),
+}
+
+/// Receiving end of the calls matched by [`Action::Notify`] rules, for the parent to allow, deny
+/// or emulate them. See [`Child::supervise`](crate::Child::supervise).
+#[derive(Debug)]
+pub struct Notifier {
+    fd: Fd,
+}
+
+impl Notifier {
+    pub(crate) fn new(fd: Fd) -> Self {
+        Self { fd }
+    }
+
+    /// Blocks until a call is notified.
+    ///
+    /// Returns `ENOENT` if the call was interrupted, by a signal or the death of its thread,
+    /// before it could be received.
+    pub fn recv(&self) -> Result<Notification, Errno> {
+        let mut raw = RawNotif::default();
+        loop {
+            let ret = unsafe {
+                syscalls::ioctl(
+                    self.fd.raw(),
+                    SECCOMP_IOCTL_NOTIF_RECV,
+                    (&mut raw as *mut RawNotif).cast(),
+                )
+            };
+            match cvt(ret) {
+                Ok(_) => break,
+                Err(Errno::EINTR) => continue,
+                Err(errno) => return Err(errno),
+            }
+        }
+        Ok(Notification {
+            id: raw.id,
+            pid: raw.pid,
+            syscall: raw.nr,
+            args: raw.args,
+            instruction_pointer: raw.instruction_pointer,
+        })
+    }
+
+    /// Answers `notification`, which lets the calling thread go on.
+    ///
+    /// Returns `ENOENT` if the call is no longer waiting for an answer.
+    pub fn respond(&self, notification: &Notification, response: Response) -> Result<(), Errno> {
+        let (val, error, flags) = match response {
+            Response::Continue => (0, 0, SECCOMP_USER_NOTIF_FLAG_CONTINUE),
+            Response::Errno(errno) => (0, -errno.raw(), 0),
+            Response::Return(value) => (value, 0, 0),
+        };
+        let mut resp = RawNotifResp {
+            id: notification.id,
+            val,
+            error,
+            flags,
+        };
+        cvt(unsafe {
+            syscalls::ioctl(
+                self.fd.raw(),
+                SECCOMP_IOCTL_NOTIF_SEND,
+                (&mut resp as *mut RawNotifResp).cast(),
+            )
+        })?;
+        Ok(())
+    }
+
+    /// Returns `true` if `notification` still waits for an answer, so its thread is alive and
+    /// its pid was not recycled.
+    pub fn is_pending(&self, notification: &Notification) -> bool {
+        let mut id = notification.id;
+        let ret = unsafe {
+            syscalls::ioctl(
+                self.fd.raw(),
+                SECCOMP_IOCTL_NOTIF_ID_VALID,
+                (&mut id as *mut u64).cast(),
+            )
+        };
+        ret == 0
+    }
+
+    /// Reads the memory of the calling thread at `address` into `buf`, with
+    /// `process_vm_readv()`. Returns the number of bytes read, which stops short at the end of
+    /// a mapping.
+    ///
+    /// Returns `ENOENT` if the call stopped waiting meanwhile, as the memory read may then belong
+    /// to another process.
+    pub fn read_memory(
+        &self,
+        notification: &Notification,
+        address: u64,
+        buf: &mut [u8],
+    ) -> Result<usize, Errno> {
+        let mut local = IoVec {
+            base: buf.as_mut_ptr(),
+            len: buf.len(),
+        };
+        let mut remote = IoVec {
+            base: address as *mut u8,
+            len: buf.len(),
+        };
+        let read = cvt(unsafe {
+            syscalls::process_vm_readv(
+                notification.pid as i32,
+                (&mut local as *mut IoVec).cast(),
+                1,
+                (&mut remote as *mut IoVec).cast(),
+                1,
+                0,
+            )
+        })?;
+        if !self.is_pending(notification) {
+            return Err(Errno::ENOENT);
+        }
+        Ok(read as usize)
+    }
+
+    /// Reads the null-terminated string at `address`, such as the path argument of `openat`,
+    /// into `buf`. See [`read_memory`](Self::read_memory).
+    ///
+    /// Returns `ENAMETOOLONG` if the string does not fit in `buf`.
+    pub fn read_c_str<'b>(
+        &self,
+        notification: &Notification,
+        address: u64,
+        buf: &'b mut [u8],
+    ) -> Result<&'b CStr, Errno> {
+        let read = self.read_memory(notification, address, buf)?;
+        CStr::from_bytes_until_nul(&buf[..read]).map_err(|_| Errno::ENAMETOOLONG)
+    }
+
+    /// Returns the raw listener descriptor, to register it in an event loop where it becomes
+    /// readable when a call is notified.
+    pub fn as_raw_fd(&self) -> i32 {
+        self.fd.raw()
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::os::fd::AsRawFd for Notifier {
+    fn as_raw_fd(&self) -> std::os::fd::RawFd {
+        self.fd.raw()
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::os::fd::AsFd for Notifier {
+    fn as_fd(&self) -> std::os::fd::BorrowedFd<'_> {
+        // Safety: the descriptor stays open as long as `self` is alive
+        unsafe { std::os::fd::BorrowedFd::borrow_raw(self.fd.raw()) }
+    }
+}
+
+/// Number of instructions checking an argument with `cmp`
+const fn check_len(cmp: Cmp) -> usize {
+    match cmp {
+        Cmp::Eq(_) | Cmp::Ne(_) => 4,
+        Cmp::Lt(_) | Cmp::Le(_) | Cmp::Gt(_) | Cmp::Ge(_) => 5,
+        Cmp::MaskedEq { .. } => 6,
+    }
+}
+
+/// Conditional jumps skip at most 255 instructions
+fn jump(offset: usize) -> Result<u8, Errno> {
+    u8::try_from(offset).map_err(|_| Errno::E2BIG)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RUNNER: RunnerCalls = RunnerCalls {
+        fd: 3,
+        path: core::ptr::null(),
+        argv: core::ptr::null(),
+        envp: core::ptr::null(),
+        report: Some((4, 0x1000 as *const u8, 8)),
+        listener_socket: None,
+    };
+
+    /// Run `program` on a system call, like the kernel does
+    fn run(program: &Program, arch: u32, nr: u32, args: [u64; 6]) -> u32 {
+        let mut data = [0u8; 64];
+        data[0..4].copy_from_slice(&nr.to_le_bytes());
+        data[4..8].copy_from_slice(&arch.to_le_bytes());
+        for (index, arg) in args.iter().enumerate() {
+            let offset = 16 + 8 * index;
+            data[offset..offset + 8].copy_from_slice(&arg.to_le_bytes());
+        }
+        let insns = program.insns.as_slice();
+        let (mut pc, mut acc) = (0, 0u32);
+        loop {
+            let insn = insns[pc];
+            pc += 1;
+            let taken = match insn.code {
+                BPF_LD_W_ABS => {
+                    let offset = insn.k as usize;
+                    acc = u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap());
+                    continue;
+                }
+                BPF_ALU_AND_K => {
+                    acc &= insn.k;
+                    continue;
+                }
+                BPF_RET_K => return insn.k,
+                BPF_JMP_JEQ_K => acc == insn.k,
+                BPF_JMP_JGT_K => acc > insn.k,
+                BPF_JMP_JGE_K => acc >= insn.k,
+                code => panic!("unexpected opcode {code:#x}"),
+            };
+            pc += if taken { insn.jt } else { insn.jf } as usize;
+        }
+    }
+
+    fn check(cmp: Cmp, arg: u64) -> bool {
+        let args = [ArgCmp::new(1, cmp)];
+        let filter = SeccompFilter::new(Action::Allow)
+            .rule(Rule::new(x86_64::UNAME, Action::Errno(Errno::EPERM)).with_args(&args));
+        let program = Program::compile(&filter, &RUNNER).unwrap();
+        let ret = run(
+            &program,
+            AUDIT_ARCH_X86_64,
+            x86_64::UNAME,
+            [0, arg, 0, 0, 0, 0],
+        );
+        ret == Action::Errno(Errno::EPERM).raw()
+    }
+
+    #[test]
+    fn test_compare_arguments() {
+        let (small, large) = (5, 0x1_0000_0003);
+        for (cmp, passing, failing) in [
+            (Cmp::Eq(large), &[large][..], &[3, 0x2_0000_0003, small][..]),
+            (Cmp::Ne(large), &[3, 0x2_0000_0003, small], &[large]),
+            (
+                Cmp::Gt(large),
+                &[large + 1, 0x2_0000_0000],
+                &[large, 0xffff_ffff, small],
+            ),
+            (Cmp::Ge(large), &[large, 0x2_0000_0000], &[large - 1, small]),
+            (
+                Cmp::Lt(large),
+                &[large - 1, small, 0xffff_ffff],
+                &[large, 0x2_0000_0000],
+            ),
+            (Cmp::Le(large), &[large, small], &[large + 1, 0x2_0000_0000]),
+            (Cmp::Lt(small), &[0, 4], &[5, 0x1_0000_0000]),
+            (
+                Cmp::MaskedEq {
+                    mask: 0xff_0000_00ff,
+                    value: 0x01_0000_0002,
+                },
+                &[0x01_1234_5602, 0x01_0000_0002],
+                &[0x02_0000_0002, 0x01_0000_0003],
+            ),
+        ] {
+            for &arg in passing {
+                assert!(check(cmp, arg), "{cmp:?} should pass for {arg:#x}");
+            }
+            for &arg in failing {
+                assert!(!check(cmp, arg), "{cmp:?} should fail for {arg:#x}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_rules_order_and_default() {
+        let args = [ArgCmp::new(0, Cmp::Ne(1)), ArgCmp::new(2, Cmp::Eq(0))];
+        let filter = SeccompFilter::new(Action::Errno(Errno::ENOSYS))
+            .rule(Rule::new(x86_64::SOCKET, Action::Trap).with_args(&args))
+            .allow(x86_64::SOCKET)
+            .rule(Rule::new(x86_64::UNAME, Action::Log))
+            .rule(Rule::new(x86_64::UNAME, Action::Kill));
+        let program = Program::compile(&filter, &RUNNER).unwrap();
+        let call = |nr, args| run(&program, AUDIT_ARCH_X86_64, nr, args);
+        assert_eq!(call(x86_64::SOCKET, [2, 1, 0, 0, 0, 0]), SECCOMP_RET_TRAP);
+        assert_eq!(call(x86_64::SOCKET, [2, 1, 6, 0, 0, 0]), SECCOMP_RET_ALLOW);
+        assert_eq!(call(x86_64::SOCKET, [1, 1, 0, 0, 0, 0]), SECCOMP_RET_ALLOW);
+        assert_eq!(call(x86_64::UNAME, [0; 6]), SECCOMP_RET_LOG);
+        assert_eq!(call(x86_64::READ, [0; 6]), SECCOMP_RET_ERRNO | 38);
+    }
+
+    #[test]
+    fn test_other_abis_killed() {
+        let program = Program::compile(&SeccompFilter::new(Action::Allow), &RUNNER).unwrap();
+        const AUDIT_ARCH_I386: u32 = 0x4000_0003;
+        assert_eq!(
+            run(&program, AUDIT_ARCH_I386, 11, [0; 6]),
+            SECCOMP_RET_KILL_PROCESS
+        );
+        let x32_execve = X32_SYSCALL_BIT | 520;
+        assert_eq!(
+            run(&program, AUDIT_ARCH_X86_64, x32_execve, [0; 6]),
+            SECCOMP_RET_KILL_PROCESS
+        );
+        assert_eq!(
+            run(&program, AUDIT_ARCH_X86_64, x86_64::READ, [0; 6]),
+            SECCOMP_RET_ALLOW
+        );
+    }
+
+    #[test]
+    fn test_runner_calls_let_through() {
+        let filter = SeccompFilter::new(Action::Kill)
+            .rules(profile::NO_EXEC)
+            .deny(x86_64::WRITE, Errno::EBADF);
+        let program = Program::compile(&filter, &RUNNER).unwrap();
+        let call = |nr, args| run(&program, AUDIT_ARCH_X86_64, nr, args);
+        let empty = EMPTY_STRING.as_ptr() as u64;
+        assert_eq!(
+            call(x86_64::EXECVEAT, [3, empty, 0, 0, AT_EMPTY_PATH, 0]),
+            SECCOMP_RET_ALLOW
+        );
+        assert_eq!(
+            call(x86_64::EXECVEAT, [5, empty, 0, 0, AT_EMPTY_PATH, 0]),
+            SECCOMP_RET_ERRNO | 1
+        );
+        assert_eq!(call(x86_64::EXECVE, [0; 6]), SECCOMP_RET_ALLOW);
+        assert_eq!(
+            call(x86_64::EXECVE, [1, 0, 0, 0, 0, 0]),
+            SECCOMP_RET_ERRNO | 1
+        );
+        assert_eq!(
+            call(x86_64::WRITE, [4, 0x1000, 8, 0, 0, 0]),
+            SECCOMP_RET_ALLOW
+        );
+        // the same descriptor number, written to by the payload
+        for args in [
+            [4, 0x2000, 8, 0, 0, 0],
+            [4, 0x1000, 1, 0, 0, 0],
+            [1, 0x1000, 8, 0, 0, 0],
+        ] {
+            assert_eq!(call(x86_64::WRITE, args), SECCOMP_RET_ERRNO | 9);
+        }
+        assert_eq!(call(x86_64::EXIT_GROUP, [0; 6]), SECCOMP_RET_ALLOW);
+        assert_eq!(call(x86_64::READ, [0; 6]), SECCOMP_RET_KILL_PROCESS);
+    }
+
+    #[test]
+    fn test_notify_sends_listener() {
+        let filter = SeccompFilter::new(Action::Allow)
+            .rule(Rule::new(x86_64::OPENAT, Action::Notify))
+            .deny(x86_64::SENDMSG, Errno::EPERM);
+        assert!(filter.notifies());
+        assert!(SeccompFilter::new(Action::Notify).notifies());
+        // nowhere to send the listener to in replace mode
+        assert_eq!(
+            Program::compile(&filter, &RUNNER).err(),
+            Some(Errno::EINVAL)
+        );
+        let slot = MsgSlot::new();
+        let msg = slot.as_ptr() as u64;
+        let flags = fd::MSG_NOSIGNAL as u64;
+        let runner = RunnerCalls {
+            listener_socket: Some((5, &slot)),
+            ..RUNNER
+        };
+        let program = Program::compile(&filter, &runner).unwrap();
+        let call = |nr, args| run(&program, AUDIT_ARCH_X86_64, nr, args);
+        assert_eq!(call(x86_64::OPENAT, [0; 6]), SECCOMP_RET_USER_NOTIF);
+        assert_eq!(
+            call(x86_64::SENDMSG, [5, msg, flags, 0, 0, 0]),
+            SECCOMP_RET_ALLOW
+        );
+        // the same socket number, used by the payload
+        for args in [[5, msg + 8, flags, 0, 0, 0], [5, msg, 0, 0, 0, 0]] {
+            assert_eq!(call(x86_64::SENDMSG, args), SECCOMP_RET_ERRNO | 1);
+        }
+        let filter = SeccompFilter::new(Action::Kill);
+        assert!(!filter.notifies());
+        let program = Program::compile(&filter, &runner).unwrap();
+        assert!(program.listener_socket.is_none());
+        let call = |nr, args| run(&program, AUDIT_ARCH_X86_64, nr, args);
+        assert_eq!(
+            call(x86_64::SENDMSG, [5, msg, flags, 0, 0, 0]),
+            SECCOMP_RET_KILL_PROCESS
+        );
+    }
+
+    #[test]
+    fn test_compile_errors() {
+        let args = [ArgCmp::new(6, Cmp::Eq(0))];
+        let filter = SeccompFilter::new(Action::Allow)
+            .rule(Rule::new(x86_64::READ, Action::Kill).with_args(&args));
+        assert_eq!(
+            Program::compile(&filter, &RUNNER).err(),
+            Some(Errno::EINVAL)
+        );
+        // jumps over a rule are limited to 255 instructions
+        let args = [ArgCmp::new(0, Cmp::Eq(0)); 64];
+        let filter = SeccompFilter::new(Action::Allow)
+            .rule(Rule::new(x86_64::READ, Action::Kill).with_args(&args));
+        assert_eq!(Program::compile(&filter, &RUNNER).err(), Some(Errno::E2BIG));
+    }
+}
@@ -0,0 +1,261 @@
+use core::fmt;
+
+use crate::Resource;
+
+const SIGXCPU: i32 = 24;
+const SIGXFSZ: i32 = 25;
+
+/// Describes how a payload process terminated.
+///
+/// This decodes the raw status reported by `wait4`, so a payload killed by a signal can be
+/// told apart from one that exited cleanly.
+///
+/// # Example
+/// ```rust,no_run
+/// let elf_bytes = std::fs::read("/usr/bin/true").unwrap();
+/// let status = memfd_runner::run(&elf_bytes).unwrap();
+/// match (status.code(), status.signal()) {
+///     (Some(code), _) => println!("exited with code {code}"),
+///     (_, Some(signal)) => println!("killed by signal {signal}"),
+///     _ => unreachable!(),
+/// }
+/// ```
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ExitStatus(i32);
+
+impl ExitStatus {
+    /// Creates an `ExitStatus` from the raw status word filled by `wait4`.
+    pub const fn from_raw(raw: i32) -> Self {
+        Self(raw)
+    }
+
+    /// Returns the raw status word filled by `wait4`.
+    pub const fn into_raw(self) -> i32 {
+        self.0
+    }
+
+    /// Returns `true` if the payload exited normally with code 0.
+    pub const fn success(self) -> bool {
+        matches!(self.code(), Some(0))
+    }
+
+    /// Returns the exit code (0-255) if the payload exited normally (`WIFEXITED`).
+    pub const fn code(self) -> Option<i32> {
+        if self.0 & 0x7f == 0 {
+            Some((self.0 >> 8) & 0xff)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the signal which terminated the payload (`WIFSIGNALED`).
+    pub const fn signal(self) -> Option<i32> {
+        let signal = self.0 & 0x7f;
+        if signal != 0 && signal != 0x7f {
+            Some(signal)
+        } else {
+            None
+        }
+    }
+
+    /// Returns `true` if the payload was terminated by a signal and dumped core (`WCOREDUMP`).
+    pub const fn core_dumped(self) -> bool {
+        self.signal().is_some() && self.0 & 0x80 != 0
+    }
+
+    /// Returns the signal which stopped the payload (`WIFSTOPPED`).
+    pub const fn stopped_signal(self) -> Option<i32> {
+        if self.0 & 0xff == 0x7f {
+            Some((self.0 >> 8) & 0xff)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the resource whose limit killed the payload: [`Resource::Cpu`] for `SIGXCPU` and
+    /// [`Resource::Fsize`] for `SIGXFSZ`.
+    ///
+    /// A payload reaching the hard CPU limit is killed with `SIGKILL` instead, which is not
+    /// attributed to a resource.
+    pub const fn exceeded_limit(self) -> Option<Resource> {
+        match self.signal() {
+            Some(SIGXCPU) => Some(Resource::Cpu),
+            Some(SIGXFSZ) => Some(Resource::Fsize),
+            _ => None,
+        }
+    }
+
+    /// Returns the code a shell would report in `$?`: the exit code for a normal exit,
+    /// or `128 + signal` for a payload terminated or stopped by a signal.
+    pub const fn shell_code(self) -> i32 {
+        match (self.code(), self.signal(), self.stopped_signal()) {
+            (Some(code), _, _) => code,
+            (_, Some(signal), _) | (_, _, Some(signal)) => 128 + signal,
+            _ => 128,
+        }
+    }
+}
+
+/// Returns the symbolic name of a standard Linux signal number.
+pub(crate) const fn signal_name(signal: i32) -> Option<&'static str> {
+    Some(match signal {
+        1 => "SIGHUP",
+        2 => "SIGINT",
+        3 => "SIGQUIT",
+        4 => "SIGILL",
+        5 => "SIGTRAP",
+        6 => "SIGABRT",
+        7 => "SIGBUS",
+        8 => "SIGFPE",
+        9 => "SIGKILL",
+        10 => "SIGUSR1",
+        11 => "SIGSEGV",
+        12 => "SIGUSR2",
+        13 => "SIGPIPE",
+        14 => "SIGALRM",
+        15 => "SIGTERM",
+        16 => "SIGSTKFLT",
+        17 => "SIGCHLD",
+        18 => "SIGCONT",
+        19 => "SIGSTOP",
+        20 => "SIGTSTP",
+        21 => "SIGTTIN",
+        22 => "SIGTTOU",
+        23 => "SIGURG",
+        24 => "SIGXCPU",
+        25 => "SIGXFSZ",
+        26 => "SIGVTALRM",
+        27 => "SIGPROF",
+        28 => "SIGWINCH",
+        29 => "SIGIO",
+        30 => "SIGPWR",
+        31 => "SIGSYS",
+        _ => return None,
+    })
+}
+
+impl fmt::Debug for ExitStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("ExitStatus")
+            .field(&format_args!("{self}"))
+            .finish()
+    }
+}
+
+impl fmt::Display for ExitStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let write_signal = |f: &mut fmt::Formatter<'_>, signal: i32| match signal_name(signal) {
+            Some(name) => write!(f, "{signal} ({name})"),
+            None => write!(f, "{signal}"),
+        };
+        if let Some(code) = self.code() {
+            write!(f, "exit status: {code}")
+        } else if let Some(signal) = self.signal() {
+            f.write_str("signal: ")?;
+            write_signal(f, signal)?;
+            if self.core_dumped() {
+                f.write_str(" (core dumped)")?;
+            }
+            Ok(())
+        } else if let Some(signal) = self.stopped_signal() {
+            f.write_str("stopped (not terminated) by signal: ")?;
+            write_signal(f, signal)
+        } else {
+            write!(f, "unrecognised wait status: {} {:#x}", self.0, self.0)
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<ExitStatus> for std::process::ExitStatus {
+    fn from(status: ExitStatus) -> Self {
+        use std::os::unix::process::ExitStatusExt;
+        std::process::ExitStatus::from_raw(status.into_raw())
+    }
+}
+
+/// Converts to the [shell-style code](ExitStatus::shell_code), so that `128 + signal` is
+/// propagated for a crashed payload.
+#[cfg(feature = "std")]
+impl From<ExitStatus> for std::process::ExitCode {
+    fn from(status: ExitStatus) -> Self {
+        std::process::ExitCode::from(status.shell_code() as u8)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    extern crate std;
+    use std::format;
+
+    #[test]
+    fn test_exit_status_exited() {
+        let status = ExitStatus::from_raw(3 << 8);
+        assert_eq!(status.code(), Some(3));
+        assert_eq!(status.signal(), None);
+        assert_eq!(status.stopped_signal(), None);
+        assert!(!status.success());
+        assert!(!status.core_dumped());
+        assert_eq!(status.shell_code(), 3);
+        assert!(ExitStatus::from_raw(0).success());
+    }
+
+    #[test]
+    fn test_exit_status_signaled() {
+        // SIGSEGV with core dump
+        let status = ExitStatus::from_raw(11 | 0x80);
+        assert_eq!(status.code(), None);
+        assert_eq!(status.signal(), Some(11));
+        assert!(status.core_dumped());
+        assert!(!status.success());
+        assert_eq!(status.shell_code(), 139);
+
+        let status = ExitStatus::from_raw(9);
+        assert_eq!(status.signal(), Some(9));
+        assert!(!status.core_dumped());
+        assert_eq!(status.shell_code(), 137);
+        assert_eq!(status.exceeded_limit(), None);
+    }
+
+    #[test]
+    fn test_exit_status_exceeded_limit() {
+        let status = ExitStatus::from_raw(SIGXCPU | 0x80);
+        assert_eq!(status.exceeded_limit(), Some(Resource::Cpu));
+        let status = ExitStatus::from_raw(SIGXFSZ);
+        assert_eq!(status.exceeded_limit(), Some(Resource::Fsize));
+        assert_eq!(ExitStatus::from_raw(SIGXFSZ << 8).exceeded_limit(), None);
+    }
+
+    #[test]
+    fn test_exit_status_stopped() {
+        // SIGSTOP
+        let status = ExitStatus::from_raw((19 << 8) | 0x7f);
+        assert_eq!(status.code(), None);
+        assert_eq!(status.signal(), None);
+        assert_eq!(status.stopped_signal(), Some(19));
+        assert_eq!(status.shell_code(), 147);
+    }
+
+    #[test]
+    fn test_exit_status_display() {
+        assert_eq!(
+            format!("{}", ExitStatus::from_raw(1 << 8)),
+            "exit status: 1"
+        );
+        assert_eq!(
+            format!("{}", ExitStatus::from_raw(11 | 0x80)),
+            "signal: 11 (SIGSEGV) (core dumped)"
+        );
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_exit_status_into_std() {
+        let status: std::process::ExitStatus = ExitStatus::from_raw(11).into();
+        assert_eq!(
+            std::os::unix::process::ExitStatusExt::signal(&status),
+            Some(11)
+        );
+    }
+}
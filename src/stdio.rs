@@ -0,0 +1,309 @@
+use crate::fd::{self, AT_FDCWD, Fd, O_CLOEXEC};
+use crate::syscalls::{self, cvt};
+use crate::{Errno, RunOptions};
+
+const O_RDWR: i32 = 0o2;
+const F_ADD_SEALS: i32 = 1033;
+const F_SEAL_SEAL: i32 = 0x1;
+const F_SEAL_SHRINK: i32 = 0x2;
+const F_SEAL_GROW: i32 = 0x4;
+const F_SEAL_WRITE: i32 = 0x8;
+const MFD_CLOEXEC: u32 = 0x1;
+const MFD_ALLOW_SEALING: u32 = 0x2;
+const SEEK_SET: i32 = 0;
+
+/// Describes what to connect to a standard stream (stdin, stdout or stderr) of the payload.
+///
+/// Streams are set up in order (stdin, stdout, then stderr) in the child, so
+/// `Stdio::Fd(1)` for stderr refers to the already redirected stdout, like `2>&1` in a shell.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Stdio {
+    /// The payload inherits the stream of the current process.
+    #[default]
+    Inherit,
+    /// The stream is connected to `/dev/null`.
+    Null,
+    /// A pipe is created, the other end is exposed on the [`Child`](crate::Child) handle.
+    Piped,
+    /// The stream is connected to a copy of this file descriptor.
+    Fd(i32),
+}
+
+/// What to install on one standard stream of the child.
+#[derive(Debug)]
+enum ChildEnd {
+    Inherit,
+    Borrowed(i32),
+    Owned(Fd),
+}
+
+/// Standard streams of the child, prepared before forking.
+#[derive(Debug)]
+pub(crate) struct StdioSetup {
+    child_ends: [ChildEnd; 3],
+    parent_ends: [Option<Fd>; 3],
+}
+
+impl StdioSetup {
+    /// Open `/dev/null`, create pipes and the stdin memfd as requested, in the parent.
+    pub(crate) fn prepare(options: &RunOptions<'_>) -> Result<Self, Errno> {
+        let mut setup = StdioSetup {
+            child_ends: [ChildEnd::Inherit, ChildEnd::Inherit, ChildEnd::Inherit],
+            parent_ends: [None, None, None],
+        };
+        for (target, stdio) in options.stdio.into_iter().enumerate() {
+            setup.child_ends[target] = match options.stdin_bytes {
+                Some(bytes) if target == 0 => ChildEnd::Owned(sealed_memfd(bytes)?),
+                _ => Self::prepare_stream(target, stdio, &mut setup.parent_ends)?,
+            };
+        }
+        Ok(setup)
+    }
+
+    fn prepare_stream(
+        target: usize,
+        stdio: Stdio,
+        parent_ends: &mut [Option<Fd>; 3],
+    ) -> Result<ChildEnd, Errno> {
+        Ok(match stdio {
+            Stdio::Inherit => ChildEnd::Inherit,
+            Stdio::Fd(fd) => ChildEnd::Borrowed(fd),
+            Stdio::Null => ChildEnd::Owned(open_dev_null()?),
+            Stdio::Piped => {
+                let (read_end, write_end) = fd::pipe()?;
+                // stdin is written by the parent, stdout and stderr are read by the parent
+                let (child_end, parent_end) = match target {
+                    0 => (read_end, write_end),
+                    _ => (write_end, read_end),
+                };
+                parent_ends[target] = Some(parent_end);
+                ChildEnd::Owned(child_end)
+            }
+        })
+    }
+
+    /// Install the standard streams, in the child. Only issues system calls.
+    pub(crate) fn apply(&self) -> Result<(), Errno> {
+        for (target, end) in self.child_ends.iter().enumerate() {
+            let fd = match end {
+                ChildEnd::Inherit => continue,
+                ChildEnd::Borrowed(fd) => *fd,
+                ChildEnd::Owned(fd) => fd.raw(),
+            };
+            fd::dup_onto(fd, target as i32)?;
+        }
+        Ok(())
+    }
+
+    /// Close the child ends, in the parent, and return the parent ends of the pipes.
+    pub(crate) fn into_parent_ends(
+        self,
+    ) -> (Option<ChildStdin>, Option<ChildStdout>, Option<ChildStderr>) {
+        let [stdin, stdout, stderr] = self.parent_ends;
+        (
+            stdin.map(|fd| ChildStdin { fd }),
+            stdout.map(|fd| ChildStdout { fd }),
+            stderr.map(|fd| ChildStderr { fd }),
+        )
+    }
+}
+
+/// Copy `bytes` into a memfd sealed against any modification, positioned at its start.
+///
+/// Unlike a pipe, the payload can seek in it and the parent does not have to feed it.
+fn sealed_memfd(bytes: &[u8]) -> Result<Fd, Errno> {
+    let fd = cvt(unsafe { syscalls::memfd_create(b"stdin\0", MFD_CLOEXEC | MFD_ALLOW_SEALING) })?;
+    let fd = Fd::from_raw(fd);
+    let mut written = 0;
+    while written < bytes.len() {
+        match fd.write(&bytes[written..])? {
+            0 => return Err(Errno::EIO),
+            n => written += n,
+        }
+    }
+    let seals = F_SEAL_SEAL | F_SEAL_SHRINK | F_SEAL_GROW | F_SEAL_WRITE;
+    cvt(unsafe { syscalls::fcntl(fd.raw(), F_ADD_SEALS, seals) })?;
+    // The child inherits the file offset along with the descriptor
+    cvt(unsafe { syscalls::lseek(fd.raw(), 0, SEEK_SET) })?;
+    Ok(fd)
+}
+
+fn open_dev_null() -> Result<Fd, Errno> {
+    let fd = cvt(unsafe { syscalls::openat(AT_FDCWD, b"/dev/null\0", O_RDWR | O_CLOEXEC, 0) })?;
+    Ok(Fd::from_raw(fd))
+}
+
+/// The parent end of the payload's stdin pipe, see [`Stdio::Piped`].
+///
+/// Dropping it closes the pipe, which the payload observes as end of file.
+#[derive(Debug)]
+pub struct ChildStdin {
+    pub(crate) fd: Fd,
+}
+
+/// The parent end of the payload's stdout pipe, see [`Stdio::Piped`].
+#[derive(Debug)]
+pub struct ChildStdout {
+    pub(crate) fd: Fd,
+}
+
+/// The parent end of the payload's stderr pipe, see [`Stdio::Piped`].
+#[derive(Debug)]
+pub struct ChildStderr {
+    pub(crate) fd: Fd,
+}
+
+impl ChildStdin {
+    /// Writes bytes to the payload's stdin, returning how many were written.
+    pub fn write(&mut self, buf: &[u8]) -> Result<usize, Errno> {
+        self.fd.write(buf)
+    }
+
+    /// Writes all of `buf` to the payload's stdin.
+    pub fn write_all(&mut self, mut buf: &[u8]) -> Result<(), Errno> {
+        while !buf.is_empty() {
+            let written = self.fd.write(buf)?;
+            buf = &buf[written..];
+        }
+        Ok(())
+    }
+}
+
+impl ChildStdout {
+    /// Reads bytes printed by the payload on stdout. Returns 0 once the payload closed it.
+    pub fn read(&mut self, buf: &mut [u8]) -> Result<usize, Errno> {
+        self.fd.read(buf)
+    }
+}
+
+impl ChildStderr {
+    /// Reads bytes printed by the payload on stderr. Returns 0 once the payload closed it.
+    pub fn read(&mut self, buf: &mut [u8]) -> Result<usize, Errno> {
+        self.fd.read(buf)
+    }
+}
+
+macro_rules! impl_pipe_end {
+    ($($pipe:ident),*) => {$(
+        impl $pipe {
+            /// Returns the raw file descriptor of this pipe end.
+            pub fn as_raw_fd(&self) -> i32 {
+                self.fd.raw()
+            }
+
+            /// Releases ownership of the raw file descriptor, which the caller must close.
+            pub fn into_raw_fd(self) -> i32 {
+                self.fd.into_raw()
+            }
+        }
+
+        #[cfg(feature = "std")]
+        impl std::os::fd::AsRawFd for $pipe {
+            fn as_raw_fd(&self) -> std::os::fd::RawFd {
+                self.fd.raw()
+            }
+        }
+
+        #[cfg(feature = "std")]
+        impl std::os::fd::AsFd for $pipe {
+            fn as_fd(&self) -> std::os::fd::BorrowedFd<'_> {
+                // Safety: the descriptor stays open as long as `self` is alive
+                unsafe { std::os::fd::BorrowedFd::borrow_raw(self.fd.raw()) }
+            }
+        }
+
+        #[cfg(feature = "std")]
+        impl From<$pipe> for std::os::fd::OwnedFd {
+            fn from(pipe: $pipe) -> Self {
+                use std::os::fd::FromRawFd;
+                // Safety: ownership of the descriptor is transferred
+                unsafe { std::os::fd::OwnedFd::from_raw_fd(pipe.fd.into_raw()) }
+            }
+        }
+    )*};
+}
+
+impl_pipe_end!(ChildStdin, ChildStdout, ChildStderr);
+
+#[cfg(feature = "std")]
+impl std::io::Write for ChildStdin {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.fd
+            .write(buf)
+            .map_err(|errno| std::io::Error::from_raw_os_error(errno.raw()))
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::io::Read for ChildStdout {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.fd
+            .read(buf)
+            .map_err(|errno| std::io::Error::from_raw_os_error(errno.raw()))
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::io::Read for ChildStderr {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.fd
+            .read(buf)
+            .map_err(|errno| std::io::Error::from_raw_os_error(errno.raw()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_prepare_parent_ends() {
+        let setup = StdioSetup::prepare(
+            &RunOptions::new()
+                .with_stdin(Stdio::Piped)
+                .with_stdout(Stdio::Null)
+                .with_stderr(Stdio::Piped),
+        )
+        .unwrap();
+        assert!(matches!(setup.child_ends[0], ChildEnd::Owned(_)));
+        assert!(matches!(setup.child_ends[1], ChildEnd::Owned(_)));
+        let (stdin, stdout, stderr) = setup.into_parent_ends();
+        assert!(stdin.is_some() && stdout.is_none() && stderr.is_some());
+    }
+
+    #[test]
+    fn test_sealed_memfd() {
+        let fd = sealed_memfd(b"input").unwrap();
+        let mut buf = [0u8; 8];
+        assert_eq!(fd.read(&mut buf).unwrap(), 5);
+        assert_eq!(&buf[..5], b"input");
+        assert_eq!(fd.write(b"more"), Err(Errno::EPERM));
+    }
+
+    #[test]
+    fn test_pipe_ends_connected() {
+        let setup = StdioSetup::prepare(
+            &RunOptions::new()
+                .with_stdout(Stdio::Piped)
+                .with_stderr(Stdio::Fd(1)),
+        )
+        .unwrap();
+        assert!(matches!(setup.child_ends[0], ChildEnd::Inherit));
+        assert!(matches!(setup.child_ends[2], ChildEnd::Borrowed(1)));
+        let ChildEnd::Owned(write_end) = &setup.child_ends[1] else {
+            panic!("stdout pipe not created");
+        };
+        assert_eq!(write_end.write(b"hi").unwrap(), 2);
+        let (_, stdout, _) = setup.into_parent_ends();
+        let mut buf = [0u8; 4];
+        let mut stdout = stdout.unwrap();
+        assert_eq!(stdout.read(&mut buf).unwrap(), 2);
+        assert_eq!(&buf[..2], b"hi");
+        // the write end was closed with the setup
+        assert_eq!(stdout.read(&mut buf).unwrap(), 0);
+    }
+}
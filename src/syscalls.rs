@@ -1,10 +1,92 @@
+use crate::Errno;
 use syscaller::wrap_syscall;
 
 wrap_syscall! {
+    0 : ssize_t read(int fd, void *buf, size_t count),
     1 : ssize_t write(int fd, void *buf, size_t count),
     3 : int close(int fd),
-    57 : int fork(),
+    7 : int poll(void *fds, unsigned int nfds, int timeout),
+    8 : ssize_t lseek(int fd, ssize_t offset, int whence),
+    13 : int rt_sigaction(int sig, void *act, void *oact, size_t sigsetsize),
+    14 : int rt_sigprocmask(int how, void *set, void *oset, size_t sigsetsize),
+    16 : int ioctl(int fd, unsigned long request, void *arg),
+    33 : int dup2(int oldfd, int newfd),
+    46 : ssize_t sendmsg(int fd, void *msg, int flags),
+    47 : ssize_t recvmsg(int fd, void *msg, int flags),
+    53 : int socketpair(int domain, int kind, int protocol, int *fds),
+    56 : int clone(unsigned long flags, void *stack, void *parent_tid, void *child_tid, unsigned long tls),
     59 : int execve(const char *path, char *const *argv, char *const *envp),
     61 : int wait4(int pid, int *status, int options, void *rusage),
+    62 : int kill(int pid, int sig),
+    72 : int fcntl(int fd, int cmd, int arg),
+    81 : int fchdir(int fd),
+    95 : int umask(unsigned int mask),
+    107 : int geteuid(),
+    108 : int getegid(),
+    115 : int getgroups(int size, void *list),
+    116 : int setgroups(int size, void *list),
+    117 : int setresuid(unsigned int ruid, unsigned int euid, unsigned int suid),
+    118 : int getresuid(void *ruid, void *euid, void *suid),
+    119 : int setresgid(unsigned int rgid, unsigned int egid, unsigned int sgid),
+    120 : int getresgid(void *rgid, void *egid, void *sgid),
+    125 : int capget(void *header, void *data),
+    126 : int capset(void *header, void *data),
+    157 : int prctl(int option, unsigned long arg2, unsigned long arg3, unsigned long arg4, unsigned long arg5),
+    165 : int mount(const char *source, const char *target, void *fstype, unsigned long flags, void *data),
+    217 : ssize_t getdents64(int fd, void *dirp, size_t count),
+    228 : int clock_gettime(int clockid, void *tp),
+    231 : int exit_group(int status),
+    257 : int openat(int dirfd, const char *path, int flags, unsigned int mode),
+    258 : int mkdirat(int dirfd, const char *path, unsigned int mode),
+    263 : int unlinkat(int dirfd, const char *path, int flags),
+    272 : int unshare(unsigned long flags),
+    293 : int pipe2(int *fds, int flags),
+    302 : int prlimit64(int pid, unsigned int resource, void *new_limit, void *old_limit),
+    310 : ssize_t process_vm_readv(int pid, void *local_iov, unsigned long liovcnt, void *remote_iov, unsigned long riovcnt, unsigned long flags),
+    317 : int seccomp(unsigned int op, unsigned int flags, void *args),
     319 : int memfd_create(const char *name, unsigned int flags),
+    322 : int execveat(int dirfd, const char *path, char *const *argv, char *const *envp, int flags),
+    424 : int pidfd_send_signal(int pidfd, int sig, void *info, unsigned int flags),
+    434 : int pidfd_open(int pid, unsigned int flags),
+    435 : int clone3(void *args, size_t size),
+    436 : int close_range(unsigned int fd, unsigned int max_fd, unsigned int flags),
+    444 : int landlock_create_ruleset(void *attr, size_t size, unsigned int flags),
+    445 : int landlock_add_rule(int ruleset_fd, unsigned int rule_type, void *rule_attr, unsigned int flags),
+    446 : int landlock_restrict_self(int ruleset_fd, unsigned int flags),
+}
+
+/// Return value of a raw system call, which is `-errno` on failure.
+pub(crate) trait SyscallReturn: Copy {
+    fn is_error(self) -> bool;
+    fn errno(self) -> Errno;
+}
+
+impl SyscallReturn for i32 {
+    fn is_error(self) -> bool {
+        self < 0
+    }
+
+    fn errno(self) -> Errno {
+        Errno::from_ret(self as isize)
+    }
+}
+
+impl SyscallReturn for isize {
+    fn is_error(self) -> bool {
+        self < 0
+    }
+
+    fn errno(self) -> Errno {
+        Errno::from_ret(self)
+    }
+}
+
+/// Converts the raw return value of a system call into a `Result`, surfacing the errno the
+/// kernel gave back instead of a bare `-1`.
+pub(crate) fn cvt<T: SyscallReturn>(ret: T) -> Result<T, Errno> {
+    if ret.is_error() {
+        Err(ret.errno())
+    } else {
+        Ok(ret)
+    }
 }
@@ -0,0 +1,52 @@
+use core::time::Duration;
+
+use crate::syscalls;
+
+const CLOCK_MONOTONIC: i32 = 1;
+
+/// `struct timespec`
+#[repr(C)]
+#[derive(Default)]
+struct Timespec {
+    tv_sec: i64,
+    tv_nsec: i64,
+}
+
+/// Reads the monotonic clock, which is not affected by changes of the system time.
+///
+/// `clock_gettime` cannot fail for `CLOCK_MONOTONIC` with a valid buffer.
+pub(crate) fn monotonic_now() -> Duration {
+    let mut ts = Timespec::default();
+    unsafe { syscalls::clock_gettime(CLOCK_MONOTONIC, (&mut ts as *mut Timespec).cast()) };
+    Duration::new(ts.tv_sec as u64, ts.tv_nsec as u32)
+}
+
+/// Converts `duration` into a `poll()` timeout in milliseconds, rounded up so that the
+/// deadline has passed when poll returns.
+pub(crate) fn poll_timeout(duration: Duration) -> i32 {
+    duration
+        .as_nanos()
+        .div_ceil(1_000_000)
+        .min(i32::MAX as u128) as i32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_monotonic_now() {
+        let first = monotonic_now();
+        let second = monotonic_now();
+        assert!(first > Duration::ZERO);
+        assert!(second >= first);
+    }
+
+    #[test]
+    fn test_poll_timeout() {
+        assert_eq!(poll_timeout(Duration::ZERO), 0);
+        assert_eq!(poll_timeout(Duration::from_nanos(1)), 1);
+        assert_eq!(poll_timeout(Duration::from_millis(1500)), 1500);
+        assert_eq!(poll_timeout(Duration::MAX), i32::MAX);
+    }
+}
@@ -0,0 +1,223 @@
+//! Resolution of user and group names from `/etc/passwd` and `/etc/group`, without `libc` or
+//! an allocator, to feed [`RunOptions::with_user`](crate::RunOptions::with_user) and
+//! [`RunOptions::with_groups`](crate::RunOptions::with_groups).
+//!
+//! Only the local files are read: accounts provided by NSS modules (LDAP, systemd-userdb...) are
+//! not visible.
+//!
+//! ```rust,no_run
+//! use memfd_runner::{users, RunOptions};
+//!
+//! let (mut passwd_buf, mut group_buf) = ([0u8; 16384], [0u8; 16384]);
+//! let passwd = users::read_file(c"/etc/passwd", &mut passwd_buf).unwrap();
+//! let group = users::read_file(c"/etc/group", &mut group_buf).unwrap();
+//! let user = users::find_user(passwd, "nobody").unwrap();
+//! let mut gids = [0u32; 64];
+//! let groups = users::supplementary_groups(group, &user, &mut gids).unwrap();
+//! let options = RunOptions::new().with_user(&user).with_groups(groups);
+//! ```
+
+use core::ffi::CStr;
+
+use crate::Errno;
+use crate::fd::{AT_FDCWD, Fd, O_CLOEXEC};
+use crate::syscalls::{self, cvt};
+
+const O_RDONLY: i32 = 0;
+
+/// An entry of `/etc/passwd`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct User<'a> {
+    /// Login name
+    pub name: &'a str,
+    /// User id
+    pub uid: u32,
+    /// Primary group id
+    pub gid: u32,
+    /// Home directory
+    pub home: &'a str,
+    /// Login shell
+    pub shell: &'a str,
+}
+
+/// An entry of `/etc/group`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Group<'a> {
+    /// Group name
+    pub name: &'a str,
+    /// Group id
+    pub gid: u32,
+    members: &'a str,
+}
+
+impl<'a> Group<'a> {
+    /// Names of the users listed as members of the group.
+    ///
+    /// Users whose primary group this is are usually not listed.
+    pub fn members(&self) -> impl Iterator<Item = &'a str> + use<'a> {
+        self.members.split(',').filter(|member| !member.is_empty())
+    }
+}
+
+/// Iterate over the entries of a `/etc/passwd` file, skipping malformed lines.
+pub fn users(passwd: &str) -> impl Iterator<Item = User<'_>> {
+    passwd.lines().filter_map(|line| {
+        // name:password:uid:gid:gecos:home:shell
+        let mut fields = line.split(':');
+        let name = fields.next().filter(|name| !name.is_empty())?;
+        let uid = fields.nth(1)?.parse().ok()?;
+        let gid = fields.next()?.parse().ok()?;
+        let home = fields.nth(1)?;
+        let shell = fields.next()?;
+        Some(User {
+            name,
+            uid,
+            gid,
+            home,
+            shell,
+        })
+    })
+}
+
+/// Iterate over the entries of a `/etc/group` file, skipping malformed lines.
+pub fn groups(group: &str) -> impl Iterator<Item = Group<'_>> {
+    group.lines().filter_map(|line| {
+        // name:password:gid:members
+        let mut fields = line.split(':');
+        let name = fields.next().filter(|name| !name.is_empty())?;
+        let gid = fields.nth(1)?.parse().ok()?;
+        let members = fields.next()?;
+        Some(Group { name, gid, members })
+    })
+}
+
+/// Find the user called `name` in a `/etc/passwd` file.
+pub fn find_user<'a>(passwd: &'a str, name: &str) -> Option<User<'a>> {
+    users(passwd).find(|user| user.name == name)
+}
+
+/// Find the group called `name` in a `/etc/group` file.
+pub fn find_group<'a>(group: &'a str, name: &str) -> Option<Group<'a>> {
+    groups(group).find(|group| group.name == name)
+}
+
+/// Collect the groups of `user` into `buf`, like `getgrouplist`: its primary group first, then
+/// every group of a `/etc/group` file listing it as a member.
+///
+/// Returns `ERANGE` if `buf` is too small.
+pub fn supplementary_groups<'b>(
+    group: &str,
+    user: &User<'_>,
+    buf: &'b mut [u32],
+) -> Result<&'b [u32], Errno> {
+    let mut len = 0;
+    let member_of = groups(group)
+        .filter(|group| group.members().any(|member| member == user.name))
+        .map(|group| group.gid);
+    for gid in core::iter::once(user.gid).chain(member_of) {
+        if buf[..len].contains(&gid) {
+            continue;
+        }
+        *buf.get_mut(len).ok_or(Errno::ERANGE)? = gid;
+        len += 1;
+    }
+    Ok(&buf[..len])
+}
+
+/// Read a whole text file such as `/etc/passwd` into `buf`.
+///
+/// Returns `EFBIG` if the file does not fit and `EILSEQ` if it is not valid UTF-8.
+pub fn read_file<'b>(path: &CStr, buf: &'b mut [u8]) -> Result<&'b str, Errno> {
+    let fd = cvt(unsafe {
+        syscalls::openat(AT_FDCWD, path.to_bytes_with_nul(), O_RDONLY | O_CLOEXEC, 0)
+    })?;
+    let fd = Fd::from_raw(fd);
+    let mut len = 0;
+    loop {
+        if len == buf.len() {
+            // only an empty read proves that the file was read entirely
+            return match fd.read(&mut [0u8; 1])? {
+                0 => core::str::from_utf8(buf).map_err(|_| Errno::EILSEQ),
+                _ => Err(Errno::EFBIG),
+            };
+        }
+        match fd.read(&mut buf[len..])? {
+            0 => break,
+            n => len += n,
+        }
+    }
+    core::str::from_utf8(&buf[..len]).map_err(|_| Errno::EILSEQ)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PASSWD: &str = "\
+root:x:0:0:root:/root:/bin/bash
+# not an entry
+nobody:x:65534:65534:nobody:/nonexistent:/usr/sbin/nologin
+broken:x:abc:0::/:/bin/sh
+alice:x:1000:1000:Alice,,,:/home/alice:/bin/zsh
+";
+
+    const GROUP: &str = "\
+root:x:0:
+wheel:x:10:alice,bob
+alice:x:1000:
+docker:x:998:bob,alice
+";
+
+    #[test]
+    fn test_find_user() {
+        let alice = find_user(PASSWD, "alice").unwrap();
+        assert_eq!(
+            alice,
+            User {
+                name: "alice",
+                uid: 1000,
+                gid: 1000,
+                home: "/home/alice",
+                shell: "/bin/zsh",
+            }
+        );
+        assert_eq!(find_user(PASSWD, "nobody").unwrap().uid, 65534);
+        assert!(find_user(PASSWD, "broken").is_none());
+        assert!(find_user(PASSWD, "mallory").is_none());
+        assert_eq!(users(PASSWD).count(), 3);
+    }
+
+    #[test]
+    fn test_find_group() {
+        let wheel = find_group(GROUP, "wheel").unwrap();
+        assert_eq!(wheel.gid, 10);
+        assert!(wheel.members().eq(["alice", "bob"]));
+        assert_eq!(find_group(GROUP, "root").unwrap().members().count(), 0);
+        assert!(find_group(GROUP, "audio").is_none());
+    }
+
+    #[test]
+    fn test_supplementary_groups() {
+        let alice = find_user(PASSWD, "alice").unwrap();
+        let mut buf = [0u32; 4];
+        assert_eq!(
+            supplementary_groups(GROUP, &alice, &mut buf).unwrap(),
+            [1000, 10, 998]
+        );
+        let mut small = [0u32; 2];
+        assert_eq!(
+            supplementary_groups(GROUP, &alice, &mut small),
+            Err(Errno::ERANGE)
+        );
+    }
+
+    #[test]
+    fn test_read_file() {
+        let mut buf = [0u8; 65536];
+        let passwd = read_file(c"/etc/passwd", &mut buf).unwrap();
+        assert_eq!(find_user(passwd, "root").unwrap().uid, 0);
+        let mut small = [0u8; 4];
+        assert_eq!(read_file(c"/etc/passwd", &mut small), Err(Errno::EFBIG));
+        assert_eq!(read_file(c"/nonexistent", &mut small), Err(Errno::ENOENT));
+    }
+}
@@ -0,0 +1,165 @@
+//! `clone(CLONE_VM | CLONE_VFORK)` spawn path, see [`SpawnMethod::Vfork`](crate::SpawnMethod::Vfork).
+//!
+//! The child shares the memory of the parent and runs on a dedicated stack until it calls
+//! `execve` or exits, while the calling thread is suspended. No page tables are copied, so the
+//! cost of spawning does not grow with the size of the parent.
+
+use crate::Errno;
+use crate::pidfd::{self, PidFd};
+use crate::syscalls::{self, cvt};
+
+const CLONE_VM: u64 = 0x100;
+const CLONE_PIDFD: u64 = 0x1000;
+const CLONE_VFORK: u64 = 0x4000;
+const SIGCHLD: u64 = 17;
+const SYS_CLONE: isize = 56;
+
+const SIG_SETMASK: i32 = 2;
+const SIG_DFL: usize = 0;
+const SIG_IGN: usize = 1;
+const NSIG: i32 = 64;
+
+/// Stack of the child, which only runs the pre-validated steps before `execve`
+const CHILD_STACK_LEN: usize = 32 * 1024;
+
+#[repr(C, align(16))]
+struct ChildStack([u8; CHILD_STACK_LEN]);
+
+/// `struct sigaction` as expected by the `rt_sigaction` system call
+#[repr(C)]
+#[derive(Default)]
+struct KernelSigaction {
+    handler: usize,
+    flags: u64,
+    restorer: usize,
+    mask: u64,
+}
+
+/// Everything the child entry point needs, living in the suspended parent's frame.
+struct Context<'a, F> {
+    child: &'a F,
+    parent_mask: u64,
+}
+
+/// Run `child` in a new process sharing our memory, then return once it called `execve` or
+/// exited. Returns the pid and pidfd of the child.
+///
+/// All signals are blocked around the `clone`, and the child resets caught signals to their
+/// default action before restoring the signal mask, so no handler of the parent can run on the
+/// shared memory. `child` must only issue system calls, and end with `execve` or `exit_group`.
+pub(crate) fn spawn<F: Fn()>(child: &F) -> Result<(i32, Option<PidFd>), Errno> {
+    let mut stack = ChildStack([0; CHILD_STACK_LEN]);
+    let all_signals = u64::MAX;
+    let mut parent_mask = 0u64;
+    cvt(unsafe {
+        syscalls::rt_sigprocmask(
+            SIG_SETMASK,
+            (&all_signals as *const u64).cast_mut().cast(),
+            (&mut parent_mask as *mut u64).cast(),
+            size_of::<u64>(),
+        )
+    })?;
+
+    let context = Context { child, parent_mask };
+    let mut pidfd: i32 = -1;
+    let flags = CLONE_VM | CLONE_VFORK | CLONE_PIDFD | SIGCHLD;
+    // The stack grows down, from a 16 bytes aligned top
+    let stack_top = unsafe { stack.0.as_mut_ptr().add(CHILD_STACK_LEN) };
+    let entry: extern "C" fn(*const Context<'_, F>) -> ! = child_entry::<F>;
+    let ret: isize;
+    unsafe {
+        core::arch::asm!(
+            "syscall",
+            "test rax, rax",
+            "jnz 2f",
+            // In the child, on the new stack: jump to the entry point, which never returns
+            "mov rdi, {context}",
+            "call {entry}",
+            "ud2",
+            "2:",
+            entry = in(reg) entry,
+            context = in(reg) &context as *const Context<'_, F>,
+            inlateout("rax") SYS_CLONE => ret,
+            in("rdi") flags,
+            in("rsi") stack_top,
+            in("rdx") &mut pidfd as *mut i32,
+            in("r10") 0usize,
+            in("r8") 0usize,
+            // clobbered by the syscall instruction, so they cannot hold the inputs above
+            out("rcx") _,
+            out("r11") _,
+            options(nostack),
+        );
+    }
+
+    unsafe {
+        syscalls::rt_sigprocmask(
+            SIG_SETMASK,
+            (&parent_mask as *const u64).cast_mut().cast(),
+            core::ptr::null_mut(),
+            size_of::<u64>(),
+        )
+    };
+    let pid = cvt(ret)? as i32;
+    // Kernels older than 5.2 ignore CLONE_PIDFD
+    let pidfd = match pidfd {
+        -1 => pidfd::open(pid).ok(),
+        fd => Some(PidFd::from_raw(fd)),
+    };
+    Ok((pid, pidfd))
+}
+
+extern "C" fn child_entry<F: Fn()>(context: *const Context<'_, F>) -> ! {
+    let context = unsafe { &*context };
+    let default = KernelSigaction::default();
+    for signal in 1..=NSIG {
+        let mut action = KernelSigaction::default();
+        let ret = unsafe {
+            syscalls::rt_sigaction(
+                signal,
+                core::ptr::null_mut(),
+                (&mut action as *mut KernelSigaction).cast(),
+                size_of::<u64>(),
+            )
+        };
+        if ret == 0 && action.handler != SIG_DFL && action.handler != SIG_IGN {
+            unsafe {
+                syscalls::rt_sigaction(
+                    signal,
+                    (&default as *const KernelSigaction).cast_mut().cast(),
+                    core::ptr::null_mut(),
+                    size_of::<u64>(),
+                )
+            };
+        }
+    }
+    unsafe {
+        syscalls::rt_sigprocmask(
+            SIG_SETMASK,
+            (&context.parent_mask as *const u64).cast_mut().cast(),
+            core::ptr::null_mut(),
+            size_of::<u64>(),
+        )
+    };
+    (context.child)();
+    // `child` must not return, as the stack it would return to belongs to the parent
+    unsafe { syscalls::exit_group(127) };
+    unreachable!("exit_group should not return");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_vfork_spawn() {
+        let code = 3;
+        let (pid, pidfd) = spawn(&|| {
+            unsafe { syscalls::exit_group(code) };
+        })
+        .unwrap();
+        assert!(pidfd.is_some());
+        let (status, _) = crate::child::wait_pid(pid, 0).unwrap().unwrap();
+        assert_eq!(status, code << 8);
+    }
+}